@@ -0,0 +1,324 @@
+//! Most of the assets of GameCube games are bundled into RARC or U8 archives,
+//! which are often Yaz0 compressed on top. This allows replacing files inside
+//! of them, including archives nested inside other archives.
+
+mod rarc;
+mod u8_archive;
+
+use failure::{Error, ResultExt};
+use yaz0;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ArchiveKind {
+    Rarc {
+        /// Whether the IDs of the files match their index in the file entries.
+        sync_ids: bool,
+    },
+    U8,
+}
+
+#[derive(PartialEq, Debug)]
+pub enum ArchiveNode {
+    Directory(ArchiveDirectory),
+    File(ArchiveFile),
+}
+
+#[derive(PartialEq, Debug)]
+pub struct ArchiveDirectory {
+    pub name: String,
+    /// The upper case short name that RARC archives store for every
+    /// directory.
+    pub node_type: [u8; 4],
+    pub children: Vec<ArchiveNode>,
+}
+
+#[derive(PartialEq, Debug)]
+pub struct ArchiveFile {
+    pub name: String,
+    /// The ID of the file in RARC archives.
+    pub id: Option<u16>,
+    /// The RARC flags that determine where the game loads the file to.
+    pub flags: u8,
+    pub data: Vec<u8>,
+}
+
+#[derive(PartialEq, Debug)]
+pub struct Archive {
+    pub kind: ArchiveKind,
+    pub root: ArchiveDirectory,
+}
+
+impl ArchiveDirectory {
+    fn new(name: &str) -> Self {
+        ArchiveDirectory {
+            name: name.to_owned(),
+            node_type: node_type(name),
+            children: Vec::new(),
+        }
+    }
+
+    fn directories(&self) -> impl Iterator<Item = &ArchiveDirectory> {
+        self.children.iter().filter_map(|c| match *c {
+            ArchiveNode::Directory(ref d) => Some(d),
+            _ => None,
+        })
+    }
+
+    fn files(&self) -> impl Iterator<Item = &ArchiveFile> {
+        self.children.iter().filter_map(|c| match *c {
+            ArchiveNode::File(ref f) => Some(f),
+            _ => None,
+        })
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        self.children.iter().position(|c| match *c {
+            ArchiveNode::Directory(ref d) => d.name == name,
+            ArchiveNode::File(ref f) => f.name == name,
+        })
+    }
+}
+
+/// The node type of a RARC directory is the first four characters of its name
+/// in upper case, padded with spaces.
+fn node_type(name: &str) -> [u8; 4] {
+    let mut node_type = *b"    ";
+    for (dst, src) in node_type.iter_mut().zip(name.bytes()) {
+        *dst = src.to_ascii_uppercase();
+    }
+    node_type
+}
+
+/// The hash of the names that RARC archives store.
+fn name_hash(name: &str) -> u16 {
+    name.bytes()
+        .fold(0u16, |hash, c| hash.wrapping_mul(3).wrapping_add(c as u16))
+}
+
+impl Archive {
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        if data.starts_with(rarc::MAGIC) {
+            Ok(rarc::parse(data).context("Couldn't parse the RARC archive")?)
+        } else if data.starts_with(u8_archive::MAGIC) {
+            Ok(u8_archive::parse(data).context("Couldn't parse the U8 archive")?)
+        } else {
+            bail!("The file is neither a RARC nor a U8 archive")
+        }
+    }
+
+    /// Parses the archive, decompressing it first if it's Yaz0 compressed.
+    pub fn open(data: &[u8]) -> Result<CompressedArchive, Error> {
+        if yaz0::is_compressed(data) {
+            let data = yaz0::decompress(data).context("Couldn't decompress the archive")?;
+            Ok(CompressedArchive {
+                archive: Archive::parse(&data)?,
+                yaz0: true,
+            })
+        } else {
+            Ok(CompressedArchive {
+                archive: Archive::parse(data)?,
+                yaz0: false,
+            })
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self.kind {
+            ArchiveKind::Rarc { sync_ids } => rarc::write(&self.root, sync_ids),
+            ArchiveKind::U8 => u8_archive::write(&self.root),
+        }
+    }
+
+    /// Replaces the file at the given path. The path may descend into nested
+    /// archives, which then get rebuilt and compressed again if they were
+    /// compressed before. Files that don't exist yet get created.
+    pub fn replace_file(
+        &mut self,
+        path: &str,
+        data: Vec<u8>,
+        yaz0: Option<bool>,
+    ) -> Result<(), Error> {
+        replace_in_directory(&mut self.root, path, data, yaz0)
+    }
+}
+
+fn replace_in_directory(
+    dir: &mut ArchiveDirectory,
+    path: &str,
+    mut data: Vec<u8>,
+    compress: Option<bool>,
+) -> Result<(), Error> {
+    let mut splits = path.splitn(2, '/');
+    let (name, sub_path) = (splits.next().unwrap_or(""), splits.next());
+
+    let index = match dir.position(name) {
+        Some(index) => index,
+        None => {
+            dir.children.push(match sub_path {
+                Some(_) => ArchiveNode::Directory(ArchiveDirectory::new(name)),
+                None => ArchiveNode::File(ArchiveFile {
+                    name: name.to_owned(),
+                    id: None,
+                    flags: rarc::DEFAULT_FILE_FLAGS,
+                    data: Vec::new(),
+                }),
+            });
+            dir.children.len() - 1
+        }
+    };
+
+    match (&mut dir.children[index], sub_path) {
+        (&mut ArchiveNode::Directory(ref mut dir), Some(sub_path)) => {
+            replace_in_directory(dir, sub_path, data, compress)
+        }
+        (&mut ArchiveNode::File(ref mut file), None) => {
+            let compress = compress.unwrap_or_else(|| yaz0::is_compressed(&file.data));
+            if compress && !yaz0::is_compressed(&data) {
+                data = yaz0::compress(&data);
+            }
+            file.data = data;
+            Ok(())
+        }
+        (&mut ArchiveNode::File(ref mut file), Some(sub_path)) => {
+            let nested = replace_in_archive(&file.data, sub_path, data, compress)
+                .with_context(|_| format!("Couldn't replace the file in \"{}\"", file.name))?;
+            file.data = nested;
+            Ok(())
+        }
+        (&mut ArchiveNode::Directory(_), None) => {
+            bail!("\"{}\" is a directory and can't be replaced", name)
+        }
+    }
+}
+
+/// Replaces a file inside of the archive, which may be Yaz0 compressed, and
+/// returns the rebuilt archive.
+pub fn replace_in_archive(
+    archive: &[u8],
+    path: &str,
+    data: Vec<u8>,
+    yaz0: Option<bool>,
+) -> Result<Vec<u8>, Error> {
+    let mut archive = Archive::open(archive)?;
+    archive.replace_file(path, data, yaz0)?;
+    Ok(archive.into_bytes())
+}
+
+/// An archive together with the compression it's stored with.
+pub struct CompressedArchive {
+    pub archive: Archive,
+    pub yaz0: bool,
+}
+
+impl CompressedArchive {
+    pub fn replace_file(
+        &mut self,
+        path: &str,
+        data: Vec<u8>,
+        yaz0: Option<bool>,
+    ) -> Result<(), Error> {
+        self.archive.replace_file(path, data, yaz0)
+    }
+
+    /// Rebuilds the archive and applies the original compression again.
+    pub fn into_bytes(self) -> Vec<u8> {
+        let data = self.archive.to_bytes();
+        if self.yaz0 {
+            yaz0::compress(&data)
+        } else {
+            data
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(name: &str, flags: u8, data: &[u8]) -> ArchiveNode {
+        ArchiveNode::File(ArchiveFile {
+            name: name.to_owned(),
+            id: None,
+            flags,
+            data: data.to_vec(),
+        })
+    }
+
+    fn directory(name: &str, children: Vec<ArchiveNode>) -> ArchiveNode {
+        ArchiveNode::Directory(ArchiveDirectory {
+            children,
+            ..ArchiveDirectory::new(name)
+        })
+    }
+
+    fn tree(flags: u8) -> ArchiveDirectory {
+        ArchiveDirectory {
+            children: vec![
+                file("a.bin", flags, b"first file"),
+                directory(
+                    "sub",
+                    vec![
+                        file("b.bin", flags, &[0xAB; 0x45]),
+                        directory("empty", Vec::new()),
+                    ],
+                ),
+                file("c.bin", flags, b""),
+            ],
+            ..ArchiveDirectory::new("root")
+        }
+    }
+
+    fn clear_ids(dir: &mut ArchiveDirectory) {
+        for child in &mut dir.children {
+            match *child {
+                ArchiveNode::Directory(ref mut dir) => clear_ids(dir),
+                ArchiveNode::File(ref mut file) => file.id = None,
+            }
+        }
+    }
+
+    /// Parses the archive, writes it again and parses the written archive.
+    /// Both the bytes and the parsed archives need to stay the same.
+    fn round_trip(data: &[u8]) -> Archive {
+        let archive = Archive::parse(data).unwrap();
+        let written = archive.to_bytes();
+        assert!(written == data);
+        assert_eq!(Archive::parse(&written).unwrap(), archive);
+        archive
+    }
+
+    #[test]
+    fn rarc() {
+        for &sync_ids in &[false, true] {
+            let mut archive = round_trip(&rarc::write(&tree(rarc::DEFAULT_FILE_FLAGS), sync_ids));
+            assert_eq!(archive.kind, ArchiveKind::Rarc { sync_ids });
+            clear_ids(&mut archive.root);
+            assert_eq!(archive.root, tree(rarc::DEFAULT_FILE_FLAGS));
+        }
+    }
+
+    #[test]
+    fn u8() {
+        let archive = round_trip(&u8_archive::write(&tree(0)));
+        assert_eq!(archive.kind, ArchiveKind::U8);
+        assert_eq!(archive.root, tree(0));
+    }
+
+    #[test]
+    fn replace_nested() {
+        let mut outer = tree(0);
+        let inner = yaz0::compress(&rarc::write(&tree(rarc::DEFAULT_FILE_FLAGS), false));
+        outer.children.push(file("inner.szs", 0, &inner));
+        let data = u8_archive::write(&outer);
+
+        let data = replace_in_archive(&data, "inner.szs/sub/b.bin", b"new".to_vec(), None).unwrap();
+        let outer = Archive::parse(&data).unwrap();
+        let inner = outer.root.files().find(|f| f.name == "inner.szs").unwrap();
+        assert!(yaz0::is_compressed(&inner.data));
+
+        let inner = Archive::open(&inner.data).unwrap().archive;
+        let sub = inner.root.directories().find(|d| d.name == "sub").unwrap();
+        let file = sub.files().find(|f| f.name == "b.bin").unwrap();
+        assert_eq!(file.data, b"new");
+    }
+}
@@ -0,0 +1,410 @@
+//! Based on http://wiki.tockdom.com/wiki/RARC_(File_Format)
+
+use super::{name_hash, Archive, ArchiveDirectory, ArchiveFile, ArchiveKind, ArchiveNode};
+use byteorder::{ByteOrder, BE};
+use failure::{err_msg, Error};
+use std::collections::HashMap;
+use std::str;
+use yaz0;
+
+pub const MAGIC: &[u8; 4] = b"RARC";
+pub const DEFAULT_FILE_FLAGS: u8 = FLAG_FILE | FLAG_PRELOAD_TO_MRAM;
+
+const FLAG_FILE: u8 = 0x01;
+const FLAG_DIRECTORY: u8 = 0x02;
+const FLAG_COMPRESSED: u8 = 0x04;
+const FLAG_PRELOAD_TO_MRAM: u8 = 0x10;
+const FLAG_PRELOAD_TO_ARAM: u8 = 0x20;
+const FLAG_LOAD_FROM_DVD: u8 = 0x40;
+const FLAG_YAZ0: u8 = 0x80;
+
+const YAY0_MAGIC: &[u8; 4] = b"Yay0";
+
+const HEADER_LENGTH: usize = 0x20;
+const INFO_LENGTH: usize = 0x20;
+const NODE_LENGTH: usize = 0x10;
+const ENTRY_LENGTH: usize = 0x14;
+const MAX_DEPTH: usize = 64;
+
+struct Tables<'a> {
+    data: &'a [u8],
+    nodes: &'a [u8],
+    entries: &'a [u8],
+    strings: &'a [u8],
+    file_data: &'a [u8],
+}
+
+impl<'a> Tables<'a> {
+    fn string(&self, offset: usize) -> Result<&'a str, Error> {
+        let strings = self.strings;
+        let bytes = strings
+            .get(offset..)
+            .ok_or_else(|| err_msg("A name is outside of the string table"))?;
+        let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+        Ok(str::from_utf8(&bytes[..len])?)
+    }
+
+    fn directory(&self, node_index: usize, depth: usize) -> Result<ArchiveDirectory, Error> {
+        ensure!(depth < MAX_DEPTH, "The directories are nested too deeply");
+
+        let node = self
+            .nodes
+            .get(node_index * NODE_LENGTH..)
+            .and_then(|n| n.get(..NODE_LENGTH))
+            .ok_or_else(|| err_msg("A directory node is outside of the node table"))?;
+
+        let mut node_type = [0; 4];
+        node_type.copy_from_slice(&node[..4]);
+        let name = self.string(BE::read_u32(&node[0x4..]) as usize)?;
+        let entry_count = BE::read_u16(&node[0xA..]) as usize;
+        let first_entry = BE::read_u32(&node[0xC..]) as usize;
+
+        let mut children = Vec::with_capacity(entry_count);
+
+        for index in first_entry..first_entry + entry_count {
+            let entry = self
+                .entries
+                .get(index * ENTRY_LENGTH..)
+                .and_then(|e| e.get(..ENTRY_LENGTH))
+                .ok_or_else(|| err_msg("A file entry is outside of the entry table"))?;
+
+            let id = BE::read_u16(entry);
+            let type_and_name = BE::read_u32(&entry[0x4..]);
+            let flags = (type_and_name >> 24) as u8;
+            let name = self.string((type_and_name & 0xFF_FFFF) as usize)?;
+            let offset = BE::read_u32(&entry[0x8..]) as usize;
+            let size = BE::read_u32(&entry[0xC..]) as usize;
+
+            if flags & FLAG_DIRECTORY != 0 {
+                if name == "." || name == ".." {
+                    continue;
+                }
+                children.push(ArchiveNode::Directory(self.directory(offset, depth + 1)?));
+            } else {
+                let data = self
+                    .file_data
+                    .get(offset..)
+                    .and_then(|d| d.get(..size))
+                    .ok_or_else(|| format_err!("The data of \"{}\" is out of bounds", name))?;
+
+                children.push(ArchiveNode::File(ArchiveFile {
+                    name: name.to_owned(),
+                    id: Some(id),
+                    flags,
+                    data: data.to_vec(),
+                }));
+            }
+        }
+
+        Ok(ArchiveDirectory {
+            name: name.to_owned(),
+            node_type,
+            children,
+        })
+    }
+}
+
+fn table(data: &[u8], offset: usize) -> Result<&[u8], Error> {
+    data.get(HEADER_LENGTH + offset..)
+        .ok_or_else(|| err_msg("A table is outside of the archive"))
+}
+
+pub fn parse(data: &[u8]) -> Result<Archive, Error> {
+    ensure!(
+        data.len() >= HEADER_LENGTH + INFO_LENGTH,
+        "The archive is too small"
+    );
+    ensure!(&data[..4] == MAGIC, "The archive is not a RARC archive");
+
+    let info = &data[HEADER_LENGTH..];
+
+    let tables = Tables {
+        data,
+        nodes: table(data, BE::read_u32(&info[0x04..]) as usize)?,
+        entries: table(data, BE::read_u32(&info[0x0C..]) as usize)?,
+        strings: table(data, BE::read_u32(&info[0x14..]) as usize)?,
+        file_data: table(data, BE::read_u32(&data[0x0C..]) as usize)?,
+    };
+
+    let node_count = BE::read_u32(info) as usize;
+    ensure!(node_count > 0, "The archive has no root directory");
+    ensure!(
+        tables.nodes.len() >= node_count * NODE_LENGTH,
+        "The node table is outside of the archive"
+    );
+
+    Ok(Archive {
+        kind: ArchiveKind::Rarc {
+            sync_ids: tables.data[HEADER_LENGTH + 0x1A] != 0,
+        },
+        root: tables.directory(0, 0)?,
+    })
+}
+
+struct Entry {
+    id: u16,
+    name_hash: u16,
+    flags: u8,
+    name_offset: u32,
+    offset: u32,
+    size: u32,
+}
+
+#[derive(Default)]
+struct StringTable {
+    bytes: Vec<u8>,
+    offsets: HashMap<String, u32>,
+}
+
+impl StringTable {
+    fn offset(&mut self, name: &str) -> u32 {
+        if let Some(&offset) = self.offsets.get(name) {
+            return offset;
+        }
+        let offset = self.bytes.len() as u32;
+        self.bytes.extend_from_slice(name.as_bytes());
+        self.bytes.push(0);
+        self.offsets.insert(name.to_owned(), offset);
+        offset
+    }
+}
+
+fn align(value: usize) -> usize {
+    (value + 0x1F) & !0x1F
+}
+
+/// The game decides whether to decompress a file based on its flags, so the
+/// compression flags need to match the data, which may have been replaced.
+fn file_flags(file: &ArchiveFile) -> u8 {
+    let flags = file.flags & !(FLAG_COMPRESSED | FLAG_YAZ0);
+    if yaz0::is_compressed(&file.data) {
+        flags | FLAG_COMPRESSED | FLAG_YAZ0
+    } else if file.data.starts_with(YAY0_MAGIC) {
+        flags | FLAG_COMPRESSED
+    } else {
+        flags
+    }
+}
+
+/// The archive's data is grouped by where the game loads the files to, so the
+/// sizes of the groups can be stored in the header.
+fn load_group(flags: u8) -> usize {
+    if flags & FLAG_PRELOAD_TO_ARAM != 0 {
+        1
+    } else if flags & FLAG_LOAD_FROM_DVD != 0 {
+        2
+    } else {
+        0
+    }
+}
+
+pub fn write(root: &ArchiveDirectory, sync_ids: bool) -> Vec<u8> {
+    // The directories are numbered in breadth first order.
+    let mut nodes: Vec<(&ArchiveDirectory, Option<usize>)> = vec![(root, None)];
+    let mut child_nodes = Vec::new();
+    let mut index = 0;
+    while index < nodes.len() {
+        let dir = nodes[index].0;
+        let mut children = Vec::new();
+        for child in dir.directories() {
+            children.push(nodes.len());
+            nodes.push((child, Some(index)));
+        }
+        child_nodes.push(children);
+        index += 1;
+    }
+
+    let mut strings = StringTable::default();
+    strings.offset(".");
+    strings.offset("..");
+
+    let mut next_id = root.max_file_id().map_or(0, |id| id + 1);
+    let mut node_table = Vec::with_capacity(nodes.len() * NODE_LENGTH);
+    let mut entries = Vec::new();
+    let mut files = Vec::new();
+
+    for (node_index, &(dir, parent)) in nodes.iter().enumerate() {
+        let first_entry = entries.len();
+        let mut dir_children = child_nodes[node_index].iter();
+
+        for child in &dir.children {
+            match *child {
+                ArchiveNode::Directory(ref child) => entries.push(Entry {
+                    id: 0xFFFF,
+                    name_hash: name_hash(&child.name),
+                    flags: FLAG_DIRECTORY,
+                    name_offset: strings.offset(&child.name),
+                    offset: *dir_children.next().unwrap() as u32,
+                    size: NODE_LENGTH as u32,
+                }),
+                ArchiveNode::File(ref file) => {
+                    let id = if sync_ids {
+                        entries.len() as u16
+                    } else {
+                        file.id.unwrap_or_else(|| {
+                            next_id += 1;
+                            next_id - 1
+                        })
+                    };
+                    files.push((entries.len(), file));
+                    entries.push(Entry {
+                        id,
+                        name_hash: name_hash(&file.name),
+                        flags: file_flags(file),
+                        name_offset: strings.offset(&file.name),
+                        offset: 0,
+                        size: file.data.len() as u32,
+                    });
+                }
+            }
+        }
+
+        entries.push(Entry {
+            id: 0xFFFF,
+            name_hash: name_hash("."),
+            flags: FLAG_DIRECTORY,
+            name_offset: strings.offset("."),
+            offset: node_index as u32,
+            size: NODE_LENGTH as u32,
+        });
+        entries.push(Entry {
+            id: 0xFFFF,
+            name_hash: name_hash(".."),
+            flags: FLAG_DIRECTORY,
+            name_offset: strings.offset(".."),
+            offset: parent.map_or(0xFFFF_FFFF, |p| p as u32),
+            size: NODE_LENGTH as u32,
+        });
+
+        let mut node = [0; NODE_LENGTH];
+        node[..4].copy_from_slice(&dir.node_type);
+        BE::write_u32(&mut node[0x4..], strings.offset(&dir.name));
+        BE::write_u16(&mut node[0x8..], name_hash(&dir.name));
+        BE::write_u16(&mut node[0xA..], (entries.len() - first_entry) as u16);
+        BE::write_u32(&mut node[0xC..], first_entry as u32);
+        node_table.extend_from_slice(&node);
+    }
+
+    let mut file_data = Vec::new();
+    let mut group_sizes = [0; 3];
+    for (group, size) in group_sizes.iter_mut().enumerate() {
+        let start = file_data.len();
+        for &(entry_index, file) in files.iter().filter(|&&(_, f)| load_group(f.flags) == group) {
+            entries[entry_index].offset = file_data.len() as u32;
+            file_data.extend_from_slice(&file.data);
+            let aligned = align(file_data.len());
+            file_data.resize(aligned, 0);
+        }
+        *size = file_data.len() - start;
+    }
+
+    let nodes_offset = INFO_LENGTH;
+    let entries_offset = align(nodes_offset + node_table.len());
+    let strings_offset = align(entries_offset + entries.len() * ENTRY_LENGTH);
+    let strings_len = align(strings.bytes.len());
+    let data_offset = strings_offset + strings_len;
+    let total_len = HEADER_LENGTH + data_offset + file_data.len();
+
+    let mut buf = vec![0; HEADER_LENGTH + data_offset];
+
+    buf[..4].copy_from_slice(MAGIC);
+    BE::write_u32(&mut buf[0x04..], total_len as u32);
+    BE::write_u32(&mut buf[0x08..], HEADER_LENGTH as u32);
+    BE::write_u32(&mut buf[0x0C..], data_offset as u32);
+    BE::write_u32(&mut buf[0x10..], file_data.len() as u32);
+    BE::write_u32(&mut buf[0x14..], group_sizes[0] as u32);
+    BE::write_u32(&mut buf[0x18..], group_sizes[1] as u32);
+    BE::write_u32(&mut buf[0x1C..], group_sizes[2] as u32);
+
+    {
+        let info = &mut buf[HEADER_LENGTH..];
+        BE::write_u32(&mut info[0x00..], nodes.len() as u32);
+        BE::write_u32(&mut info[0x04..], nodes_offset as u32);
+        BE::write_u32(&mut info[0x08..], entries.len() as u32);
+        BE::write_u32(&mut info[0x0C..], entries_offset as u32);
+        BE::write_u32(&mut info[0x10..], strings_len as u32);
+        BE::write_u32(&mut info[0x14..], strings_offset as u32);
+        let next_free_id = if sync_ids { entries.len() } else { next_id as usize };
+        BE::write_u16(&mut info[0x18..], next_free_id as u16);
+        info[0x1A] = sync_ids as u8;
+
+        info[nodes_offset..][..node_table.len()].copy_from_slice(&node_table);
+
+        for (entry, dst) in entries
+            .iter()
+            .zip(info[entries_offset..].chunks_mut(ENTRY_LENGTH))
+        {
+            BE::write_u16(&mut dst[0x0..], entry.id);
+            BE::write_u16(&mut dst[0x2..], entry.name_hash);
+            BE::write_u32(
+                &mut dst[0x4..],
+                (entry.flags as u32) << 24 | entry.name_offset,
+            );
+            BE::write_u32(&mut dst[0x8..], entry.offset);
+            BE::write_u32(&mut dst[0xC..], entry.size);
+        }
+
+        info[strings_offset..][..strings.bytes.len()].copy_from_slice(&strings.bytes);
+    }
+
+    buf.extend_from_slice(&file_data);
+    buf
+}
+
+impl ArchiveDirectory {
+    fn max_file_id(&self) -> Option<u16> {
+        let files = self.files().filter_map(|f| f.id);
+        let nested = self.directories().filter_map(|d| d.max_file_id());
+        files.chain(nested).max()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compression_flags() {
+        let compressed = yaz0::compress(b"compressed contents of the file");
+        let flags = DEFAULT_FILE_FLAGS | FLAG_COMPRESSED | FLAG_YAZ0;
+        let root = ArchiveDirectory {
+            children: vec![
+                ArchiveNode::File(ArchiveFile {
+                    name: "compressed.bin".to_owned(),
+                    id: None,
+                    flags,
+                    data: compressed,
+                }),
+                ArchiveNode::File(ArchiveFile {
+                    name: "plain.bin".to_owned(),
+                    id: None,
+                    flags: DEFAULT_FILE_FLAGS,
+                    data: b"plain".to_vec(),
+                }),
+            ],
+            ..ArchiveDirectory::new("root")
+        };
+
+        let mut archive = parse(&write(&root, false)).unwrap();
+        archive
+            .replace_file("compressed.bin", b"now plain".to_vec(), Some(false))
+            .unwrap();
+        archive
+            .replace_file("plain.bin", b"now compressed".to_vec(), Some(true))
+            .unwrap();
+
+        let archive = parse(&archive.to_bytes()).unwrap();
+        let flags = archive
+            .root
+            .files()
+            .map(|f| (&f.name[..], f.flags))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            flags,
+            [
+                ("compressed.bin", DEFAULT_FILE_FLAGS),
+                ("plain.bin", DEFAULT_FILE_FLAGS | FLAG_COMPRESSED | FLAG_YAZ0),
+            ]
+        );
+    }
+}
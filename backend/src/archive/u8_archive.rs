@@ -0,0 +1,201 @@
+//! Based on http://wiki.tockdom.com/wiki/U8_(File_Format)
+
+use super::{node_type, Archive, ArchiveDirectory, ArchiveFile, ArchiveKind, ArchiveNode};
+use byteorder::{ByteOrder, BE};
+use failure::{err_msg, Error};
+use std::str;
+
+pub const MAGIC: &[u8; 4] = b"\x55\xAA\x38\x2D";
+
+const HEADER_LENGTH: usize = 0x20;
+const NODE_LENGTH: usize = 0xC;
+const MAX_DEPTH: usize = 64;
+
+struct Nodes<'a> {
+    data: &'a [u8],
+    nodes: &'a [u8],
+    strings: &'a [u8],
+}
+
+impl<'a> Nodes<'a> {
+    fn node(&self, index: usize) -> Result<(bool, &'a str, usize, usize), Error> {
+        let nodes = self.nodes;
+        let node = nodes
+            .get(index * NODE_LENGTH..)
+            .and_then(|n| n.get(..NODE_LENGTH))
+            .ok_or_else(|| err_msg("A node is outside of the node table"))?;
+
+        let is_directory = node[0] != 0;
+        let name_offset = BE::read_u32(node) as usize & 0xFF_FFFF;
+        let strings = self.strings;
+        let name = strings
+            .get(name_offset..)
+            .ok_or_else(|| err_msg("A name is outside of the string table"))?;
+        let len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+        let name = str::from_utf8(&name[..len])?;
+
+        Ok((
+            is_directory,
+            name,
+            BE::read_u32(&node[4..]) as usize,
+            BE::read_u32(&node[8..]) as usize,
+        ))
+    }
+
+    /// Parses the directory at the given index and returns it together with
+    /// the index of the node following it.
+    fn directory(&self, index: usize, depth: usize) -> Result<(ArchiveDirectory, usize), Error> {
+        ensure!(depth < MAX_DEPTH, "The directories are nested too deeply");
+
+        let (_, name, _, end) = self.node(index)?;
+        let mut children = Vec::new();
+        let mut index = index + 1;
+
+        while index < end {
+            let (is_directory, child_name, offset, size) = self.node(index)?;
+            if is_directory {
+                let (dir, next) = self.directory(index, depth + 1)?;
+                ensure!(next > index, "The directory structure is corrupted");
+                children.push(ArchiveNode::Directory(dir));
+                index = next;
+            } else {
+                let data = self
+                    .data
+                    .get(offset..)
+                    .and_then(|d| d.get(..size))
+                    .ok_or_else(|| format_err!("The data of \"{}\" is out of bounds", child_name))?;
+                children.push(ArchiveNode::File(ArchiveFile {
+                    name: child_name.to_owned(),
+                    id: None,
+                    flags: 0,
+                    data: data.to_vec(),
+                }));
+                index += 1;
+            }
+        }
+
+        Ok((
+            ArchiveDirectory {
+                name: name.to_owned(),
+                node_type: node_type(name),
+                children,
+            },
+            index,
+        ))
+    }
+}
+
+pub fn parse(data: &[u8]) -> Result<Archive, Error> {
+    ensure!(data.len() >= HEADER_LENGTH, "The archive is too small");
+    ensure!(&data[..4] == MAGIC, "The archive is not a U8 archive");
+
+    let root_offset = BE::read_u32(&data[4..]) as usize;
+    let root = data
+        .get(root_offset..)
+        .and_then(|r| r.get(..NODE_LENGTH))
+        .ok_or_else(|| err_msg("The root node is outside of the archive"))?;
+    let node_count = BE::read_u32(&root[8..]) as usize;
+
+    let nodes = &data[root_offset..];
+    ensure!(
+        nodes.len() >= node_count * NODE_LENGTH,
+        "The node table is outside of the archive"
+    );
+    let (nodes, strings) = nodes.split_at(node_count * NODE_LENGTH);
+
+    let (root, _) = Nodes {
+        data,
+        nodes,
+        strings,
+    }.directory(0, 0)?;
+
+    Ok(Archive {
+        kind: ArchiveKind::U8,
+        root,
+    })
+}
+
+fn align(value: usize) -> usize {
+    (value + 0x1F) & !0x1F
+}
+
+fn write_directory<'a>(
+    dir: &'a ArchiveDirectory,
+    parent: usize,
+    nodes: &mut Vec<[u8; NODE_LENGTH]>,
+    strings: &mut Vec<u8>,
+    files: &mut Vec<(usize, &'a ArchiveFile)>,
+) {
+    let index = nodes.len();
+    nodes.push(node(true, strings, &dir.name, parent, 0));
+
+    for child in &dir.children {
+        match *child {
+            ArchiveNode::Directory(ref child) => {
+                write_directory(child, index, nodes, strings, files)
+            }
+            ArchiveNode::File(ref file) => {
+                files.push((nodes.len(), file));
+                nodes.push(node(false, strings, &file.name, 0, file.data.len()));
+            }
+        }
+    }
+
+    let end = nodes.len();
+    BE::write_u32(&mut nodes[index][8..], end as u32);
+}
+
+fn node(
+    is_directory: bool,
+    strings: &mut Vec<u8>,
+    name: &str,
+    offset: usize,
+    size: usize,
+) -> [u8; NODE_LENGTH] {
+    let mut node = [0; NODE_LENGTH];
+    BE::write_u32(&mut node, strings.len() as u32);
+    node[0] = is_directory as u8;
+    BE::write_u32(&mut node[4..], offset as u32);
+    BE::write_u32(&mut node[8..], size as u32);
+    strings.extend_from_slice(name.as_bytes());
+    strings.push(0);
+    node
+}
+
+pub fn write(root: &ArchiveDirectory) -> Vec<u8> {
+    let mut nodes = Vec::new();
+    let mut strings = Vec::new();
+    let mut files = Vec::new();
+
+    write_directory(root, 0, &mut nodes, &mut strings, &mut files);
+
+    let header_size = nodes.len() * NODE_LENGTH + strings.len();
+    let data_offset = align(HEADER_LENGTH + header_size);
+
+    let mut file_data = Vec::new();
+    for (index, file) in files {
+        BE::write_u32(
+            &mut nodes[index][4..],
+            (data_offset + file_data.len()) as u32,
+        );
+        file_data.extend_from_slice(&file.data);
+        let aligned = align(file_data.len());
+        file_data.resize(aligned, 0);
+    }
+
+    let mut buf = Vec::with_capacity(data_offset + file_data.len());
+    buf.extend_from_slice(MAGIC);
+    buf.extend_from_slice(&[0; HEADER_LENGTH - 4]);
+    BE::write_u32(&mut buf[0x4..], HEADER_LENGTH as u32);
+    BE::write_u32(&mut buf[0x8..], header_size as u32);
+    BE::write_u32(&mut buf[0xC..], data_offset as u32);
+
+    for node in &nodes {
+        buf.extend_from_slice(node);
+    }
+    buf.extend_from_slice(&strings);
+    buf.resize(data_offset, 0);
+    buf.extend_from_slice(&file_data);
+
+    buf
+}
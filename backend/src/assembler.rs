@@ -1,10 +1,12 @@
+use byteorder::{ByteOrder, BE};
 use failure::{err_msg, Error, ResultExt};
+use framework_map::{self, GameSymbol, SymbolKind};
 use std::collections::{BTreeMap, HashMap};
 use syn::{self, synom::ParseError};
 
 pub struct Assembler<'a> {
     symbol_table: BTreeMap<&'a str, u32>,
-    prelinked_symbols: &'a HashMap<String, u32>,
+    prelinked_symbols: &'a HashMap<String, GameSymbol>,
     program_counter: u32,
 }
 
@@ -13,10 +15,33 @@ pub struct Instruction {
     pub data: u32,
 }
 
+impl Instruction {
+    /// Encodes the instructions as pairs of big endian addresses and data.
+    pub fn encode_all(instructions: &[Instruction]) -> Vec<u8> {
+        let mut buf = vec![0; 8 * instructions.len()];
+        for (instruction, dst) in instructions.iter().zip(buf.chunks_mut(8)) {
+            BE::write_u32(dst, instruction.address);
+            BE::write_u32(&mut dst[4..], instruction.data);
+        }
+        buf
+    }
+
+    pub fn decode_all(buf: &[u8]) -> Result<Vec<Instruction>, Error> {
+        ensure!(buf.len() % 8 == 0, "The instructions are truncated");
+        Ok(buf
+            .chunks(8)
+            .map(|chunk| Instruction {
+                address: BE::read_u32(chunk),
+                data: BE::read_u32(&chunk[4..]),
+            })
+            .collect())
+    }
+}
+
 impl<'a> Assembler<'a> {
     pub fn new(
         symbol_table: BTreeMap<&'a str, u32>,
-        prelinked_symbols: &'a HashMap<String, u32>,
+        prelinked_symbols: &'a HashMap<String, GameSymbol>,
     ) -> Assembler<'a> {
         Assembler {
             symbol_table,
@@ -53,28 +78,44 @@ impl<'a> Assembler<'a> {
 
         if line.starts_with("bl ") {
             let operand = &line[3..];
-            let destination = self.resolve_symbol(operand)?;
+            let destination = self.resolve_function(operand)?;
             data = build_branch_instruction(self.program_counter, destination, false, true);
         } else if line.starts_with("b ") {
             let operand = &line[2..];
-            let destination = self.resolve_symbol(operand)?;
+            let destination = self.resolve_function(operand)?;
             data = build_branch_instruction(self.program_counter, destination, false, false);
         } else if line.starts_with("u32 ") {
-            data = parse_u32_literal(&line[4..]).context("Couldn't parse the u32 literal")?;
+            data = self
+                .resolve_symbol(line[4..].trim())
+                .context("Couldn't parse the u32 literal")?;
         } else if line.starts_with("lis ") {
             let mut splits = line[4..].split(',').map(|s| s.trim());
-            let register = splits.next().ok_or_else(|| err_msg("Expected register"))?;
-            if !register.starts_with('r') {
-                bail!("Unexpected register: \"{}\"", register);
-            }
-            let register =
-                parse_i64_literal(&register[1..]).context("Couldn't parse the register index")?;
+            let register = parse_register(splits.next())?;
             let imm = splits
                 .next()
                 .ok_or_else(|| err_msg("Expected immediate for lis instruction"))?;
-            let imm =
-                parse_i64_literal(imm).context("Couldn't parse immediate for lis instruction")?;
-            data = build_lis_instruction(register as u8, imm as i16);
+            let imm = self
+                .parse_immediate(imm)
+                .context("Couldn't parse immediate for lis instruction")?;
+            data = build_lis_instruction(register, imm as i16);
+        } else if line.starts_with("addi ") || line.starts_with("ori ") {
+            let is_addi = line.starts_with("addi ");
+            let mut splits = line[if is_addi { 5 } else { 4 }..]
+                .split(',')
+                .map(|s| s.trim());
+            let reg_d = parse_register(splits.next())?;
+            let reg_a = parse_register(splits.next())?;
+            let imm = splits
+                .next()
+                .ok_or_else(|| err_msg("Expected immediate"))?;
+            let imm = self
+                .parse_immediate(imm)
+                .context("Couldn't parse the immediate")?;
+            data = if is_addi {
+                build_addi_instruction(reg_d, reg_a, imm as i16)
+            } else {
+                build_ori_instruction(reg_d, reg_a, imm as u16)
+            };
         } else if line == "nop" {
             data = 0x60000000;
         } else {
@@ -96,13 +137,42 @@ impl<'a> Assembler<'a> {
             return Ok(symbol);
         }
 
-        if let Some(&symbol) = self.prelinked_symbols.get(symbol) {
-            return Ok(symbol);
+        if let Some(symbol) = framework_map::lookup(self.prelinked_symbols, symbol) {
+            return Ok(symbol.address);
         }
 
         bail!(format!("The symbol \"{}\" wasn't found", symbol))
     }
 
+    /// Resolves the destination of a branch, which can't be a data symbol of
+    /// the game.
+    fn resolve_function(&self, symbol: &str) -> Result<u32, Error> {
+        if let Some(game_symbol) = framework_map::lookup(self.prelinked_symbols, symbol) {
+            ensure!(
+                game_symbol.kind != SymbolKind::Object,
+                "The symbol \"{}\" is data and can't be branched to",
+                symbol
+            );
+        }
+        self.resolve_symbol(symbol)
+    }
+
+    /// Parses a 16-bit immediate, which is either an integer literal or the
+    /// high adjusted (`@ha`), high (`@h`) or low (`@l`) half of a symbol's
+    /// address.
+    fn parse_immediate(&self, imm: &str) -> Result<u16, Error> {
+        Ok(if imm.ends_with("@ha") {
+            let address = self.resolve_symbol(&imm[..imm.len() - 3])?;
+            (address.wrapping_add(0x8000) >> 16) as u16
+        } else if imm.ends_with("@h") {
+            (self.resolve_symbol(&imm[..imm.len() - 2])? >> 16) as u16
+        } else if imm.ends_with("@l") {
+            self.resolve_symbol(&imm[..imm.len() - 2])? as u16
+        } else {
+            parse_i64_literal(imm)? as u16
+        })
+    }
+
     fn parse_program_counter_label(&self, line: &str) -> Result<u32, Error> {
         let mut line = line[..line.len() - 1].trim_left();
         let mut address = 0u32;
@@ -177,6 +247,17 @@ fn reduce_line_to_code(line: &str) -> &str {
     line.trim()
 }
 
+fn parse_register(register: Option<&str>) -> Result<u8, Error> {
+    let register = register.ok_or_else(|| err_msg("Expected register"))?;
+    if !register.starts_with('r') {
+        bail!("Unexpected register: \"{}\"", register);
+    }
+    let index =
+        parse_i64_literal(&register[1..]).context("Couldn't parse the register index")?;
+    ensure!(0 <= index && index < 32, "Unexpected register: \"{}\"", register);
+    Ok(index as u8)
+}
+
 fn parse_i64_literal(literal: &str) -> Result<i64, ParseError> {
     let val: syn::LitInt = syn::parse_str(literal)?;
     Ok(val.value() as i64)
@@ -208,3 +289,17 @@ fn build_addis_instruction(reg_d: u8, reg_a: u8, imm: i16) -> u32 {
         | ((reg_a as u32 & 0b11111) << 16)
         | (imm as u32 & 0xFFFF)
 }
+
+fn build_addi_instruction(reg_d: u8, reg_a: u8, imm: i16) -> u32 {
+    0x3800_0000
+        | ((reg_d as u32 & 0b11111) << 21)
+        | ((reg_a as u32 & 0b11111) << 16)
+        | (imm as u32 & 0xFFFF)
+}
+
+fn build_ori_instruction(reg_a: u8, reg_s: u8, imm: u16) -> u32 {
+    0x6000_0000
+        | ((reg_s as u32 & 0b11111) << 21)
+        | ((reg_a as u32 & 0b11111) << 16)
+        | imm as u32
+}
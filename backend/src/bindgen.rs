@@ -0,0 +1,306 @@
+//! Generates Rust bindings for the functions of the game out of the signatures
+//! of their mangled names in the symbol map.
+
+use demangle::{parse_signature, Type};
+use framework_map::GameSymbol;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+const KEYWORDS: &[&str] = &[
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "crate",
+    "do", "dyn", "else", "enum", "extern", "false", "final", "fn", "for", "if", "impl", "in",
+    "let", "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref",
+    "return", "self", "Self", "static", "struct", "super", "trait", "true", "try", "type",
+    "typeof", "unsafe", "unsized", "use", "virtual", "where", "while", "yield",
+];
+
+const PRIMITIVES: &[&str] = &[
+    "i8", "i16", "i32", "i64", "u8", "u16", "u32", "u64", "f32", "f64", "bool",
+];
+
+/// A namespace or class of the game, which becomes a module.
+#[derive(Default)]
+struct Module {
+    /// Classes get an opaque type of the same name in their module.
+    is_class: bool,
+    functions: Vec<String>,
+    /// How often each function name is used, so overloads get unique names.
+    function_names: HashMap<String, usize>,
+    modules: BTreeMap<String, Module>,
+}
+
+impl Module {
+    fn get(&mut self, path: &[String]) -> &mut Module {
+        path.iter().fold(self, |module, name| {
+            module
+                .modules
+                .entry(name.clone())
+                .or_insert_with(Module::default)
+        })
+    }
+
+    fn write(&self, out: &mut String, name: &str, indent: usize) {
+        let pad = " ".repeat(4 * indent);
+        if self.is_class {
+            writeln!(out, "{}#[repr(C)]", pad).unwrap();
+            writeln!(out, "{}pub struct {} {{", pad, name).unwrap();
+            writeln!(out, "{}    _private: [u8; 0],", pad).unwrap();
+            writeln!(out, "{}}}", pad).unwrap();
+        }
+
+        if !self.functions.is_empty() {
+            if self.is_class {
+                writeln!(out).unwrap();
+            }
+            writeln!(out, "{}extern \"C\" {{", pad).unwrap();
+            for (index, function) in self.functions.iter().enumerate() {
+                if index != 0 {
+                    writeln!(out).unwrap();
+                }
+                for line in function.lines() {
+                    writeln!(out, "{}    {}", pad, line).unwrap();
+                }
+            }
+            writeln!(out, "{}}}", pad).unwrap();
+        }
+
+        for (index, (name, module)) in self.modules.iter().enumerate() {
+            if index != 0 || self.is_class || !self.functions.is_empty() {
+                writeln!(out).unwrap();
+            }
+            writeln!(out, "{}pub mod {} {{", pad, name).unwrap();
+            module.write(out, name, indent + 1);
+            writeln!(out, "{}}}", pad).unwrap();
+        }
+    }
+}
+
+fn identifier(name: &str) -> Option<String> {
+    let is_valid = name
+        .chars()
+        .next()
+        .map_or(false, |c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+    if !is_valid {
+        None
+    } else if KEYWORDS.contains(&name) {
+        Some(format!("{}_", name))
+    } else {
+        Some(name.to_owned())
+    }
+}
+
+/// Translates the types of the CodeWarrior type grammar into Rust types.
+struct TypeMapper<'m> {
+    root: &'m mut Module,
+    /// How deep the module is that the type is used in.
+    depth: usize,
+}
+
+impl<'m> TypeMapper<'m> {
+    /// The path of a class relative to the module the type is used in. The
+    /// class gets marked as such, so its opaque type gets generated.
+    fn class(&mut self, path: &[&str]) -> Option<String> {
+        let path = path
+            .iter()
+            .map(|p| identifier(p))
+            .collect::<Option<Vec<_>>>()?;
+        self.root.get(&path).is_class = true;
+
+        let mut rust_path = if self.depth == 0 {
+            String::from("self::")
+        } else {
+            "super::".repeat(self.depth)
+        };
+        for element in &path {
+            rust_path.push_str(element);
+            rust_path.push_str("::");
+        }
+        rust_path.push_str(path.last()?);
+        Some(rust_path)
+    }
+
+    /// Maps a type that is passed by value. Classes can't be passed by value,
+    /// as their layout is unknown.
+    fn value(&mut self, typ: &Type) -> Option<String> {
+        match *typ {
+            Type::Normal(_, name) if PRIMITIVES.contains(&name) => Some(name.to_owned()),
+            Type::Pointer(_, ref pointee) => self.pointer(pointee, false),
+            Type::Reference(_, ref pointee) => self.pointer(pointee, true),
+            // Arrays decay to pointers when they are passed.
+            Type::Array(_, ref element) => Some(format!("*mut {}", self.pointee(element)?)),
+            _ => None,
+        }
+    }
+
+    /// Maps the type that a pointer or reference points to.
+    fn pointee(&mut self, typ: &Type) -> Option<String> {
+        match *typ {
+            Type::Normal(_, "void") => Some(String::from("u8")),
+            Type::Normal(_, name) if PRIMITIVES.contains(&name) => Some(name.to_owned()),
+            Type::Normal(_, name) => self.class(&[name]),
+            Type::Path(_, ref path) => self.class(path),
+            Type::Array(count, ref element) => {
+                Some(format!("[{}; {}]", self.pointee(element)?, count))
+            }
+            _ => self.value(typ),
+        }
+    }
+
+    fn pointer(&mut self, pointee: &Type, is_reference: bool) -> Option<String> {
+        if let Type::Function(_, ref return_type, ref parameters) = *pointee {
+            let function = self.function_type(return_type, parameters)?;
+            return Some(format!("Option<unsafe extern \"C\" fn{}>", function));
+        }
+
+        let is_const = match *pointee {
+            Type::Path(is_const, _)
+            | Type::Normal(is_const, _)
+            | Type::Pointer(is_const, _)
+            | Type::Reference(is_const, _)
+            | Type::Function(is_const, _, _) => is_const,
+            _ => false,
+        };
+        let pointee = self.pointee(pointee)?;
+
+        Some(match (is_reference, is_const) {
+            (false, false) => format!("*mut {}", pointee),
+            (false, true) => format!("*const {}", pointee),
+            (true, false) => format!("&mut {}", pointee),
+            (true, true) => format!("&{}", pointee),
+        })
+    }
+
+    fn return_type(&mut self, typ: &Type) -> Option<String> {
+        match *typ {
+            Type::Normal(_, "void") => Some(String::new()),
+            _ => Some(format!(" -> {}", self.value(typ)?)),
+        }
+    }
+
+    /// Maps the parameters and the return type of a function type.
+    fn function_type(&mut self, return_type: &Type, parameters: &[Type]) -> Option<String> {
+        let mut function = String::from("(");
+        for (index, parameter) in parameters.iter().enumerate() {
+            if index != 0 {
+                function.push_str(", ");
+            }
+            match *parameter {
+                Type::VarArgs if index + 1 == parameters.len() => function.push_str("..."),
+                Type::Normal(false, "void") => {}
+                _ => function.push_str(&self.value(parameter)?),
+            }
+        }
+        function.push(')');
+        function.push_str(&self.return_type(return_type)?);
+        Some(function)
+    }
+}
+
+/// The extern declaration of a function.
+struct Function {
+    /// The module the function belongs into.
+    path: Vec<String>,
+    name: String,
+    link_name: String,
+    parameters: Vec<String>,
+    return_type: String,
+}
+
+fn function(root: &mut Module, mangled_name: &str) -> Option<Function> {
+    let signature = parse_signature(mangled_name).ok()?;
+
+    let path = signature
+        .path
+        .iter()
+        .map(|p| identifier(p))
+        .collect::<Option<Vec<_>>>()?;
+    let name = match signature.name {
+        "__ct" => String::from("construct"),
+        "__dt" => String::from("destruct"),
+        name if name.starts_with("__") => return None,
+        name => identifier(name)?,
+    };
+
+    let mut mapper = TypeMapper {
+        root,
+        depth: path.len(),
+    };
+
+    let mut parameters = Vec::new();
+    if !path.is_empty() {
+        let class = mapper.class(&signature.path)?;
+        parameters.push(if signature.is_const {
+            format!("this: *const {}", class)
+        } else {
+            format!("this: *mut {}", class)
+        });
+    }
+    for (index, parameter) in signature.parameters.iter().enumerate() {
+        if let Type::VarArgs = *parameter {
+            if index + 1 != signature.parameters.len() {
+                return None;
+            }
+            parameters.push(String::from("..."));
+        } else {
+            parameters.push(format!("arg{}: {}", index, mapper.value(parameter)?));
+        }
+    }
+    let return_type = mapper.return_type(&signature.return_type)?;
+
+    Some(Function {
+        path,
+        name,
+        link_name: mangled_name.to_owned(),
+        parameters,
+        return_type,
+    })
+}
+
+/// Generates a Rust module with extern declarations for all the mangled
+/// functions of the game. The functions are grouped into modules by their
+/// namespaces and classes. Functions of classes are assumed to be methods that
+/// take the object as their first parameter.
+pub fn generate(symbols: &HashMap<String, GameSymbol>) -> String {
+    let mut symbols = symbols
+        .values()
+        .filter_map(|symbol| symbol.mangled_name.as_ref())
+        .collect::<Vec<_>>();
+    symbols.sort();
+
+    let mut root = Module::default();
+    for mangled_name in symbols {
+        if let Some(function) = function(&mut root, mangled_name) {
+            let module = root.get(&function.path);
+
+            // Overloaded functions get numbered.
+            let count = module
+                .function_names
+                .entry(function.name.clone())
+                .or_insert(0);
+            let name = if *count == 0 {
+                function.name
+            } else {
+                format!("{}_{}", function.name, count)
+            };
+            *count += 1;
+
+            module.functions.push(format!(
+                "#[link_name = {:?}]\npub fn {}({}){};",
+                function.link_name,
+                name,
+                function.parameters.join(", "),
+                function.return_type
+            ));
+        }
+    }
+
+    let mut out = String::from(
+        "//! Bindings for the functions of the game, generated by `romhack bindgen`.\n\
+         \n\
+         #![allow(non_snake_case, non_camel_case_types, dead_code)]\n\n",
+    );
+    root.write(&mut out, "", 0);
+    out
+}
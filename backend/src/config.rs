@@ -1,15 +1,185 @@
-use std::collections::HashMap;
+use failure::Error;
+use iso::formats::DiscFormat;
+use iso::writer::{Layout, WriteOptions};
+use std::collections::{BTreeMap, HashMap};
 use std::path::PathBuf;
 
 #[derive(Deserialize, Serialize, Debug)]
 pub struct Config {
     #[serde(default)]
     pub info: Info,
+    #[serde(default)]
+    pub disc: Disc,
     pub src: Src,
     #[serde(default)]
-    pub files: HashMap<String, PathBuf>,
+    pub files: Files,
     pub build: Build,
     pub link: Link,
+    /// Symbols of the game that aren't in any of its symbol maps, such as the
+    /// ones that got reverse engineered. They take precedence over the maps.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub symbols: BTreeMap<String, SymbolAddress>,
+    /// The game the patch was built for. Patches only get applied to games
+    /// that match it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub original: Option<Original>,
+    /// Variants of the Rom Hack, such as the different regions of the game,
+    /// that override parts of the configuration.
+    #[serde(default, rename = "target", skip_serializing_if = "HashMap::is_empty")]
+    pub targets: HashMap<String, Target>,
+}
+
+impl Config {
+    /// Applies the overrides of the target. The built game and symbol map get
+    /// the name of the target appended, so the targets don't overwrite each
+    /// other.
+    pub fn apply_target(&mut self, name: &str) -> Result<(), Error> {
+        let target = self
+            .targets
+            .remove(name)
+            .ok_or_else(|| format_err!("There is no target \"{}\".", name))?;
+        self.targets.clear();
+
+        let src = target.src;
+        if src.src.is_some() {
+            self.src.src = src.src;
+        }
+        if let Some(iso) = src.iso {
+            self.src.iso = iso;
+        }
+        if src.patch.is_some() {
+            self.src.patch = src.patch;
+        }
+        if src.map.is_some() {
+            self.src.map = src.map;
+        }
+        if let Some(maps) = src.maps {
+            self.src.maps = maps;
+        }
+
+        let link = target.link;
+        if let Some(entries) = link.entries {
+            self.link.entries = entries;
+        }
+        if let Some(base) = link.base {
+            self.link.base = base;
+        }
+        if link.libs.is_some() {
+            self.link.libs = link.libs;
+        }
+
+        self.files.remove.extend(target.files.remove);
+        self.files.rename.extend(target.files.rename);
+        self.files.replace.extend(target.files.replace);
+
+        self.symbols.extend(target.symbols);
+
+        append_to_file_stem(&mut self.build.iso, name);
+        if let Some(map) = &mut self.build.map {
+            append_to_file_stem(map, name);
+        }
+
+        Ok(())
+    }
+}
+
+fn append_to_file_stem(path: &mut PathBuf, suffix: &str) {
+    let mut file_name = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    file_name.push('-');
+    file_name.push_str(suffix);
+    if let Some(extension) = path.extension() {
+        file_name.push('.');
+        file_name.push_str(&extension.to_string_lossy());
+    }
+    path.set_file_name(file_name);
+}
+
+/// The parts of the configuration that a target overrides. Replaced files,
+/// symbols, removals and renames get added to the ones of the base
+/// configuration.
+#[derive(Deserialize, Serialize, Default, Debug)]
+pub struct Target {
+    #[serde(default)]
+    pub src: TargetSrc,
+    #[serde(default)]
+    pub link: TargetLink,
+    #[serde(default)]
+    pub files: Files,
+    #[serde(default)]
+    pub symbols: BTreeMap<String, SymbolAddress>,
+}
+
+#[derive(Deserialize, Serialize, Default, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct TargetSrc {
+    pub src: Option<PathBuf>,
+    pub iso: Option<PathBuf>,
+    pub patch: Option<PathBuf>,
+    pub map: Option<String>,
+    pub maps: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Serialize, Default, Debug)]
+pub struct TargetLink {
+    pub entries: Option<Vec<String>>,
+    pub base: Option<String>,
+    pub libs: Option<Vec<PathBuf>>,
+}
+
+#[derive(Deserialize, Serialize, Default, Debug)]
+pub struct Files {
+    /// The files and folders to remove from the game, in the order they are
+    /// listed. They get removed before anything gets renamed.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub remove: Vec<String>,
+    /// The files and folders to rename or move, in the order they are listed.
+    /// They get renamed after the removals and before any files get replaced.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rename: Vec<RenamedFile>,
+    /// The files to add to the game or replace, keyed by their path in the
+    /// game.
+    #[serde(flatten)]
+    pub replace: HashMap<String, FileReplacement>,
+}
+
+/// A file or folder to rename or move to a different folder of the game.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct RenamedFile {
+    pub from: String,
+    pub to: String,
+}
+
+/// A file to replace in the game. It's either just the path to the new file or
+/// a table that additionally specifies how to store it.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(untagged)]
+pub enum FileReplacement {
+    Path(PathBuf),
+    Detailed {
+        path: PathBuf,
+        /// Whether the file gets Yaz0 compressed before it's stored in the
+        /// game. If not specified, it gets compressed if the original file is.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        yaz0: Option<bool>,
+    },
+}
+
+impl FileReplacement {
+    pub fn path(&self) -> &PathBuf {
+        match *self {
+            FileReplacement::Path(ref path) | FileReplacement::Detailed { ref path, .. } => path,
+        }
+    }
+
+    pub fn yaz0(&self) -> Option<bool> {
+        match *self {
+            FileReplacement::Path(_) => None,
+            FileReplacement::Detailed { yaz0, .. } => yaz0,
+        }
+    }
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -19,6 +189,26 @@ pub struct Src {
     pub iso: PathBuf,
     pub patch: Option<PathBuf>,
     pub map: Option<String>,
+    /// Additional symbol maps. Just like `map`, they are looked up in the game
+    /// first and on the host otherwise.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub maps: Vec<String>,
+}
+
+impl Src {
+    /// All the symbol maps in the order they get merged in.
+    pub fn all_maps<'a>(&'a self) -> impl Iterator<Item = &'a String> + 'a {
+        self.map.iter().chain(&self.maps)
+    }
+}
+
+/// The address of a symbol. TOML doesn't support hexadecimal numbers, so it may
+/// also be a string such as `"0x8000_3100"`.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum SymbolAddress {
+    Number(u32),
+    Text(String),
 }
 
 #[derive(Deserialize, Serialize, Default, Debug)]
@@ -32,10 +222,84 @@ pub struct Info {
     pub image: Option<PathBuf>,
 }
 
+/// Changes to the disc header. Every option that isn't specified keeps the
+/// value of the original game.
 #[derive(Deserialize, Serialize, Default, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct Disc {
+    /// The four character game ID, optionally followed by the maker code.
+    pub game_id: Option<String>,
+    pub maker_code: Option<String>,
+    pub disc_number: Option<u8>,
+    pub revision: Option<u8>,
+    pub internal_name: Option<String>,
+    #[serde(default)]
+    pub bi2: Bi2,
+}
+
+#[derive(Deserialize, Serialize, Default, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct Bi2 {
+    pub debug_monitor_size: Option<u32>,
+    pub simulated_memory_size: Option<u32>,
+    pub argument_offset: Option<u32>,
+    pub debug_flag: Option<u32>,
+    pub track_location: Option<u32>,
+    pub track_size: Option<u32>,
+    pub region: Option<Region>,
+}
+
+#[derive(Deserialize, Serialize, Copy, Clone, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Region {
+    Japan = 0,
+    Usa = 1,
+    Pal = 2,
+}
+
+#[derive(Deserialize, Serialize, Default, Debug)]
+#[serde(rename_all = "kebab-case")]
 pub struct Build {
     pub map: Option<PathBuf>,
     pub iso: PathBuf,
+    /// The disc format of the built game. If not specified, it's determined
+    /// by the file extension of the ISO.
+    pub format: Option<DiscFormat>,
+    /// Fills the unused areas of the disc with junk data like on retail discs.
+    /// This isn't supported for RVZ images.
+    #[serde(default)]
+    pub junk_padding: bool,
+    /// Whether the files are packed or stay at their original offsets.
+    #[serde(default)]
+    pub layout: Layout,
+    pub file_alignment: Option<usize>,
+    /// Fails the build if the game doesn't fit onto a GameCube disc.
+    #[serde(default)]
+    pub limit_size: bool,
+    /// Stores the already linked Rom Hack in patches, so applying them
+    /// doesn't need to link it again.
+    #[serde(default)]
+    pub prelink: bool,
+    /// Additionally creates a binary patch from the original game to the built
+    /// one. It's a BPS patch if the file extension is `.bps`, otherwise it's a
+    /// VCDIFF patch like the ones xdelta3 creates.
+    pub delta: Option<PathBuf>,
+    /// Additionally stores the Rom Hack as patches in a game settings file and
+    /// a symbol map in this Dolphin user folder. This way the Rom Hack can be
+    /// tried out in Dolphin on the original game.
+    pub dolphin: Option<PathBuf>,
+}
+
+impl Build {
+    pub fn write_options(&self) -> WriteOptions {
+        let defaults = WriteOptions::default();
+        WriteOptions {
+            junk_padding: self.junk_padding,
+            layout: self.layout,
+            file_alignment: self.file_alignment.unwrap_or(defaults.file_alignment),
+            limit_size: self.limit_size,
+        }
+    }
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -43,4 +307,29 @@ pub struct Link {
     pub entries: Vec<String>,
     pub base: String,
     pub libs: Option<Vec<PathBuf>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prelinked: Option<Prelinked>,
+}
+
+/// The already linked Rom Hack stored in a patch.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Prelinked {
+    /// The linked sections, stored as a DOL.
+    pub dol: PathBuf,
+    /// The instructions assembled from the patch.asm.
+    pub instructions: PathBuf,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct Original {
+    /// The game ID including the maker code.
+    pub game_id: String,
+    pub revision: u8,
+    /// The SHA-1 hash of the main DOL.
+    pub dol_sha1: String,
+    /// The SHA-1 hashes of the original files that the patch replaces, keyed
+    /// by their path in the game.
+    #[serde(default)]
+    pub files: HashMap<String, String>,
 }
@@ -0,0 +1,298 @@
+//! Based on byuu's BPS specification of the beat patcher.
+
+use super::{Op, Output, Source, CHUNK_SIZE};
+use byteorder::{ByteOrder, LE};
+use failure::{err_msg, Error};
+use flate2::Crc;
+use std::cmp;
+use std::io::{self, Read, Seek, Write};
+
+pub(super) const MAGIC: &[u8; 4] = b"BPS1";
+
+const FOOTER_LENGTH: usize = 12;
+
+const SOURCE_READ: u64 = 0;
+const TARGET_READ: u64 = 1;
+const SOURCE_COPY: u64 = 2;
+const TARGET_COPY: u64 = 3;
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc::new();
+    crc.update(data);
+    crc.sum()
+}
+
+fn write_number(buf: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let x = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            buf.push(0x80 | x);
+            break;
+        }
+        buf.push(x);
+        value -= 1;
+    }
+}
+
+fn write_action(buf: &mut Vec<u8>, action: u64, len: usize) {
+    write_number(buf, (len as u64 - 1) << 2 | action);
+}
+
+fn write_u32(buf: &mut Vec<u8>, value: u32) {
+    let mut bytes = [0; 4];
+    LE::write_u32(&mut bytes, value);
+    buf.extend_from_slice(&bytes);
+}
+
+/// Writes the patch window by window. The checksum of the patch is
+/// calculated along the way, as it's part of the footer.
+pub(super) struct Encoder<W> {
+    writer: W,
+    buf: Vec<u8>,
+    crc: Crc,
+    output_offset: u64,
+    source_relative: i64,
+}
+
+impl<W: Write> Encoder<W> {
+    pub(super) fn new(writer: W, source_len: u64, target_len: u64) -> io::Result<Self> {
+        let mut encoder = Encoder {
+            writer,
+            buf: MAGIC.to_vec(),
+            crc: Crc::new(),
+            output_offset: 0,
+            source_relative: 0,
+        };
+        write_number(&mut encoder.buf, source_len);
+        write_number(&mut encoder.buf, target_len);
+        // No metadata
+        write_number(&mut encoder.buf, 0);
+        encoder.flush()?;
+        Ok(encoder)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.crc.update(&self.buf);
+        self.writer.write_all(&self.buf)?;
+        self.buf.clear();
+        Ok(())
+    }
+
+    pub(super) fn encode_window(&mut self, window: &[u8], ops: &[Op]) -> io::Result<()> {
+        for op in ops {
+            match *op {
+                Op::Copy { offset, len } if offset == self.output_offset => {
+                    write_action(&mut self.buf, SOURCE_READ, len);
+                }
+                Op::Copy { offset, len } => {
+                    write_action(&mut self.buf, SOURCE_COPY, len);
+                    let relative = offset as i64 - self.source_relative;
+                    write_number(
+                        &mut self.buf,
+                        (relative.abs() as u64) << 1 | (relative < 0) as u64,
+                    );
+                    self.source_relative = (offset + len as u64) as i64;
+                }
+                Op::Insert { offset, len } => {
+                    write_action(&mut self.buf, TARGET_READ, len);
+                    self.buf.extend_from_slice(&window[offset..][..len]);
+                }
+            }
+            self.output_offset += op.len() as u64;
+        }
+        self.flush()
+    }
+
+    pub(super) fn finish(mut self, source_crc: u32, target_crc: u32) -> io::Result<()> {
+        write_u32(&mut self.buf, source_crc);
+        write_u32(&mut self.buf, target_crc);
+        self.flush()?;
+        let patch_crc = self.crc.sum();
+        write_u32(&mut self.buf, patch_crc);
+        self.writer.write_all(&self.buf)
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        ensure!(len <= self.data.len(), "The patch ends unexpectedly");
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn number(&mut self) -> Result<u64, Error> {
+        let (mut value, mut shift) = (0u64, 1u64);
+        loop {
+            let x = self.bytes(1)?[0];
+            value += (x & 0x7F) as u64 * shift;
+            if x & 0x80 != 0 {
+                return Ok(value);
+            }
+            ensure!(shift < 1 << 49, "The patch contains an invalid number");
+            shift <<= 7;
+            value += shift;
+        }
+    }
+}
+
+/// Moves the relative offset by the signed amount that got decoded.
+fn relative(offset: u64, value: u64) -> Result<u64, Error> {
+    let amount = value >> 1;
+    let offset = if value & 1 != 0 {
+        offset.checked_sub(amount)
+    } else {
+        offset.checked_add(amount)
+    };
+    offset.ok_or_else(|| err_msg("The patch references data outside of the game"))
+}
+
+/// Writes the data to the output while keeping track of its checksum.
+fn push<W>(output: &mut Output<W>, crc: &mut Crc, data: &[u8]) -> Result<(), Error>
+where
+    W: Read + Write + Seek,
+{
+    crc.update(data);
+    output.push(data)?;
+    Ok(())
+}
+
+/// The header and footer of a BPS patch and the actions in between.
+struct Patch<'a> {
+    actions: Reader<'a>,
+    source_size: u64,
+    target_size: u64,
+    footer: &'a [u8],
+}
+
+fn parse<'a>(patch: &'a [u8]) -> Result<Patch<'a>, Error> {
+    ensure!(
+        patch.starts_with(MAGIC) && patch.len() >= MAGIC.len() + FOOTER_LENGTH,
+        "The file is not a BPS patch"
+    );
+    let (body, footer) = patch.split_at(patch.len() - FOOTER_LENGTH);
+
+    ensure!(
+        crc32(&patch[..patch.len() - 4]) == LE::read_u32(&footer[8..]),
+        "The patch is corrupted"
+    );
+
+    let mut reader = Reader {
+        data: &body[MAGIC.len()..],
+    };
+    let source_size = reader.number()?;
+    let target_size = reader.number()?;
+    let metadata_size = reader.number()?;
+    ensure!(
+        metadata_size <= reader.data.len() as u64,
+        "The patch ends unexpectedly"
+    );
+    reader.bytes(metadata_size as usize)?;
+
+    Ok(Patch {
+        actions: reader,
+        source_size,
+        target_size,
+        footer,
+    })
+}
+
+/// Checks the size and the checksum of the source, so a patch that isn't meant
+/// for the game is rejected before anything gets written.
+pub(super) fn verify_source<R>(patch: &[u8], source: &mut Source<R>) -> Result<(), Error>
+where
+    R: Read + Seek,
+{
+    let patch = parse(patch)?;
+    let mut crc = Crc::new();
+    source.inspect(|_, data| crc.update(data))?;
+    ensure!(
+        source.len == patch.source_size && crc.sum() == LE::read_u32(patch.footer),
+        "The patch isn't meant for this game"
+    );
+    Ok(())
+}
+
+/// Applies the patch. The source is expected to be verified already, so only
+/// the checksum of the patched game gets verified.
+pub(super) fn decode<R, W>(
+    patch: &[u8],
+    source: &mut Source<R>,
+    output: &mut Output<W>,
+    verify: bool,
+) -> Result<(), Error>
+where
+    R: Read + Seek,
+    W: Read + Write + Seek,
+{
+    let Patch {
+        actions: mut reader,
+        target_size,
+        footer,
+        ..
+    } = parse(patch)?;
+
+    let mut crc = Crc::new();
+    let (mut source_relative, mut target_relative) = (0, 0);
+
+    while !reader.data.is_empty() {
+        let action = reader.number()?;
+        let len = (action >> 2) + 1;
+        ensure!(
+            output.len() + len <= target_size,
+            "The patch writes past the end of the game"
+        );
+
+        match action & 3 {
+            SOURCE_READ => {
+                let start = output.len();
+                source.copy(start, len, |data| push(output, &mut crc, data))?;
+            }
+            TARGET_READ => {
+                let data = reader.bytes(len as usize)?;
+                push(output, &mut crc, data)?;
+            }
+            SOURCE_COPY => {
+                source_relative = relative(source_relative, reader.number()?)?;
+                source.copy(source_relative, len, |data| push(output, &mut crc, data))?;
+                source_relative += len;
+            }
+            TARGET_COPY => {
+                target_relative = relative(target_relative, reader.number()?)?;
+                ensure!(
+                    target_relative < output.len(),
+                    "The patch copies data that isn't written yet"
+                );
+                // The copy may overlap with the data it writes, so it only
+                // reads as much at once as is already written.
+                let mut remaining = len;
+                let mut buf = Vec::new();
+                while remaining > 0 {
+                    let available = output.len() - target_relative;
+                    let piece = cmp::min(cmp::min(remaining, available), CHUNK_SIZE as u64);
+                    buf.resize(piece as usize, 0);
+                    output.read_at(target_relative, &mut buf)?;
+                    push(output, &mut crc, &buf)?;
+                    target_relative += piece;
+                    remaining -= piece;
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    ensure!(output.len() == target_size, "The patch is incomplete");
+    if verify {
+        ensure!(
+            crc.sum() == LE::read_u32(&footer[4..]),
+            "The patched game doesn't match the expected checksum"
+        );
+    }
+
+    Ok(())
+}
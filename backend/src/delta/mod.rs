@@ -0,0 +1,569 @@
+//! Binary patches from the original game to the Rom Hack, for distribution
+//! channels that only accept standard patch formats. Both BPS and VCDIFF, as
+//! created by xdelta3, are supported. Patches are created and applied window
+//! by window, so neither of the disc images has to fit into memory.
+
+mod bps;
+mod vcdiff;
+
+use failure::{err_msg, Error};
+use flate2::Crc;
+use std::cmp;
+use std::collections::HashMap;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum DeltaFormat {
+    Vcdiff,
+    Bps,
+}
+
+impl DeltaFormat {
+    /// Determines the format by the file extension. Everything but `.bps` is
+    /// a VCDIFF patch.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Self {
+        let extension = path
+            .as_ref()
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase());
+
+        match extension.as_ref().map(|e| &**e) {
+            Some("bps") => DeltaFormat::Bps,
+            _ => DeltaFormat::Vcdiff,
+        }
+    }
+
+    /// Detects the format of a patch by its magic.
+    pub fn detect(patch: &[u8]) -> Option<Self> {
+        if patch.starts_with(bps::MAGIC) {
+            Some(DeltaFormat::Bps)
+        } else if patch.starts_with(vcdiff::MAGIC) {
+            Some(DeltaFormat::Vcdiff)
+        } else {
+            None
+        }
+    }
+}
+
+/// The operations that produce a window of the target one after another.
+#[derive(Copy, Clone, Debug)]
+enum Op {
+    /// Copies data from the source.
+    Copy { offset: u64, len: usize },
+    /// Stores the data of the window at the offset in the patch itself.
+    Insert { offset: usize, len: usize },
+}
+
+impl Op {
+    fn len(&self) -> usize {
+        match *self {
+            Op::Copy { len, .. } | Op::Insert { len, .. } => len,
+        }
+    }
+}
+
+/// The amount of the target that gets diffed at once. This is also the size of
+/// the windows of VCDIFF patches.
+const WINDOW_SIZE: usize = 1 << 23;
+/// The amount of the source that is read at once when looking at data that
+/// isn't at the same position as the window.
+const CHUNK_SIZE: usize = 1 << 16;
+const BLOCK_SIZE: usize = 256;
+/// Only the blocks at this distance from each other are indexed, which keeps
+/// the index small for whole disc images. Data that got moved still contains
+/// an indexed block, as long as it's larger than this.
+const INDEX_STRIDE: usize = 4096;
+const HASH_BASE: u32 = 0x0100_0193;
+
+fn hash(block: &[u8]) -> u32 {
+    block
+        .iter()
+        .fold(0, |hash, &b| hash.wrapping_mul(HASH_BASE).wrapping_add(b as u32))
+}
+
+fn len_of<R: Seek>(reader: &mut R) -> io::Result<u64> {
+    let len = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(0))?;
+    Ok(len)
+}
+
+/// Reads as much as possible into the buffer, which is only less than its size
+/// at the end of the reader.
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// Random access to the source, which keeps the chunk that was read last.
+struct Source<R> {
+    reader: R,
+    len: u64,
+    chunk: Vec<u8>,
+    chunk_start: u64,
+}
+
+impl<R: Read + Seek> Source<R> {
+    fn new(mut reader: R) -> io::Result<Self> {
+        let len = len_of(&mut reader)?;
+        Ok(Source {
+            reader,
+            len,
+            chunk: Vec::new(),
+            chunk_start: 0,
+        })
+    }
+
+    /// Reads up to `len` bytes at the offset. Less is only returned at the end
+    /// of the source.
+    fn get(&mut self, offset: u64, len: usize) -> io::Result<&[u8]> {
+        let chunk_end = self.chunk_start + self.chunk.len() as u64;
+        if offset < self.chunk_start || offset + len as u64 > chunk_end {
+            let available = self.len.saturating_sub(offset);
+            let len = cmp::min(cmp::max(len, CHUNK_SIZE) as u64, available);
+            self.chunk.resize(len as usize, 0);
+            self.reader.seek(SeekFrom::Start(offset))?;
+            self.reader.read_exact(&mut self.chunk)?;
+            self.chunk_start = offset;
+        }
+        let start = (offset - self.chunk_start) as usize;
+        let end = cmp::min(start + len, self.chunk.len());
+        Ok(&self.chunk[start..end])
+    }
+
+    /// Calls the function with consecutive pieces of the `len` bytes at the
+    /// offset.
+    fn copy<F>(&mut self, mut offset: u64, mut len: u64, mut f: F) -> Result<(), Error>
+    where
+        F: FnMut(&[u8]) -> Result<(), Error>,
+    {
+        ensure!(
+            offset.checked_add(len).map_or(false, |end| end <= self.len),
+            "The patch reads past the end of the game"
+        );
+        while len > 0 {
+            let piece = cmp::min(len, CHUNK_SIZE as u64) as usize;
+            f(self.get(offset, piece)?)?;
+            offset += piece as u64;
+            len -= piece as u64;
+        }
+        Ok(())
+    }
+
+    /// Streams through the whole source.
+    fn inspect<F: FnMut(u64, &[u8])>(&mut self, mut f: F) -> io::Result<()> {
+        self.reader.seek(SeekFrom::Start(0))?;
+        let mut buf = vec![0; WINDOW_SIZE];
+        let mut offset = 0;
+        loop {
+            let len = read_full(&mut self.reader, &mut buf)?;
+            if len == 0 {
+                return Ok(());
+            }
+            f(offset, &buf[..len]);
+            offset += len as u64;
+        }
+    }
+}
+
+/// The patched game that is being written. The data that was written last is
+/// kept in memory, as patches usually copy from the data they just wrote.
+struct Output<W> {
+    writer: W,
+    buffer: Vec<u8>,
+    flushed: u64,
+}
+
+impl<W: Read + Write + Seek> Output<W> {
+    fn new(writer: W) -> Self {
+        Output {
+            writer,
+            buffer: Vec::new(),
+            flushed: 0,
+        }
+    }
+
+    fn len(&self) -> u64 {
+        self.flushed + self.buffer.len() as u64
+    }
+
+    fn push(&mut self, data: &[u8]) -> io::Result<()> {
+        self.buffer.extend_from_slice(data);
+        if self.buffer.len() >= WINDOW_SIZE {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.write_all(&self.buffer)?;
+        self.flushed += self.buffer.len() as u64;
+        self.buffer.clear();
+        Ok(())
+    }
+
+    /// Reads data that was already written.
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        let end = offset + buf.len() as u64;
+        let split = cmp::min(buf.len() as u64, self.flushed.saturating_sub(offset)) as usize;
+        if split > 0 {
+            self.writer.seek(SeekFrom::Start(offset))?;
+            self.writer.read_exact(&mut buf[..split])?;
+            self.writer.seek(SeekFrom::Start(self.flushed))?;
+        }
+        if end > self.flushed {
+            let start = (offset + split as u64 - self.flushed) as usize;
+            let len = buf.len() - split;
+            buf[split..].copy_from_slice(&self.buffer[start..][..len]);
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        self.flush()?;
+        self.writer.flush()
+    }
+}
+
+/// Indexes the source by the hashes of its blocks and calculates its checksum
+/// along the way.
+fn index_source<R: Read + Seek>(source: &mut Source<R>) -> io::Result<(HashMap<u32, u64>, u32)> {
+    let mut index = HashMap::new();
+    let mut crc = Crc::new();
+    source.inspect(|offset, data| {
+        crc.update(data);
+        // The chunks are a multiple of the stride, so every indexed block
+        // starts at the same place within them.
+        let mut pos = 0;
+        while pos + BLOCK_SIZE <= data.len() {
+            index
+                .entry(hash(&data[pos..][..BLOCK_SIZE]))
+                .or_insert(offset + pos as u64);
+            pos += INDEX_STRIDE;
+        }
+    })?;
+    Ok((index, crc.sum()))
+}
+
+/// Finds the parts of the window of the target that can be copied from the
+/// source. Unchanged data usually stays where it is, so it's compared to the
+/// source at the same position first. Everything else is looked up in the
+/// index with a rolling hash over the window. Every match is extended in both
+/// directions.
+fn diff_window<R: Read + Seek>(
+    source: &mut Source<R>,
+    index: &HashMap<u32, u64>,
+    window: &[u8],
+    window_start: u64,
+) -> Result<Vec<Op>, Error> {
+    let mut same = vec![0; window.len()];
+    if window_start < source.len {
+        source.reader.seek(SeekFrom::Start(window_start))?;
+        let len = read_full(&mut source.reader, &mut same)?;
+        same.truncate(len);
+    } else {
+        same.clear();
+    }
+
+    // The factor of the byte that leaves the rolling hash.
+    let out_factor = (1..BLOCK_SIZE).fold(1u32, |f, _| f.wrapping_mul(HASH_BASE));
+
+    let mut ops = Vec::new();
+    let (mut pos, mut literal_start) = (0, 0);
+    let mut rolling_hash = window.get(..BLOCK_SIZE).map_or(0, hash);
+
+    while pos + BLOCK_SIZE <= window.len() {
+        let block = &window[pos..][..BLOCK_SIZE];
+
+        let found = if same.get(pos..pos + BLOCK_SIZE) == Some(block) {
+            Some(window_start + pos as u64)
+        } else {
+            match index.get(&rolling_hash) {
+                Some(&offset) if source.get(offset, BLOCK_SIZE)? == block => Some(offset),
+                _ => None,
+            }
+        };
+
+        if let Some(offset) = found {
+            let back = {
+                let max = cmp::min((pos - literal_start) as u64, offset) as usize;
+                source
+                    .get(offset - max as u64, max)?
+                    .iter()
+                    .rev()
+                    .zip(window[literal_start..pos].iter().rev())
+                    .take_while(|&(a, b)| a == b)
+                    .count()
+            };
+            let (offset, start) = (offset - back as u64, pos - back);
+
+            let mut len = 0;
+            while start + len < window.len() {
+                let piece = cmp::min(window.len() - start - len, CHUNK_SIZE);
+                let matched = source
+                    .get(offset + len as u64, piece)?
+                    .iter()
+                    .zip(&window[start + len..])
+                    .take_while(|&(a, b)| a == b)
+                    .count();
+                len += matched;
+                if matched < piece {
+                    break;
+                }
+            }
+
+            if start > literal_start {
+                ops.push(Op::Insert {
+                    offset: literal_start,
+                    len: start - literal_start,
+                });
+            }
+            ops.push(Op::Copy { offset, len });
+
+            pos = start + len;
+            literal_start = pos;
+            if let Some(block) = window.get(pos..pos + BLOCK_SIZE) {
+                rolling_hash = hash(block);
+            }
+        } else {
+            if let Some(&next) = window.get(pos + BLOCK_SIZE) {
+                rolling_hash = rolling_hash
+                    .wrapping_sub((window[pos] as u32).wrapping_mul(out_factor))
+                    .wrapping_mul(HASH_BASE)
+                    .wrapping_add(next as u32);
+            }
+            pos += 1;
+        }
+    }
+
+    if literal_start < window.len() {
+        ops.push(Op::Insert {
+            offset: literal_start,
+            len: window.len() - literal_start,
+        });
+    }
+
+    Ok(ops)
+}
+
+/// Diffs the target window by window. Returns the checksum of the target.
+fn diff<R, T, F>(
+    source: &mut Source<R>,
+    index: &HashMap<u32, u64>,
+    target: &mut T,
+    mut f: F,
+) -> Result<u32, Error>
+where
+    R: Read + Seek,
+    T: Read,
+    F: FnMut(&[u8], &[Op]) -> Result<(), Error>,
+{
+    let mut crc = Crc::new();
+    let mut window = vec![0; WINDOW_SIZE];
+    let mut window_start = 0;
+    loop {
+        let len = read_full(target, &mut window)?;
+        if len == 0 {
+            return Ok(crc.sum());
+        }
+        let window = &window[..len];
+        crc.update(window);
+        let ops = diff_window(source, index, window, window_start)?;
+        f(window, &ops)?;
+        window_start += len as u64;
+    }
+}
+
+/// Creates a patch that turns the source into the target.
+pub fn create<S, T, W>(
+    format: DeltaFormat,
+    source: S,
+    mut target: T,
+    mut patch: W,
+) -> Result<(), Error>
+where
+    S: Read + Seek,
+    T: Read + Seek,
+    W: Write,
+{
+    let mut source = Source::new(source)?;
+    let target_len = len_of(&mut target)?;
+    let (index, source_crc) = index_source(&mut source)?;
+
+    match format {
+        DeltaFormat::Vcdiff => {
+            let mut encoder = vcdiff::Encoder::new(&mut patch)?;
+            diff(&mut source, &index, &mut target, |window, ops| {
+                Ok(encoder.encode_window(window, ops)?)
+            })?;
+        }
+        DeltaFormat::Bps => {
+            let mut encoder = bps::Encoder::new(&mut patch, source.len, target_len)?;
+            let target_crc = diff(&mut source, &index, &mut target, |window, ops| {
+                Ok(encoder.encode_window(window, ops)?)
+            })?;
+            encoder.finish(source_crc, target_crc)?;
+        }
+    }
+
+    patch.flush()?;
+    Ok(())
+}
+
+/// Applies the patch to the source and writes the result to the output, which
+/// only gets created once the patch is known to be meant for the source.
+/// Unless `verify` is turned off, the checksums stored in the patch need to
+/// match.
+pub fn apply<S, W, F>(patch: &[u8], source: S, create_output: F, verify: bool) -> Result<(), Error>
+where
+    S: Read + Seek,
+    W: Read + Write + Seek,
+    F: FnOnce() -> Result<W, Error>,
+{
+    let format = DeltaFormat::detect(patch)
+        .ok_or_else(|| err_msg("The file is neither a VCDIFF nor a BPS patch"))?;
+    let mut source = Source::new(source)?;
+    // VCDIFF patches only store checksums of the target windows, so only BPS
+    // patches can be checked against the source up front.
+    if verify && format == DeltaFormat::Bps {
+        bps::verify_source(patch, &mut source)?;
+    }
+
+    let mut output = Output::new(create_output()?);
+    match format {
+        DeltaFormat::Vcdiff => vcdiff::decode(patch, &mut source, &mut output, verify)?,
+        DeltaFormat::Bps => bps::decode(patch, &mut source, &mut output, verify)?,
+    }
+    output.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// The example from section 3 of RFC 3284, encoded with the default code
+    /// table. It copies from the source, adds data, copies from the target in
+    /// a way that overlaps with the data it writes and ends with a run. The
+    /// window carries an Adler-32 checksum like the ones xdelta3 writes.
+    const VCDIFF_PATCH: &[u8] = &[
+        0xD6, 0xC3, 0xC4, 0x00, 0x00, // Header
+        0x05, 0x10, 0x00, 0x17, // Source segment and length of the delta
+        0x1C, 0x00, 0x05, 0x06, 0x03, // Window length and section lengths
+        0xA7, 0xFC, 0x0B, 0xBD, // Adler-32
+        0x77, 0x78, 0x79, 0x7A, 0x7A, // Data
+        0x14, 0x05, 0x14, 0x2C, 0x00, 0x04, // Instructions
+        0x00, 0x04, 0x04, // Addresses
+    ];
+
+    /// The same target as a BPS patch, which uses each of the four actions.
+    const BPS_PATCH: &[u8] = &[
+        0x42, 0x50, 0x53, 0x31, 0x90, 0x9C, 0x80, // Header
+        0x8C, // SourceRead 4
+        0x8D, 0x77, 0x78, 0x79, 0x7A, // TargetRead 4
+        0x8E, 0x88, // SourceCopy 4 from +4
+        0xAF, 0x90, // TargetCopy 12 from +8
+        0x81, 0x7A, // TargetRead 1
+        0x8B, 0x88, // TargetCopy 3 from +4
+        0x93, 0xC0, 0x3A, 0x94, 0xDA, 0xDA, 0x42, 0xBB, 0x76, 0x51, 0x72, 0x51, // Footer
+    ];
+
+    const SOURCE: &[u8] = b"abcdefghijklmnop";
+    const TARGET: &[u8] = b"abcdwxyzefghefghefghefghzzzz";
+
+    fn apply_patch(patch: &[u8], source: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut output = Cursor::new(Vec::new());
+        apply(patch, Cursor::new(source), || Ok(&mut output), true)?;
+        Ok(output.into_inner())
+    }
+
+    fn round_trip(format: DeltaFormat, source: &[u8], target: &[u8]) -> Vec<u8> {
+        let mut patch = Vec::new();
+        create(format, Cursor::new(source), Cursor::new(target), &mut patch).unwrap();
+        assert_eq!(DeltaFormat::detect(&patch), Some(format));
+        assert!(apply_patch(&patch, source).unwrap() == target);
+        patch
+    }
+
+    fn random(len: usize, mut state: u32) -> Vec<u8> {
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (state >> 16) as u8
+            })
+            .collect()
+    }
+
+    /// A target that spans multiple windows, with changed, moved, inserted
+    /// and appended data.
+    fn large_game() -> (Vec<u8>, Vec<u8>) {
+        let source = random(WINDOW_SIZE + 0x30_0000, 1);
+        let mut target = source.clone();
+        for byte in &mut target[0x1234..0x1300] {
+            *byte ^= 0xFF;
+        }
+        let moved = source[0x10_0000..0x14_0000].to_vec();
+        target[0x50_0000..0x54_0000].copy_from_slice(&moved);
+        let inserted = random(0x8000, 2);
+        let position = WINDOW_SIZE - 0x100;
+        target.splice(position..position, inserted);
+        target.extend(random(0x2345, 3));
+        assert!(target.len() > WINDOW_SIZE);
+        (source, target)
+    }
+
+    #[test]
+    fn vcdiff_vector() {
+        assert!(apply_patch(VCDIFF_PATCH, SOURCE).unwrap() == TARGET);
+    }
+
+    #[test]
+    fn bps_vector() {
+        assert!(apply_patch(BPS_PATCH, SOURCE).unwrap() == TARGET);
+    }
+
+    #[test]
+    fn round_trip_large() {
+        let (source, target) = large_game();
+        for &format in &[DeltaFormat::Vcdiff, DeltaFormat::Bps] {
+            let patch = round_trip(format, &source, &target);
+            assert!(patch.len() < 0x10_0000);
+        }
+    }
+
+    #[test]
+    fn round_trip_identical() {
+        let source = random(0x12_3456, 4);
+        for &format in &[DeltaFormat::Vcdiff, DeltaFormat::Bps] {
+            let patch = round_trip(format, &source, &source);
+            assert!(patch.len() < 0x40);
+        }
+    }
+
+    #[test]
+    fn wrong_source() {
+        let mut source = SOURCE.to_vec();
+        source[5] ^= 1;
+        let mut created = false;
+        let result = apply(
+            BPS_PATCH,
+            Cursor::new(&source),
+            || {
+                created = true;
+                Ok(Cursor::new(Vec::new()))
+            },
+            true,
+        );
+        assert!(result.is_err());
+        assert!(!created);
+    }
+}
@@ -0,0 +1,473 @@
+//! Based on RFC 3284, including the Adler-32 checksums that xdelta3 stores for
+//! every window. Secondary compression and custom code tables aren't
+//! supported.
+
+use super::{Op, Output, Source};
+use byteorder::{ByteOrder, BE};
+use failure::{err_msg, Error};
+use std::cmp;
+use std::io::{self, Read, Seek, Write};
+
+pub(super) const MAGIC: &[u8; 4] = b"\xD6\xC3\xC4\x00";
+
+const VCD_DECOMPRESS: u8 = 0x01;
+const VCD_CODETABLE: u8 = 0x02;
+const VCD_APPHEADER: u8 = 0x04;
+
+const VCD_SOURCE: u8 = 0x01;
+const VCD_TARGET: u8 = 0x02;
+const VCD_ADLER32: u8 = 0x04;
+
+const NEAR_CACHE_SIZE: usize = 4;
+const SAME_CACHE_SIZE: usize = 3;
+
+/// The codes of the default code table that encode the size separately.
+const CODE_ADD: u8 = 1;
+const CODE_COPY_SELF: u8 = 19;
+
+#[derive(Copy, Clone, PartialEq)]
+enum Kind {
+    Noop,
+    Add,
+    Run,
+    Copy,
+}
+
+#[derive(Copy, Clone)]
+struct Instruction {
+    kind: Kind,
+    size: u8,
+    mode: u8,
+}
+
+fn instruction(kind: Kind, size: u8, mode: u8) -> Instruction {
+    Instruction { kind, size, mode }
+}
+
+/// The default code table as listed in section 5.6 of the RFC.
+fn code_table() -> Vec<(Instruction, Instruction)> {
+    use self::Kind::*;
+
+    let noop = instruction(Noop, 0, 0);
+    let mut table = vec![(instruction(Run, 0, 0), noop)];
+    for size in 0..18 {
+        table.push((instruction(Add, size, 0), noop));
+    }
+    for mode in 0..9 {
+        table.push((instruction(Copy, 0, mode), noop));
+        for size in 4..19 {
+            table.push((instruction(Copy, size, mode), noop));
+        }
+    }
+    for mode in 0..6 {
+        for add_size in 1..5 {
+            for copy_size in 4..7 {
+                table.push((instruction(Add, add_size, 0), instruction(Copy, copy_size, mode)));
+            }
+        }
+    }
+    for mode in 6..9 {
+        for add_size in 1..5 {
+            table.push((instruction(Add, add_size, 0), instruction(Copy, 4, mode)));
+        }
+    }
+    for mode in 0..9 {
+        table.push((instruction(Copy, 4, mode), instruction(Add, 1, 0)));
+    }
+    table
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    // The sums can't overflow within this many bytes.
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    b << 16 | a
+}
+
+fn write_int(buf: &mut Vec<u8>, mut value: u64) {
+    let mut bytes = [0; 10];
+    let mut index = bytes.len();
+    loop {
+        index -= 1;
+        bytes[index] = (value & 0x7F) as u8;
+        if index != bytes.len() - 1 {
+            bytes[index] |= 0x80;
+        }
+        value >>= 7;
+        if value == 0 {
+            break;
+        }
+    }
+    buf.extend_from_slice(&bytes[index..]);
+}
+
+/// Writes the patch window by window, one VCDIFF window per window of the
+/// target.
+pub(super) struct Encoder<W> {
+    writer: W,
+}
+
+impl<W: Write> Encoder<W> {
+    pub(super) fn new(mut writer: W) -> io::Result<Self> {
+        writer.write_all(MAGIC)?;
+        // No secondary compression, code table or application header
+        writer.write_all(&[0])?;
+        Ok(Encoder { writer })
+    }
+
+    pub(super) fn encode_window(&mut self, window: &[u8], ops: &[Op]) -> io::Result<()> {
+        let mut buf = Vec::new();
+        encode_window(&mut buf, window, ops);
+        self.writer.write_all(&buf)
+    }
+}
+
+fn encode_window(buf: &mut Vec<u8>, window: &[u8], ops: &[Op]) {
+    // The source segment spans all the data that gets copied from the source.
+    let segment = ops
+        .iter()
+        .filter_map(|op| match *op {
+            Op::Copy { offset, len } => Some((offset, offset + len as u64)),
+            Op::Insert { .. } => None,
+        })
+        .fold(None, |segment, (start, end)| match segment {
+            Some((s, e)) => Some((cmp::min(s, start), cmp::max(e, end))),
+            None => Some((start, end)),
+        });
+    let segment_start = segment.map_or(0, |(start, _)| start);
+
+    let (mut data, mut instructions, mut addresses) = (Vec::new(), Vec::new(), Vec::new());
+    for op in ops {
+        match *op {
+            Op::Copy { offset, len } => {
+                instructions.push(CODE_COPY_SELF);
+                write_int(&mut instructions, len as u64);
+                write_int(&mut addresses, offset - segment_start);
+            }
+            Op::Insert { offset, len } => {
+                instructions.push(CODE_ADD);
+                write_int(&mut instructions, len as u64);
+                data.extend_from_slice(&window[offset..][..len]);
+            }
+        }
+    }
+
+    match segment {
+        Some((start, end)) => {
+            buf.push(VCD_SOURCE | VCD_ADLER32);
+            write_int(buf, end - start);
+            write_int(buf, start);
+        }
+        None => buf.push(VCD_ADLER32),
+    }
+
+    let mut delta = Vec::new();
+    write_int(&mut delta, window.len() as u64);
+    // No compressed sections
+    delta.push(0);
+    write_int(&mut delta, data.len() as u64);
+    write_int(&mut delta, instructions.len() as u64);
+    write_int(&mut delta, addresses.len() as u64);
+    let mut checksum = [0; 4];
+    BE::write_u32(&mut checksum, adler32(window));
+    delta.extend_from_slice(&checksum);
+    delta.extend_from_slice(&data);
+    delta.extend_from_slice(&instructions);
+    delta.extend_from_slice(&addresses);
+
+    write_int(buf, delta.len() as u64);
+    buf.extend_from_slice(&delta);
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        ensure!(len <= self.data.len(), "The patch ends unexpectedly");
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, Error> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn int(&mut self) -> Result<usize, Error> {
+        let mut value = 0usize;
+        loop {
+            let byte = self.byte()?;
+            ensure!(
+                value.leading_zeros() >= 7,
+                "The patch contains an invalid number"
+            );
+            value = value << 7 | (byte & 0x7F) as usize;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+    }
+}
+
+struct AddressCache {
+    near: [usize; NEAR_CACHE_SIZE],
+    next_slot: usize,
+    same: [usize; SAME_CACHE_SIZE * 256],
+}
+
+impl AddressCache {
+    fn new() -> Self {
+        AddressCache {
+            near: [0; NEAR_CACHE_SIZE],
+            next_slot: 0,
+            same: [0; SAME_CACHE_SIZE * 256],
+        }
+    }
+
+    fn decode(&mut self, addresses: &mut Reader, here: usize, mode: u8) -> Result<usize, Error> {
+        let mode = mode as usize;
+        let address = if mode == 0 {
+            Some(addresses.int()?)
+        } else if mode == 1 {
+            here.checked_sub(addresses.int()?)
+        } else if mode < 2 + NEAR_CACHE_SIZE {
+            self.near[mode - 2].checked_add(addresses.int()?)
+        } else {
+            Some(self.same[(mode - 2 - NEAR_CACHE_SIZE) * 256 + addresses.byte()? as usize])
+        };
+        let address = address.ok_or_else(|| err_msg("The patch contains an invalid address"))?;
+
+        self.near[self.next_slot] = address;
+        self.next_slot = (self.next_slot + 1) % NEAR_CACHE_SIZE;
+        self.same[address % (SAME_CACHE_SIZE * 256)] = address;
+
+        Ok(address)
+    }
+}
+
+/// The data that the copies of a window refer to before the window itself.
+enum Segment {
+    None,
+    Source { position: u64, len: usize },
+    Target(Vec<u8>),
+}
+
+impl Segment {
+    fn len(&self) -> usize {
+        match *self {
+            Segment::None => 0,
+            Segment::Source { len, .. } => len,
+            Segment::Target(ref data) => data.len(),
+        }
+    }
+}
+
+pub(super) fn decode<R, W>(
+    patch: &[u8],
+    source: &mut Source<R>,
+    output: &mut Output<W>,
+    verify: bool,
+) -> Result<(), Error>
+where
+    R: Read + Seek,
+    W: Read + Write + Seek,
+{
+    ensure!(patch.starts_with(MAGIC), "The file is not a VCDIFF patch");
+
+    let mut reader = Reader {
+        data: &patch[MAGIC.len()..],
+    };
+    let indicator = reader.byte()?;
+    ensure!(
+        indicator & VCD_DECOMPRESS == 0,
+        "Patches with secondary compression aren't supported. \
+         Create the patch with `xdelta3 -S none` instead."
+    );
+    ensure!(
+        indicator & VCD_CODETABLE == 0,
+        "Patches with custom code tables aren't supported"
+    );
+    if indicator & VCD_APPHEADER != 0 {
+        let len = reader.int()?;
+        reader.bytes(len)?;
+    }
+
+    let table = code_table();
+    while !reader.is_empty() {
+        decode_window(&mut reader, &table, source, output, verify)?;
+    }
+
+    Ok(())
+}
+
+fn decode_window<R, W>(
+    reader: &mut Reader,
+    table: &[(Instruction, Instruction)],
+    source: &mut Source<R>,
+    output: &mut Output<W>,
+    verify: bool,
+) -> Result<(), Error>
+where
+    R: Read + Seek,
+    W: Read + Write + Seek,
+{
+    let indicator = reader.byte()?;
+    let segment = if indicator & (VCD_SOURCE | VCD_TARGET) != 0 {
+        let len = reader.int()?;
+        let position = reader.int()? as u64;
+        let available = if indicator & VCD_SOURCE != 0 {
+            source.len
+        } else {
+            output.len()
+        };
+        ensure!(
+            position.checked_add(len as u64).map_or(false, |end| end <= available),
+            "The patch references data outside of the game"
+        );
+        if indicator & VCD_SOURCE != 0 {
+            Segment::Source { position, len }
+        } else {
+            let mut data = vec![0; len];
+            output.read_at(position, &mut data)?;
+            Segment::Target(data)
+        }
+    } else {
+        Segment::None
+    };
+
+    let _delta_len = reader.int()?;
+    let window_len = reader.int()?;
+    ensure!(
+        reader.byte()? == 0,
+        "Patches with compressed sections aren't supported"
+    );
+    let data_len = reader.int()?;
+    let instructions_len = reader.int()?;
+    let addresses_len = reader.int()?;
+    let checksum = if indicator & VCD_ADLER32 != 0 {
+        Some(BE::read_u32(reader.bytes(4)?))
+    } else {
+        None
+    };
+
+    let mut data = Reader {
+        data: reader.bytes(data_len)?,
+    };
+    let mut instructions = Reader {
+        data: reader.bytes(instructions_len)?,
+    };
+    let mut addresses = Reader {
+        data: reader.bytes(addresses_len)?,
+    };
+
+    let mut window = Vec::new();
+    let mut cache = AddressCache::new();
+
+    while !instructions.is_empty() {
+        let (first, second) = table[instructions.byte()? as usize];
+        for instruction in &[first, second] {
+            if instruction.kind == Kind::Noop {
+                continue;
+            }
+            let size = match instruction.size {
+                0 => instructions.int()?,
+                size => size as usize,
+            };
+            ensure!(
+                size <= window_len - window.len(),
+                "The patch writes past the end of a window"
+            );
+
+            match instruction.kind {
+                Kind::Add => window.extend_from_slice(data.bytes(size)?),
+                Kind::Run => {
+                    let byte = data.byte()?;
+                    let len = window.len() + size;
+                    window.resize(len, byte);
+                }
+                Kind::Copy => {
+                    let here = segment.len() + window.len();
+                    let address = cache.decode(&mut addresses, here, instruction.mode)?;
+                    ensure!(
+                        address < here,
+                        "The patch copies data that isn't written yet"
+                    );
+                    copy(source, &segment, &mut window, address, size)?;
+                }
+                Kind::Noop => {}
+            }
+        }
+    }
+
+    ensure!(
+        window.len() == window_len,
+        "A window of the patch is incomplete"
+    );
+    if let (true, Some(checksum)) = (verify, checksum) {
+        ensure!(
+            adler32(&window) == checksum,
+            "The patched game doesn't match the expected checksum. \
+             The patch may not be meant for this game."
+        );
+    }
+
+    output.push(&window)?;
+    Ok(())
+}
+
+/// Copies data from the segment, followed by the window itself, to the end of
+/// the window.
+fn copy<R: Read + Seek>(
+    source: &mut Source<R>,
+    segment: &Segment,
+    window: &mut Vec<u8>,
+    mut address: usize,
+    mut size: usize,
+) -> Result<(), Error> {
+    if address < segment.len() {
+        let len = cmp::min(size, segment.len() - address);
+        match *segment {
+            Segment::Source { position, .. } => {
+                source.copy(position + address as u64, len as u64, |data| {
+                    window.extend_from_slice(data);
+                    Ok(())
+                })?;
+            }
+            Segment::Target(ref data) => window.extend_from_slice(&data[address..][..len]),
+            Segment::None => unreachable!(),
+        }
+        address += len;
+        size -= len;
+        if size == 0 {
+            return Ok(());
+        }
+    }
+
+    // The copy may overlap with the data it writes.
+    let mut start = address - segment.len();
+    while size > 0 {
+        let len = cmp::min(size, window.len() - start);
+        let end = start + len;
+        for i in start..end {
+            let byte = window[i];
+            window.push(byte);
+        }
+        start = end;
+        size -= len;
+    }
+
+    Ok(())
+}
@@ -1,19 +1,129 @@
+use rustc_demangle::try_demangle;
 use std::borrow::Cow;
 use std::fmt::{self, Display};
 
-type IsConst = bool;
+pub type IsConst = bool;
 
 #[derive(Debug, PartialEq, Eq, Clone)]
-enum Type<'a> {
+pub enum Type<'a> {
     Path(IsConst, Vec<&'a str>),
     Normal(IsConst, &'a str),
     Function(IsConst, Box<Type<'a>>, Vec<Type<'a>>),
     Array(usize, Box<Type<'a>>),
     Pointer(IsConst, Box<Type<'a>>),
     Reference(IsConst, Box<Type<'a>>),
+    /// A pointer to a member of the class that is the first type.
+    MemberPointer(IsConst, Box<Type<'a>>, Box<Type<'a>>),
+    Volatile(Box<Type<'a>>),
     VarArgs,
 }
 
+/// The operators CodeWarrior encodes as the base name of a function, except
+/// for conversion operators, which are encoded as `__op` followed by the type.
+const OPERATORS: &[(&str, &str)] = &[
+    ("__nw", " new"),
+    ("__dl", " delete"),
+    ("__nwa", " new[]"),
+    ("__dla", " delete[]"),
+    ("__pl", "+"),
+    ("__mi", "-"),
+    ("__ml", "*"),
+    ("__dv", "/"),
+    ("__md", "%"),
+    ("__er", "^"),
+    ("__ad", "&"),
+    ("__or", "|"),
+    ("__co", "~"),
+    ("__nt", "!"),
+    ("__as", "="),
+    ("__lt", "<"),
+    ("__gt", ">"),
+    ("__apl", "+="),
+    ("__ami", "-="),
+    ("__amu", "*="),
+    ("__adv", "/="),
+    ("__amd", "%="),
+    ("__aer", "^="),
+    ("__aad", "&="),
+    ("__aor", "|="),
+    ("__ls", "<<"),
+    ("__rs", ">>"),
+    ("__als", "<<="),
+    ("__ars", ">>="),
+    ("__eq", "=="),
+    ("__ne", "!="),
+    ("__le", "<="),
+    ("__ge", ">="),
+    ("__aa", "&&"),
+    ("__oo", "||"),
+    ("__pp", "++"),
+    ("__mm", "--"),
+    ("__cm", ","),
+    ("__rm", "->*"),
+    ("__rf", "->"),
+    ("__cl", "()"),
+    ("__vc", "[]"),
+];
+
+/// Splits the arguments of a template at the commas that aren't part of a
+/// nested template.
+pub fn split_template_arguments(arguments: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let (mut depth, mut start) = (0i32, 0);
+    for (index, c) in arguments.char_indices() {
+        match c {
+            '<' => depth += 1,
+            '>' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(&arguments[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&arguments[start..]);
+    parts
+}
+
+/// A name that may have template arguments, which are mangled types or
+/// integer constants.
+struct Name<'a>(&'a str);
+
+impl<'a> Display for Name<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let &Name(name) = self;
+        let open = match name.find('<') {
+            Some(open) if name.ends_with('>') => open,
+            _ => return write!(f, "{}", name),
+        };
+
+        write!(f, "{}<", &name[..open])?;
+        let arguments = split_template_arguments(&name[open + 1..name.len() - 1]);
+        for (i, argument) in arguments.into_iter().enumerate() {
+            if i != 0 {
+                write!(f, ", ")?;
+            }
+            let is_integer = argument
+                .trim_left_matches('-')
+                .chars()
+                .all(|c| c.is_digit(10));
+            match parse_type(argument) {
+                Ok((Some(typ), "")) if !is_integer => write!(f, "{}", typ)?,
+                _ => write!(f, "{}", argument)?,
+            }
+        }
+        write!(f, ">")
+    }
+}
+
+/// Pointers to functions and arrays need parentheses around them.
+fn needs_parentheses(typ: &Type) -> bool {
+    match *typ {
+        Type::Function(..) | Type::Array(..) => true,
+        _ => false,
+    }
+}
+
 struct DisplayLeft<'a>(&'a Type<'a>);
 struct DisplayRight<'a>(&'a Type<'a>);
 
@@ -27,7 +137,7 @@ impl<'a> Display for DisplayLeft<'a> {
                     if i != 0 {
                         write!(f, "::")?;
                     }
-                    write!(f, "{}", element)?;
+                    write!(f, "{}", Name(element))?;
                 }
                 if is_const {
                     write!(f, " const")?;
@@ -35,28 +145,50 @@ impl<'a> Display for DisplayLeft<'a> {
                 Ok(())
             }
             Normal(is_const, t) => {
-                write!(f, "{}", t)?;
+                write!(f, "{}", Name(t))?;
                 if is_const {
                     write!(f, " const")?;
                 }
                 Ok(())
             }
-            Function(_, ref r, _) => write!(f, "{} (", DisplayLeft(r)),
-            Array(_, ref t) => write!(f, "{} (", DisplayLeft(t)),
+            Function(_, ref r, _) => write!(f, "{}", DisplayLeft(r)),
+            Array(_, ref t) => write!(f, "{}", DisplayLeft(t)),
             Pointer(is_const, ref t) => {
-                write!(f, "{}*", DisplayLeft(t))?;
+                write!(f, "{}", DisplayLeft(t))?;
+                if needs_parentheses(t) {
+                    write!(f, " (")?;
+                }
+                write!(f, "*")?;
                 if is_const {
                     write!(f, " const")?;
                 }
                 Ok(())
             }
             Reference(is_const, ref t) => {
-                write!(f, "{}&", DisplayLeft(t))?;
+                write!(f, "{}", DisplayLeft(t))?;
+                if needs_parentheses(t) {
+                    write!(f, " (")?;
+                }
+                write!(f, "&")?;
+                if is_const {
+                    write!(f, " const")?;
+                }
+                Ok(())
+            }
+            MemberPointer(is_const, ref class, ref t) => {
+                write!(f, "{}", DisplayLeft(t))?;
+                if needs_parentheses(t) {
+                    write!(f, " (")?;
+                } else {
+                    write!(f, " ")?;
+                }
+                write!(f, "{}::*", class)?;
                 if is_const {
                     write!(f, " const")?;
                 }
                 Ok(())
             }
+            Volatile(ref t) => write!(f, "{} volatile", DisplayLeft(t)),
             VarArgs => write!(f, "..."),
         }
     }
@@ -69,7 +201,7 @@ impl<'a> Display for DisplayRight<'a> {
         match *typ {
             Path(_, _) | Normal(_, _) | VarArgs => Ok(()),
             Function(is_const, ref r, ref p) => {
-                write!(f, ")(")?;
+                write!(f, "(")?;
 
                 for (i, param) in p.iter().enumerate() {
                     if i != 0 {
@@ -84,9 +216,14 @@ impl<'a> Display for DisplayRight<'a> {
                     write!(f, "){}", DisplayRight(r))
                 }
             }
-            Array(count, ref t) => write!(f, ")[{}]{}", count, DisplayRight(t)),
-            Pointer(_, ref t) => write!(f, "{}", DisplayRight(t)),
-            Reference(_, ref t) => write!(f, "{}", DisplayRight(t)),
+            Array(count, ref t) => write!(f, "[{}]{}", count, DisplayRight(t)),
+            Pointer(_, ref t) | Reference(_, ref t) | MemberPointer(_, _, ref t) => {
+                if needs_parentheses(t) {
+                    write!(f, ")")?;
+                }
+                write!(f, "{}", DisplayRight(t))
+            }
+            Volatile(ref t) => write!(f, "{}", DisplayRight(t)),
         }
     }
 }
@@ -126,12 +263,17 @@ fn parse_type(text: &str) -> Result<(Option<Type>, &str), Cow<'static, str>> {
         Some('x') => Type::Normal(false, "i64"),
         Some('f') => Type::Normal(false, "f32"),
         Some('d') => Type::Normal(false, "f64"),
+        Some('r') => Type::Normal(false, "long double"),
         Some('b') => Type::Normal(false, "bool"),
+        Some('w') => Type::Normal(false, "wchar_t"),
         Some('e') => Type::VarArgs,
         Some('0'...'9') => {
             let (count, remaining) = parse_count(original_text)?;
             text = remaining;
 
+            if count > text.len() || !text.is_char_boundary(count) {
+                return Err("Name is longer than the symbol".into());
+            }
             let type_name = &text[..count];
             text = &text[count..];
 
@@ -170,6 +312,9 @@ fn parse_type(text: &str) -> Result<(Option<Type>, &str), Cow<'static, str>> {
             let (count, remaining) = parse_count(text)?;
             text = remaining;
 
+            if !text.starts_with('_') {
+                return Err("Expected _ after the Array Length".into());
+            }
             text = &text[1..];
 
             let (typ, remaining) = parse_type(text)?;
@@ -192,8 +337,41 @@ fn parse_type(text: &str) -> Result<(Option<Type>, &str), Cow<'static, str>> {
 
             Type::Reference(false, Box::new(typ.ok_or("Expected Type of Reference")?))
         }
+        Some('M') => {
+            let (class, remaining) = parse_type(text)?;
+            text = remaining;
+            let class = match class.ok_or("Expected Class of Member Pointer")? {
+                class @ Type::Normal(..) | class @ Type::Path(..) => class,
+                t => return Err(format!("Unexpected Member Pointer Class {}", t).into()),
+            };
+
+            let (typ, remaining) = parse_type(text)?;
+            text = remaining;
+            let typ = match typ.ok_or("Expected Type of Member Pointer")? {
+                // Member functions take the object as a void pointer first,
+                // which is const for const member functions.
+                Type::Function(_, return_type, mut parameters) => {
+                    let is_const = match parameters.first() {
+                        Some(&Type::Pointer(_, ref this)) => match **this {
+                            Type::Normal(is_const, "void") => is_const,
+                            _ => return Err("Expected this Pointer of Member Function".into()),
+                        },
+                        _ => return Err("Expected this Pointer of Member Function".into()),
+                    };
+                    parameters.remove(0);
+                    Type::Function(is_const, return_type, parameters)
+                }
+                t => t,
+            };
+
+            Type::MemberPointer(false, Box::new(class), Box::new(typ))
+        }
         Some('Q') => {
-            let skip_index = text.char_indices().nth(1).unwrap().0;
+            let skip_index = text
+                .char_indices()
+                .nth(1)
+                .ok_or("Expected Path Length")?
+                .0;
             let (count, _) = parse_count(&text[..skip_index])?;
             text = &text[skip_index..];
 
@@ -229,16 +407,14 @@ fn parse_type(text: &str) -> Result<(Option<Type>, &str), Cow<'static, str>> {
         Some('C') => {
             let (typ, remaining) = parse_type(text)?;
             text = remaining;
-            let typ = typ.ok_or("Expected Constant Type")?;
-
-            match typ {
-                Type::Normal(_, name) => Type::Normal(true, name),
-                Type::Path(_, elements) => Type::Path(true, elements),
-                Type::Function(_, return_type, params) => Type::Function(true, return_type, params),
-                Type::Pointer(_, typ) => Type::Pointer(true, typ),
-                Type::Reference(_, typ) => Type::Reference(true, typ),
-                t => return Err(format!("Unexpected const type {}", t).into()),
-            }
+
+            into_const(typ.ok_or("Expected Constant Type")?)?
+        }
+        Some('V') => {
+            let (typ, remaining) = parse_type(text)?;
+            text = remaining;
+
+            Type::Volatile(Box::new(typ.ok_or("Expected Volatile Type")?))
         }
         Some('S') => return parse_type(text),
         Some(c) => return Err(format!("Unexpected token {}", c).into()),
@@ -248,108 +424,352 @@ fn parse_type(text: &str) -> Result<(Option<Type>, &str), Cow<'static, str>> {
     Ok((Some(typ), text))
 }
 
-fn base_name(function: &str) -> (&str, Option<&str>) {
-    if let Some(underscore_index) = function.rfind("__") {
-        (
-            &function[..underscore_index],
-            Some(&function[underscore_index + 2..]),
-        )
-    } else {
-        (function, None)
-    }
+fn into_const(typ: Type) -> Result<Type, Cow<'static, str>> {
+    Ok(match typ {
+        Type::Normal(_, name) => Type::Normal(true, name),
+        Type::Path(_, elements) => Type::Path(true, elements),
+        Type::Function(_, return_type, params) => Type::Function(true, return_type, params),
+        Type::Pointer(_, typ) => Type::Pointer(true, typ),
+        Type::Reference(_, typ) => Type::Reference(true, typ),
+        Type::MemberPointer(_, class, typ) => Type::MemberPointer(true, class, typ),
+        Type::Volatile(typ) => Type::Volatile(Box::new(into_const(*typ)?)),
+        t => return Err(format!("Unexpected const type {}", t).into()),
+    })
 }
 
-pub fn demangle(function: &str) -> Result<Cow<str>, Cow<'static, str>> {
-    fn extend_by_params(signature: &mut String, typ: Type) -> Result<(), Cow<'static, str>> {
-        if let Type::Function(is_const, return_value, params) = typ {
-            signature.push_str("(");
-            for (i, param) in params.iter().enumerate() {
-                if param == &Type::Normal(false, "void") {
-                    continue;
+/// The ways the symbol may be split into its base name and the mangled part
+/// after the `__`. The base name may contain `__` itself, so every `__` that is
+/// followed by a class or function type and isn't part of template arguments is
+/// a candidate.
+fn base_names(function: &str) -> Vec<(&str, &str)> {
+    let bytes = function.as_bytes();
+    let mut candidates = Vec::new();
+    let mut depth = 0i32;
+
+    // The leading underscores of special names like `__ct` are skipped.
+    for i in 1..bytes.len() {
+        match bytes[i] {
+            b'<' => depth += 1,
+            b'>' => depth -= 1,
+            b'_' if depth == 0 && bytes[i - 1] == b'_' && i >= 2 => {
+                let is_mangled = match bytes.get(i + 1).cloned() {
+                    Some(b'0'...b'9') | Some(b'Q') | Some(b'F') => true,
+                    _ => false,
+                };
+                if is_mangled {
+                    candidates.push((&function[..i - 1], &function[i + 1..]));
                 }
+            }
+            _ => {}
+        }
+    }
+
+    candidates
+}
+
+/// The name of the function the base name stands for, which may be an
+/// operator.
+fn function_name(base_name: &str) -> Result<String, Cow<'static, str>> {
+    if let Some(&(_, operator)) = OPERATORS.iter().find(|&&(name, _)| name == base_name) {
+        return Ok(format!("operator{}", operator));
+    }
+
+    if base_name.starts_with("__op") {
+        match parse_type(&base_name[4..])? {
+            (Some(typ), "") => return Ok(format!("operator {}", typ)),
+            _ => return Err(format!("Unexpected conversion operator {}", base_name).into()),
+        }
+    }
+
+    Ok(Name(base_name).to_string())
+}
+
+/// The name of a class without its template arguments, as it's used for the
+/// names of constructors and destructors.
+fn class_name(class: &str) -> &str {
+    &class[..class.find('<').unwrap_or(class.len())]
+}
+
+/// Parses the class path and the type of the mangled part of a symbol. The
+/// type is `None` for symbols that aren't functions, such as static members.
+fn parse_mangled(text: &str) -> Result<(Vec<&str>, Option<Type>), Cow<'static, str>> {
+    let (typ, remaining) = parse_type(text)?;
+    let (path, (typ, remaining)) = match typ.ok_or("Expected path")? {
+        Type::Path(_, path) => (path, parse_type(remaining)?),
+        Type::Normal(_, name) => (vec![name], parse_type(remaining)?),
+        t => (Vec::new(), (Some(t), remaining)),
+    };
+    if !remaining.is_empty() {
+        return Err(format!("Unexpected trailing characters {}", remaining).into());
+    }
+
+    Ok((path, typ))
+}
+
+/// The parts of a mangled function symbol.
+pub struct Signature<'a> {
+    /// The namespaces and classes the function is in.
+    pub path: Vec<&'a str>,
+    /// The name of the function, which is `__ct` for constructors and `__dt`
+    /// for destructors.
+    pub name: &'a str,
+    pub is_const: IsConst,
+    pub parameters: Vec<Type<'a>>,
+    pub return_type: Type<'a>,
+}
+
+/// Parses the signature of a mangled function symbol. Symbols that aren't
+/// mangled functions, such as mangled variables, are rejected.
+pub fn parse_signature(function: &str) -> Result<Signature, Cow<'static, str>> {
+    let mut error = Cow::from("The symbol isn't mangled");
+
+    for (name, text) in base_names(function) {
+        match parse_mangled(text) {
+            Ok((path, Some(Type::Function(is_const, return_type, parameters)))) => {
+                return Ok(Signature {
+                    path,
+                    name,
+                    is_const,
+                    parameters: parameters
+                        .into_iter()
+                        .filter(|p| p != &Type::Normal(false, "void"))
+                        .collect(),
+                    return_type: *return_type,
+                })
+            }
+            Ok(_) => error = "The symbol isn't a function".into(),
+            Err(e) => error = e,
+        }
+    }
+
+    Err(error)
+}
+
+fn demangle_parts(base_name: &str, text: &str) -> Result<String, Cow<'static, str>> {
+    let (path, typ) = parse_mangled(text)?;
+
+    let mut result = String::new();
+    for element in &path {
+        result.push_str(&Name(element).to_string());
+        result.push_str("::");
+    }
+
+    match (base_name, path.last()) {
+        ("__ct", Some(class)) => result.push_str(class_name(class)),
+        ("__dt", Some(class)) => {
+            result.push('~');
+            result.push_str(class_name(class));
+        }
+        _ => result.push_str(&function_name(base_name)?),
+    }
+
+    let (is_volatile, typ) = match typ {
+        Some(Type::Volatile(typ)) => (true, Some(*typ)),
+        typ => (false, typ),
+    };
+
+    match typ {
+        Some(Type::Function(is_const, return_value, params)) => {
+            result.push_str("(");
+            for (i, param) in params
+                .iter()
+                .filter(|&p| p != &Type::Normal(false, "void"))
+                .enumerate()
+            {
                 if i != 0 {
-                    signature.push_str(", ");
+                    result.push_str(", ");
                 }
-                signature.push_str(&param.to_string());
+                result.push_str(&param.to_string());
             }
-            signature.push_str(")");
+            result.push_str(")");
 
             if return_value.as_ref() != &Type::Normal(false, "void") {
-                signature.push_str(" -> ");
-                signature.push_str(&return_value.to_string());
+                result.push_str(" -> ");
+                result.push_str(&return_value.to_string());
             }
 
             if is_const {
-                signature.push_str(" const");
+                result.push_str(" const");
             }
-
-            Ok(())
-        } else {
-            Err(format!(
+            if is_volatile {
+                result.push_str(" volatile");
+            }
+        }
+        Some(typ) => {
+            return Err(format!(
                 "Unexpected Type {} for signature {}, expected Function",
-                typ, signature
+                typ, result
             ).into())
         }
+        None => {}
     }
 
-    fn extend_by_base_name(signature: &mut String, base_name: &str) {
-        if base_name == "__ct" {
-        } else if base_name == "__dt" {
-            *signature = format!("~{}", signature);
-        } else if !base_name.is_empty() {
-            if !signature.is_empty() {
-                signature.push_str("::");
-            }
-            signature.push_str(base_name);
+    Ok(result)
+}
+
+/// Demangles a symbol that CodeWarrior mangled. Symbols that aren't mangled
+/// are returned as they are.
+pub fn demangle(function: &str) -> Result<Cow<str>, Cow<'static, str>> {
+    let candidates = base_names(function);
+    if candidates.is_empty() {
+        return Ok(function.into());
+    }
+
+    let mut error = None;
+    for (base_name, text) in candidates {
+        match demangle_parts(base_name, text) {
+            Ok(demangled) => return Ok(demangled.into()),
+            Err(e) => error = error.or(Some(e)),
         }
     }
 
-    let (base_name, path) = base_name(function);
+    Err(error.unwrap())
+}
 
-    if base_name.is_empty() {
-        return Ok(function.into());
+/// Demangles a symbol that rustc mangled, with either the legacy or the v0
+/// scheme. The hashes are left out, so the name stays the same across builds.
+/// Section names, such as `.text._ZN...`, keep their prefix. Returns `None` for
+/// symbols that rustc didn't mangle.
+pub fn demangle_rust(name: &str) -> Option<String> {
+    if let Ok(demangled) = try_demangle(name) {
+        return Some(format!("{:#}", demangled));
     }
 
-    if let Some(mut text) = path {
-        let mut result = String::new();
+    for (index, _) in name.match_indices('.') {
+        if let Ok(demangled) = try_demangle(&name[index + 1..]) {
+            return Some(format!("{}{:#}", &name[..index + 1], demangled));
+        }
+    }
 
-        let (typ, remaining) = parse_type(text)?;
-        text = remaining;
+    None
+}
 
-        let typ = typ.ok_or("Expected path")?;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        match typ {
-            Type::Path(_, path) => {
-                for (i, element) in path.iter().enumerate() {
-                    if i != 0 {
-                        result.push_str("::");
-                    }
-                    result.push_str(element);
-                }
-                extend_by_base_name(&mut result, base_name);
+    fn assert_demangles(mangled: &str, demangled: &str) {
+        assert_eq!(demangle(mangled).unwrap(), demangled, "{}", mangled);
+    }
 
-                let (typ, _) = parse_type(text)?;
-                if let Some(typ) = typ {
-                    extend_by_params(&mut result, typ)?;
-                }
-            }
-            Type::Normal(_, name) => {
-                result.push_str(name);
-                extend_by_base_name(&mut result, base_name);
+    #[test]
+    fn functions() {
+        assert_demangles("OSReport__FPCce", "OSReport(i8 const*, ...)");
+        assert_demangles(
+            "getLayerNo__14dComIfG_play_cFi",
+            "dComIfG_play_c::getLayerNo(i32)",
+        );
+        assert_demangles("get__Q23Foo3BarFv", "Foo::Bar::get()");
+        assert_demangles("my__weird__3FooFv", "Foo::my__weird()");
+    }
 
-                let (typ, _) = parse_type(text)?;
-                if let Some(typ) = typ {
-                    extend_by_params(&mut result, typ)?;
-                }
-            }
-            t => {
-                extend_by_base_name(&mut result, base_name);
-                extend_by_params(&mut result, t)?;
-            }
-        }
+    #[test]
+    fn unmangled() {
+        assert_demangles("OSReport", "OSReport");
+        assert_demangles("__sinit_d_a_cpp", "__sinit_d_a_cpp");
+        assert_demangles("sInstance__7JKRHeap", "JKRHeap::sInstance");
+    }
+
+    #[test]
+    fn templates() {
+        assert_demangles(
+            "__ct__Q29JGeometry8TVec3<f>Fv",
+            "JGeometry::TVec3<f32>::TVec3()",
+        );
+        assert_demangles(
+            "set__Q29JGeometry8TVec3<f>Ffff",
+            "JGeometry::TVec3<f32>::set(f32, f32, f32)",
+        );
+        assert_demangles("func<i,3>__FPv", "func<i32, 3>(void*)");
+        assert_demangles(
+            "get__20TBox<Q23Foo3Bar,-12>Fv",
+            "TBox<Foo::Bar, -12>::get()",
+        );
+    }
+
+    #[test]
+    fn operators() {
+        assert_demangles("__ct__3FooFPi", "Foo::Foo(i32*)");
+        assert_demangles("__dt__Q23Foo3BarFv", "Foo::Bar::~Bar()");
+        assert_demangles("__as__3FooFRC3Foo", "Foo::operator=(Foo const&)");
+        assert_demangles("__eq__3FooCFRC3Foo", "Foo::operator==(Foo const&) const");
+        assert_demangles("__vc__3FooFi", "Foo::operator[](i32)");
+        assert_demangles("__cl__3FooFv", "Foo::operator()()");
+        assert_demangles("__nwa__FUl", "operator new[](u32)");
+        assert_demangles("__dl__FPv", "operator delete(void*)");
+        assert_demangles("__opb__3FooCFv", "Foo::operator bool() const");
+        assert_demangles("__opPCc__3FooCFv", "Foo::operator i8 const*() const");
+    }
+
+    #[test]
+    fn qualifiers() {
+        assert_demangles("get__3FooCFv", "Foo::get() const");
+        assert_demangles("get__3FooVFv", "Foo::get() volatile");
+        assert_demangles("get__3FooCVFv", "Foo::get() const volatile");
+        assert_demangles("read__FPVUi", "read(u32 volatile*)");
+        assert_demangles("write__FPCVUc", "write(u8 const volatile*)");
+    }
+
+    #[test]
+    fn member_pointers() {
+        assert_demangles("field__FM3Fooi", "field(i32 Foo::*)");
+        assert_demangles("call__FM3FooFPvi_v", "call(void (Foo::*)(i32))");
+        assert_demangles("call__FM3FooFPCvi_v", "call(void (Foo::*)(i32) const)");
+    }
+
+    #[test]
+    fn arrays_and_function_pointers() {
+        assert_demangles("arr__FPA3_A4_i", "arr(i32 (*)[3][4])");
+        assert_demangles("ref__FRA2_f", "ref(f32 (&)[2])");
+        assert_demangles("cb__FPFiPv_v", "cb(void (*)(i32, void*))");
+        assert_demangles("cb__FPFiPv_PFi_v", "cb(void (* (*)(i32, void*))(i32))");
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(demangle("broken__F99x").is_err());
+        assert!(demangle("arr__FA3i").is_err());
+    }
+
+    #[test]
+    fn rust() {
+        assert_eq!(
+            demangle_rust("_ZN4core3fmt5write17h0123456789abcdefE").unwrap(),
+            "core::fmt::write"
+        );
+        assert_eq!(
+            demangle_rust(".text._ZN4core3fmt5write17h0123456789abcdefE").unwrap(),
+            ".text.core::fmt::write"
+        );
+        assert_eq!(
+            demangle_rust("_ZN4core3fmt5writeE").unwrap(),
+            "core::fmt::write"
+        );
+        assert_eq!(
+            demangle_rust("_RNvNtCs4fqI2P2rA04_7mycrate3foo3bar").unwrap(),
+            "mycrate::foo::bar"
+        );
+        assert_eq!(
+            demangle_rust("_RINvNtCs4fqI2P2rA04_7mycrate3foo4swapINtB4_3BoxlEE").unwrap(),
+            "mycrate::foo::swap::<mycrate::Box<i32>>"
+        );
+        assert_eq!(
+            demangle_rust(".text._RNvNtCs4fqI2P2rA04_7mycrate3foo3bar").unwrap(),
+            ".text.mycrate::foo::bar"
+        );
+        assert_eq!(demangle_rust("OSReport"), None);
+        assert_eq!(demangle_rust(".text.init"), None);
+    }
 
-        Ok(result.into())
-    } else {
-        Ok(base_name.into())
+    #[test]
+    fn rust_stable_across_builds() {
+        assert_eq!(
+            demangle_rust("_ZN4core3fmt5write17h0123456789abcdefE"),
+            demangle_rust("_ZN4core3fmt5write17hfedcba9876543210E")
+        );
+        assert_eq!(
+            demangle_rust("_RNvNtCs4fqI2P2rA04_7mycrate3foo3bar"),
+            demangle_rust("_RNvNtCs1234_7mycrate3foo3bar")
+        );
     }
 }
@@ -0,0 +1,207 @@
+//! Files for the Dolphin emulator, so the Rom Hack can be tried out on the
+//! original game without building a new one. Dolphin applies the patches of a
+//! game settings file every frame and loads the symbols of `Maps/<ID>.map`
+//! into its debugger.
+
+use assembler::Instruction;
+use demangle::demangle_rust;
+use dol::DolFile;
+use framework_map::{GameSymbol, SymbolKind};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+/// Creates the lines of a Dolphin patch that writes the linked code and the
+/// instructions of the patch into the game. Dolphin applies these patches every
+/// frame, so the data sections of the Rom Hack aren't part of it, as that would
+/// reset its variables every frame. Only code that doesn't rely on initialized
+/// data can be tried out this way.
+pub fn create_patch(name: &str, linked: &DolFile, instructions: &[Instruction]) -> Vec<String> {
+    let mut patch = vec![format!("${}", name)];
+
+    for section in &linked.text_sections {
+        for (i, chunk) in section.data.chunks(4).enumerate() {
+            let word = chunk
+                .iter()
+                .chain(&[0; 4])
+                .take(4)
+                .fold(0u32, |word, &b| word << 8 | b as u32);
+            let address = section.address + 4 * i as u32;
+            patch.push(format!("0x{:08X}:dword:0x{:08X}", address, word));
+        }
+    }
+
+    for instruction in instructions {
+        patch.push(format!(
+            "0x{:08X}:dword:0x{:08X}",
+            instruction.address, instruction.data
+        ));
+    }
+
+    patch
+}
+
+/// Adds the patch to the contents of a game settings file and enables it. A
+/// previous version of the patch gets replaced, while everything else in the
+/// file is kept.
+pub fn add_patch(ini: &str, name: &str, patch: Vec<String>) -> String {
+    let mut sections = parse_ini(ini);
+    replace_entry(&mut sections, "OnFrame", name, patch);
+    replace_entry(&mut sections, "OnFrame_Enabled", name, vec![format!("${}", name)]);
+
+    let mut ini = String::new();
+    for (header, lines) in sections {
+        if let Some(header) = header {
+            writeln!(ini, "[{}]", header).unwrap();
+        }
+        for line in lines {
+            writeln!(ini, "{}", line).unwrap();
+        }
+    }
+    ini
+}
+
+/// Splits a game settings file into its sections. The lines before the first
+/// section don't have a header.
+fn parse_ini(ini: &str) -> Vec<(Option<String>, Vec<String>)> {
+    let mut sections = vec![(None, Vec::new())];
+    for line in ini.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') && trimmed.ends_with(']') {
+            let header = trimmed[1..trimmed.len() - 1].to_owned();
+            sections.push((Some(header), Vec::new()));
+        } else {
+            sections.last_mut().unwrap().1.push(line.to_owned());
+        }
+    }
+    sections
+}
+
+/// Replaces the entry with the name in the section, which is created if it
+/// doesn't exist yet. An entry starts with `$<name>` and reaches up to the next
+/// entry.
+fn replace_entry(
+    sections: &mut Vec<(Option<String>, Vec<String>)>,
+    header: &str,
+    name: &str,
+    entry: Vec<String>,
+) {
+    let position = sections
+        .iter()
+        .position(|&(ref h, _)| h.as_ref().map(|h| &h[..]) == Some(header));
+    let index = match position {
+        Some(index) => index,
+        None => {
+            sections.push((Some(header.to_owned()), Vec::new()));
+            sections.len() - 1
+        }
+    };
+    let lines = &mut sections[index].1;
+
+    let entry_name = format!("${}", name);
+    if let Some(start) = lines.iter().position(|l| l.trim() == entry_name) {
+        let mut end = lines[start + 1..]
+            .iter()
+            .position(|l| l.trim_left().starts_with('$'))
+            .map_or(lines.len(), |i| start + 1 + i);
+        while end > start + 1 && lines[end - 1].trim().is_empty() {
+            end -= 1;
+        }
+        lines.drain(start..end);
+    }
+
+    // Empty lines at the end of the section stay there.
+    let insert_at = lines
+        .iter()
+        .rposition(|l| !l.trim().is_empty())
+        .map_or(0, |i| i + 1);
+    lines.splice(insert_at..insert_at, entry);
+}
+
+struct Symbol {
+    address: u32,
+    size: Option<u32>,
+    kind: SymbolKind,
+    name: String,
+}
+
+/// Creates a symbol map in the format Dolphin saves its own maps in. It
+/// contains the symbols of the game's symbol map as well as the ones of the
+/// Rom Hack, demangled so the debugger shows readable names. Symbols without a
+/// known size are assumed to reach up to the next one or the end of their
+/// section. Symbols without a known kind are categorized by their section.
+pub fn create_map(
+    original: &DolFile,
+    original_symbols: &HashMap<String, GameSymbol>,
+    linked: &DolFile,
+    linked_symbols: &BTreeMap<&str, u32>,
+) -> String {
+    let sections = original
+        .text_sections
+        .iter()
+        .chain(&linked.text_sections)
+        .map(|s| (s.address, s.address + s.data.len() as u32, true))
+        .chain(
+            original
+                .data_sections
+                .iter()
+                .chain(&linked.data_sections)
+                .map(|s| (s.address, s.address + s.data.len() as u32, false)),
+        ).collect::<Vec<_>>();
+
+    let mut symbols = original_symbols
+        .iter()
+        .map(|(name, symbol)| Symbol {
+            address: symbol.address,
+            size: symbol.size,
+            kind: symbol.kind,
+            name: name.clone(),
+        }).chain(linked_symbols.iter().map(|(name, &address)| Symbol {
+            address,
+            size: None,
+            kind: SymbolKind::Unknown,
+            name: demangle_rust(name).unwrap_or_else(|| name.to_string()),
+        })).collect::<Vec<_>>();
+    symbols.sort_by(|a, b| a.address.cmp(&b.address).then(a.name.cmp(&b.name)));
+    symbols.dedup_by_key(|s| s.address);
+
+    let mut text = String::new();
+    let mut data = String::new();
+
+    for (i, symbol) in symbols.iter().enumerate() {
+        let section = sections
+            .iter()
+            .find(|&&(start, end, _)| start <= symbol.address && symbol.address < end);
+        let next = symbols.get(i + 1).map(|s| s.address);
+        let end = match (section, next) {
+            (Some(&(_, end, _)), Some(next)) => end.min(next),
+            (Some(&(_, end, _)), None) => end,
+            (None, Some(next)) => next,
+            (None, None) => symbol.address,
+        };
+        let size = symbol.size.unwrap_or(end - symbol.address);
+        let is_text = match symbol.kind {
+            SymbolKind::Function => true,
+            SymbolKind::Object => false,
+            SymbolKind::Unknown => section.map_or(false, |&(_, _, is_text)| is_text),
+        };
+        let out = if is_text {
+            &mut text
+        } else {
+            &mut data
+        };
+
+        writeln!(
+            out,
+            "{:08x} {:06x} {:08x} 0 {}",
+            symbol.address,
+            size,
+            symbol.address,
+            symbol.name
+        ).unwrap();
+    }
+
+    format!(
+        ".text section layout\n{}\n.data section layout\n{}",
+        text, data
+    )
+}
@@ -1,14 +1,46 @@
 use failure::{err_msg, Error};
+use glob::{self, MatchOptions, Pattern};
 use image::{self, DynamicImage};
 use std::fs;
 use std::io::prelude::*;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use zip::ZipArchive;
 
 pub trait FileSource {
     fn read_to_vec<P: AsRef<Path>>(&mut self, path: P) -> Result<Vec<u8>, Error>;
     fn read_to_string<P: AsRef<Path>>(&mut self, path: P) -> Result<String, Error>;
     fn open_image<P: AsRef<Path>>(&mut self, path: P) -> Result<DynamicImage, Error>;
+    /// Expands a path that is either a single file, a directory or a glob
+    /// pattern into all the files it refers to. Each file is returned with its
+    /// path relative to the directory or the base of the glob pattern. A single
+    /// file has an empty relative path.
+    fn expand<P: AsRef<Path>>(&mut self, path: P) -> Result<Vec<(PathBuf, PathBuf)>, Error>;
+}
+
+fn is_glob(path: &Path) -> bool {
+    path.to_string_lossy().contains(|c| c == '*' || c == '?' || c == '[')
+}
+
+/// The part of a glob pattern before the first component with a wildcard.
+fn glob_base(pattern: &Path) -> PathBuf {
+    pattern
+        .components()
+        .take_while(|c| !is_glob(Path::new(c.as_os_str())))
+        .collect()
+}
+
+fn walk_dir(base: &Path, dir: &Path, files: &mut Vec<(PathBuf, PathBuf)>) -> Result<(), Error> {
+    let mut entries = fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|e| e.path());
+    for entry in entries {
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            walk_dir(base, &path, files)?;
+        } else {
+            files.push((path.strip_prefix(base)?.to_owned(), path));
+        }
+    }
+    Ok(())
 }
 
 pub struct FileSystem;
@@ -23,6 +55,25 @@ impl FileSource for FileSystem {
     fn open_image<P: AsRef<Path>>(&mut self, path: P) -> Result<DynamicImage, Error> {
         Ok(image::open(path)?)
     }
+    fn expand<P: AsRef<Path>>(&mut self, path: P) -> Result<Vec<(PathBuf, PathBuf)>, Error> {
+        let path = path.as_ref();
+        let mut files = Vec::new();
+        if path.is_dir() {
+            walk_dir(path, path, &mut files)?;
+        } else if is_glob(path) {
+            let base = glob_base(path);
+            let pattern = path.to_str().ok_or_else(|| err_msg("Invalid path"))?;
+            for file in glob::glob(pattern)? {
+                let file = file?;
+                if file.is_file() {
+                    files.push((file.strip_prefix(&base)?.to_owned(), file));
+                }
+            }
+        } else {
+            files.push((PathBuf::new(), path.to_owned()));
+        }
+        Ok(files)
+    }
 }
 
 impl<R: Read + Seek> FileSource for ZipArchive<R> {
@@ -50,4 +101,32 @@ impl<R: Read + Seek> FileSource for ZipArchive<R> {
         let buf = self.read_to_vec(path)?;
         Ok(image::load_from_memory(&buf)?)
     }
+    fn expand<P: AsRef<Path>>(&mut self, path: P) -> Result<Vec<(PathBuf, PathBuf)>, Error> {
+        let path = path.as_ref();
+        let name = path.to_str().ok_or_else(|| err_msg("Invalid path"))?;
+        if self.by_name(name).is_ok() {
+            return Ok(vec![(PathBuf::new(), path.to_owned())]);
+        }
+
+        let (base, pattern) = if is_glob(path) {
+            (glob_base(path), Pattern::new(name)?)
+        } else {
+            (path.to_owned(), Pattern::new(&format!("{}/**/*", Pattern::escape(name)))?)
+        };
+        let options = MatchOptions {
+            require_literal_separator: true,
+            ..MatchOptions::new()
+        };
+
+        let mut files = Vec::new();
+        for index in 0..self.len() {
+            let file = self.by_index(index)?;
+            if !file.name().ends_with('/') && pattern.matches_with(file.name(), &options) {
+                let file = PathBuf::from(file.name());
+                files.push((file.strip_prefix(&base)?.to_owned(), file));
+            }
+        }
+        files.sort();
+        Ok(files)
+    }
 }
@@ -1,17 +1,130 @@
 use config::Config;
-use demangle::demangle as demangle_tww;
+use demangle::{demangle as demangle_tww, demangle_rust};
 use failure::{Error, ResultExt};
 use linker::{LinkedSection, SectionKind};
-use regex::{Captures, Regex};
-use rustc_demangle::demangle as demangle_rust;
-use std::collections::HashMap;
+use mangle::mangle;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
 use std::fs::File;
 use std::io::{prelude::*, BufWriter};
 use std::str;
 
+/// Whether a symbol of the game is code or data.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum SymbolKind {
+    Function,
+    Object,
+    /// The symbol map doesn't tell what kind of symbol it is.
+    Unknown,
+}
+
+impl SymbolKind {
+    fn of_section(section: &str) -> Self {
+        if section == ".init" || section.starts_with(".text") {
+            SymbolKind::Function
+        } else {
+            SymbolKind::Object
+        }
+    }
+}
+
+/// A symbol of the game, as far as the symbol map describes it.
+#[derive(Clone, Debug)]
+pub struct GameSymbol {
+    pub address: u32,
+    pub size: Option<u32>,
+    pub align: Option<u32>,
+    pub section: Option<String>,
+    pub kind: SymbolKind,
+    pub object_file: Option<String>,
+    /// The name as it's listed in the symbol map, if it got demangled.
+    pub mangled_name: Option<String>,
+}
+
+impl GameSymbol {
+    /// A symbol that nothing but its address is known of.
+    pub fn new(address: u32) -> Self {
+        GameSymbol {
+            address,
+            size: None,
+            align: None,
+            section: None,
+            kind: SymbolKind::Unknown,
+            object_file: None,
+            mangled_name: None,
+        }
+    }
+
+    /// The section the symbol is listed in. Symbols without a section are
+    /// assumed to be in `.text` or `.data`, depending on their kind.
+    pub fn section_name(&self) -> &str {
+        match (&self.section, self.kind) {
+            (Some(section), _) => section,
+            (None, SymbolKind::Object) => ".data",
+            (None, _) => ".text",
+        }
+    }
+}
+
+/// Looks up a symbol of the game by its name. The name may be either the
+/// CodeWarrior mangled name or the signature of the function, regardless of
+/// which of the two the symbol map lists.
+pub fn lookup<'a>(
+    symbols: &'a HashMap<String, GameSymbol>,
+    name: &str,
+) -> Option<&'a GameSymbol> {
+    if let Some(symbol) = symbols.get(name) {
+        return Some(symbol);
+    }
+
+    if let Ok(demangled) = demangle_tww(name) {
+        if let Some(symbol) = symbols.get(&*demangled) {
+            return Some(symbol);
+        }
+    }
+
+    // Mangling and demangling the signature again normalizes it to the way
+    // the demangled names of the symbol map are written.
+    let mangled = mangle(name).ok()?;
+    if let Some(symbol) = symbols.get(&mangled) {
+        return Some(symbol);
+    }
+    let demangled = demangle_tww(&mangled).ok()?;
+    symbols.get(&*demangled)
+}
+
+/// The section layout of the map that the linked section gets listed in.
+fn layout_of_section(section: &LinkedSection) -> &'static str {
+    match section.kind {
+        SectionKind::TextSection => ".text",
+        SectionKind::BlockStartedBySymbol => ".bss",
+        SectionKind::DataSection
+            if section.section_name.starts_with(".rodata")
+                || section.section_name.starts_with(".sdata2") =>
+        {
+            ".rodata"
+        }
+        SectionKind::DataSection => ".data",
+    }
+}
+
+/// A line of a section layout of the created map.
+struct Row<'a> {
+    address: u32,
+    size: u32,
+    align: u32,
+    name: Cow<'a, str>,
+    object_file: &'a str,
+}
+
+/// Creates a symbol map in the format of CodeWarrior that lists the sections
+/// and symbols of the Rom Hack together with the symbols of the game. Every
+/// section gets a single layout, which the rows of both are sorted into by
+/// their address. The symbols of the Rom Hack are listed with their demangled
+/// names, without the hash.
 pub fn create(
     config: &Config,
-    original: Option<&[u8]>,
+    original_symbols: &HashMap<String, GameSymbol>,
     sections: &[LinkedSection],
 ) -> Result<(), Error> {
     let path = match &config.build.map {
@@ -19,74 +132,649 @@ pub fn create(
         None => return Ok(()),
     };
 
+    let mut layouts = BTreeMap::new();
+
+    for section in sections {
+        let rows = layouts
+            .entry(layout_of_section(section))
+            .or_insert_with(Vec::new);
+        rows.push(Row {
+            address: section.address,
+            size: section.len,
+            align: section.align,
+            name: demangled_rust_name(section.section_name),
+            object_file: section.member_name,
+        });
+        for symbol in &section.symbols {
+            rows.push(Row {
+                address: symbol.address,
+                size: symbol.size,
+                align: section.align,
+                name: demangled_rust_name(symbol.name),
+                object_file: section.member_name,
+            });
+        }
+    }
+
+    // The symbols of the game are listed by the name in the original map,
+    // which is the mangled one if it got demangled.
+    for (name, symbol) in original_symbols {
+        layouts
+            .entry(symbol.section_name())
+            .or_insert_with(Vec::new)
+            .push(Row {
+                address: symbol.address,
+                size: symbol.size.unwrap_or(0),
+                align: symbol.align.unwrap_or(1),
+                name: Cow::Borrowed(symbol.mangled_name.as_ref().unwrap_or(name)),
+                object_file: symbol.object_file.as_ref().map_or("", |o| &o[..]),
+            });
+    }
+
+    let mut layouts = layouts.into_iter().collect::<Vec<_>>();
+    for &mut (_, ref mut rows) in &mut layouts {
+        // The sort is stable, so the sections of the Rom Hack stay in front of
+        // their first symbol.
+        rows.sort_by_key(|row| row.address);
+    }
+    layouts.sort_by_key(|&(_, ref rows)| rows[0].address);
+
     let mut file = BufWriter::new(File::create(path).context("Couldn't create the symbol map")?);
 
-    writeln!(file, ".text section layout")?;
+    for (i, (layout, rows)) in layouts.into_iter().enumerate() {
+        if i != 0 {
+            writeln!(file)?;
+            writeln!(file)?;
+        }
+        writeln!(file, "{} section layout", layout)?;
 
-    for section in sections {
-        let mut section_name_buf;
-        let section_name = section.section_name;
-        let section_name = if section_name.starts_with(".text.")
-            && section.kind == SectionKind::TextSection
-        {
-            section_name_buf = demangle_rust(&section_name[".text.".len()..]).to_string();
-            let mut section_name: &str = &section_name_buf;
-            if section_name.len() >= 19 && &section_name[section_name.len() - 19..][..3] == "::h" {
-                section_name = &section_name[..section_name.len() - 19];
+        let start = rows[0].address;
+        for row in rows {
+            writeln!(
+                file,
+                "  {:08x} {:06x} {:08x} {:2} {} \t{}",
+                row.address - start,
+                row.size,
+                row.address,
+                row.align,
+                row.name,
+                row.object_file
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+fn demangled_rust_name(name: &str) -> Cow<str> {
+    match demangle_rust(name) {
+        Some(demangled) => Cow::Owned(demangled),
+        None => Cow::Borrowed(name),
+    }
+}
+
+/// The symbols of a parsed symbol map.
+pub struct ParsedMap {
+    pub symbols: HashMap<String, GameSymbol>,
+    /// The amount of lines that are neither a symbol nor any other line the
+    /// dialect of the map is known to contain.
+    pub unparsed_lines: usize,
+}
+
+/// The different kinds of symbol maps that can be parsed.
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Dialect {
+    /// The maps CodeWarrior generates. Depending on its version, there may be
+    /// a file offset column and the alignment may be missing.
+    CodeWarrior,
+    /// The maps Dolphin saves, which look like CodeWarrior maps without the
+    /// object files.
+    Dolphin,
+    /// Symbol tables that IDA or Ghidra export as CSV. The columns of the
+    /// fields are determined by the header.
+    Csv {
+        name: usize,
+        address: usize,
+        /// The column of the size and its radix.
+        size: Option<(usize, u32)>,
+        kind: Option<usize>,
+        functions_only: bool,
+    },
+    /// Plain lists of an address followed by a name.
+    List,
+}
+
+enum Entry<'a> {
+    Symbol(&'a str, GameSymbol),
+    /// A line that is valid, but doesn't define a symbol.
+    Ignored,
+}
+
+/// Splits off the first whitespace separated token of the line.
+fn split_token(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim_left();
+    if line.is_empty() {
+        return None;
+    }
+    let end = line.find(char::is_whitespace).unwrap_or_else(|| line.len());
+    Some((&line[..end], &line[end..]))
+}
+
+fn parse_address(text: &str) -> Option<u32> {
+    let text = text.trim();
+    let text = if text.starts_with("0x") || text.starts_with("0X") {
+        &text[2..]
+    } else {
+        text
+    };
+    if text.is_empty() || text.len() > 8 {
+        return None;
+    }
+    u32::from_str_radix(text, 16).ok()
+}
+
+fn is_alignment(token: &str) -> bool {
+    token.len() <= 3 && token.bytes().all(|b| b.is_ascii_digit())
+}
+
+fn split_csv(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                fields.last_mut().unwrap().push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(String::new()),
+            c => fields.last_mut().unwrap().push(c),
+        }
+    }
+    fields
+}
+
+fn is_layout_header(line: &str) -> bool {
+    line.ends_with("section layout")
+}
+
+/// Whether the line is part of the call tree at the start of a CodeWarrior map,
+/// such as `Link map of __start`, `1] __start (func,global) found in os.a
+/// __start.o` or `>>> SYMBOL NOT FOUND: __fini`.
+fn is_link_map_line(line: &str) -> bool {
+    if line.starts_with("Link map of") || line.starts_with(">>>") {
+        return true;
+    }
+    let digits = line.bytes().take_while(u8::is_ascii_digit).count();
+    digits > 0 && line[digits..].starts_with(']')
+}
+
+fn detect(text: &str) -> Dialect {
+    let mut lines = text.lines().map(str::trim).filter(|l| !l.is_empty());
+
+    if let Some(header) = lines.next() {
+        if header.contains(',') {
+            let columns = split_csv(header)
+                .iter()
+                .map(|c| c.trim().to_lowercase())
+                .collect::<Vec<_>>();
+            let find = |names: &[&str]| columns.iter().position(|c| names.contains(&&c[..]));
+            let name = find(&["name", "function name", "symbol", "symbol name"]);
+            let address = find(&["location", "address", "start", "start address"]);
+            if let (Some(name), Some(address)) = (name, address) {
+                return Dialect::Csv {
+                    name,
+                    address,
+                    // IDA's lengths are hexadecimal, Ghidra's sizes decimal.
+                    size: find(&["length"])
+                        .map(|c| (c, 16))
+                        .or_else(|| find(&["size", "function size"]).map(|c| (c, 10))),
+                    kind: find(&["type", "symbol type"]),
+                    // IDA exports its list of functions.
+                    functions_only: columns[name] == "function name",
+                };
             }
-            section_name
+        }
+    }
+
+    if text.lines().any(|l| is_layout_header(l.trim())) {
+        // Dolphin doesn't indent its symbols, CodeWarrior does.
+        if text
+            .lines()
+            .any(|l| l.starts_with(' ') || l.starts_with('\t'))
+        {
+            Dialect::CodeWarrior
         } else {
-            section_name
-        };
+            Dialect::Dolphin
+        }
+    } else {
+        Dialect::List
+    }
+}
 
-        writeln!(
-            file,
-            "  00000000 {:06x} {:08x}  4 {} \t{}",
-            section.len - section.sym_offset,
-            section.address + section.sym_offset,
-            section_name,
-            section.member_name
-        )?;
+/// Parses a symbol of a section layout of a CodeWarrior map. The columns are
+/// the starting address, the size, the virtual address, optionally the file
+/// offset, optionally the alignment, the name and the object file.
+fn parse_code_warrior_symbol<'a>(line: &'a str, section: Option<&str>) -> Option<Entry<'a>> {
+    let (start, rest) = split_token(line)?;
+    let (size, rest) = split_token(rest)?;
+    let (address, rest) = split_token(rest)?;
+    if (start != "UNUSED" && parse_address(start).is_none()) || parse_address(size).is_none() {
+        return None;
     }
+    if address.starts_with("....") {
+        // Unused symbols that got stripped by the linker.
+        return Some(Entry::Ignored);
+    }
+    let address = parse_address(address)?;
 
-    if let Some(original) = original {
-        let regex = Regex::new(r"(\s{2}\d\s)(.*)(\s{2}.*)").unwrap();
+    // The text that starts with the name.
+    let mut text = rest;
+    let (mut name, mut rest) = split_token(text)?;
+    if name.len() == 8 && parse_address(name).is_some() && split_token(rest).is_some() {
+        text = rest;
+        let (token, after_offset) = split_token(text)?;
+        name = token;
+        rest = after_offset;
+    }
+    let mut align = None;
+    if is_alignment(name) {
+        align = name.parse().ok();
+        text = rest;
+        let (token, after_alignment) = split_token(text)?;
+        name = token;
+        rest = after_alignment;
+    }
+
+    // The object file is separated by a tab, so the name may contain spaces,
+    // like the demangled names in the maps that get created.
+    if let Some(tab) = text.find('\t') {
+        name = text[..tab].trim();
+        rest = &text[tab..];
+        if let Some(entry) = name.find(" (entry of") {
+            name = name[..entry].trim_right();
+        }
+    }
+    if name.is_empty() {
+        return None;
+    }
 
-        writeln!(file)?;
-        writeln!(file)?;
+    // Entry points of a section are marked with "(entry of .section)".
+    let object_file = rest.trim();
+    let object_file = if object_file.starts_with("(entry of") {
+        object_file.find(')').map_or("", |i| object_file[i + 1..].trim())
+    } else {
+        object_file
+    };
 
-        for line in str::from_utf8(original)?.lines() {
-            let line = regex.replace(&line, |c: &Captures| {
-                let demangled = demangle_tww(&c[2]);
-                format!("{}{}{}", &c[1], demangled.unwrap_or(c[2].into()), &c[3])
-            });
+    Some(Entry::Symbol(
+        name,
+        GameSymbol {
+            address,
+            size: parse_address(size),
+            align,
+            section: section.map(str::to_owned),
+            kind: section.map_or(SymbolKind::Unknown, SymbolKind::of_section),
+            object_file: if object_file.is_empty() {
+                None
+            } else {
+                Some(object_file.to_owned())
+            },
+            mangled_name: None,
+        },
+    ))
+}
 
-            writeln!(file, "{}", line)?;
+/// Parses a symbol of a Dolphin map. The columns are the address, the size,
+/// the virtual address, the alignment and the name, which may contain spaces.
+fn parse_dolphin_symbol<'a>(line: &'a str, section: Option<&str>) -> Option<Entry<'a>> {
+    let (_, rest) = split_token(line)?;
+    let (size, rest) = split_token(rest)?;
+    let (address, rest) = split_token(rest)?;
+    let size = parse_address(size)?;
+    let address = parse_address(address)?;
+
+    let mut name = rest.trim();
+    let mut align = None;
+    if let Some((alignment, rest)) = split_token(name) {
+        if is_alignment(alignment) && !rest.trim().is_empty() {
+            align = alignment.parse().ok();
+            name = rest.trim();
         }
     }
+    if name.is_empty() {
+        return None;
+    }
 
-    Ok(())
+    Some(Entry::Symbol(
+        name,
+        GameSymbol {
+            address,
+            size: Some(size),
+            // Dolphin writes 0 when the alignment isn't known.
+            align: align.and_then(|a| if a == 0 { None } else { Some(a) }),
+            section: section.map(str::to_owned),
+            kind: section.map_or(SymbolKind::Unknown, SymbolKind::of_section),
+            object_file: None,
+            mangled_name: None,
+        },
+    ))
 }
 
-pub fn parse(buf: &[u8]) -> Result<HashMap<String, u32>, Error> {
-    let mut symbols = HashMap::new();
-    let regex = Regex::new(r"\s{2}\w{8}\s\w{6}\s(\w{8}).{4}(.*)\s{2}").unwrap();
+fn parse_list_symbol(line: &str) -> Option<Entry> {
+    let (address, name) = split_token(line)?;
+    let address = parse_address(address)?;
+    let name = name.trim();
+    if name.is_empty() {
+        return None;
+    }
+    Some(Entry::Symbol(name, GameSymbol::new(address)))
+}
+
+fn parse_csv_symbol(
+    fields: &[String],
+    name: usize,
+    address: usize,
+    size: Option<(usize, u32)>,
+    kind: Option<usize>,
+    functions_only: bool,
+) -> Option<(&str, GameSymbol)> {
+    let name = fields.get(name)?.trim();
+    // Ghidra prefixes the address with the address space.
+    let address = fields.get(address)?.rsplit(':').next()?;
+    let address = parse_address(address)?;
+    if name.is_empty() {
+        return None;
+    }
+
+    let kind = match kind.and_then(|k| fields.get(k)) {
+        Some(kind) => {
+            let kind = kind.to_lowercase();
+            if kind.contains("func") {
+                SymbolKind::Function
+            } else if kind.contains("data") || kind.contains("object") {
+                SymbolKind::Object
+            } else {
+                SymbolKind::Unknown
+            }
+        }
+        None if functions_only => SymbolKind::Function,
+        None => SymbolKind::Unknown,
+    };
+
+    Some((
+        name,
+        GameSymbol {
+            address,
+            size: size.and_then(|(column, radix)| {
+                u32::from_str_radix(fields.get(column)?.trim(), radix).ok()
+            }),
+            align: None,
+            section: None,
+            kind,
+            object_file: None,
+            mangled_name: None,
+        },
+    ))
+}
+
+/// Section symbols only mark where the sections of the object files start, so
+/// they are skipped.
+fn insert(symbols: &mut HashMap<String, GameSymbol>, name: &str, mut symbol: GameSymbol) {
+    if !name.starts_with('.') {
+        let demangled = match demangle_tww(name) {
+            Ok(demangled) => demangled.into_owned(),
+            Err(_) => name.to_owned(),
+        };
+        if demangled != name {
+            symbol.mangled_name = Some(name.to_owned());
+        }
+        symbols.insert(demangled, symbol);
+    }
+}
+
+/// Parses a symbol map. The dialect of the map is detected automatically.
+/// Lines that can't be parsed are skipped and counted.
+pub fn parse(buf: &[u8]) -> Result<ParsedMap, Error> {
     let text = str::from_utf8(buf).context("The symbol map has invalid UTF-8")?;
-    for line in text.lines() {
-        if let Some(captures) = regex.captures(line) {
-            let name = captures.get(2).unwrap().as_str();
-            if !name.starts_with('.') {
-                let address = u32::from_str_radix(captures.get(1).unwrap().as_str(), 16)
-                    .context("Couldn't parse the address")?;
-
-                symbols.insert(
-                    demangle_tww(name)
-                        .map(|n| n.into_owned())
-                        .unwrap_or_else(|_| name.to_owned()),
-                    address,
-                );
+    let dialect = detect(text);
+
+    let mut symbols = HashMap::new();
+    let mut unparsed_lines = 0;
+    // Whether the line is in the memory map or the list of linker generated
+    // symbols at the end of a CodeWarrior map.
+    let (mut in_memory_map, mut in_linker_symbols) = (false, false);
+    // The section of the current section layout.
+    let mut section = None;
+    let mut lines = text.lines();
+
+    if let Dialect::Csv { .. } = dialect {
+        // Skip the header
+        lines.next();
+    }
+
+    for line in lines {
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            continue;
+        }
+
+        let entry = match dialect {
+            Dialect::CodeWarrior => {
+                if is_layout_header(trimmed) {
+                    in_memory_map = false;
+                    in_linker_symbols = false;
+                    section = trimmed.split_whitespace().next();
+                    Some(Entry::Ignored)
+                } else if trimmed == "Memory map:" {
+                    in_memory_map = true;
+                    Some(Entry::Ignored)
+                } else if trimmed == "Linker generated symbols:" {
+                    in_memory_map = false;
+                    in_linker_symbols = true;
+                    Some(Entry::Ignored)
+                } else if in_memory_map
+                    || is_link_map_line(trimmed)
+                    || trimmed.starts_with("Starting")
+                    || trimmed.starts_with("address")
+                    || trimmed.starts_with("---")
+                {
+                    Some(Entry::Ignored)
+                } else if in_linker_symbols {
+                    split_token(trimmed).and_then(|(name, address)| {
+                        parse_address(address).map(|a| Entry::Symbol(name, GameSymbol::new(a)))
+                    })
+                } else {
+                    parse_code_warrior_symbol(line, section)
+                }
+            }
+            Dialect::Dolphin => {
+                if is_layout_header(trimmed) {
+                    section = trimmed.split_whitespace().next();
+                    Some(Entry::Ignored)
+                } else {
+                    parse_dolphin_symbol(line, section)
+                }
+            }
+            Dialect::Csv {
+                name,
+                address,
+                size,
+                kind,
+                functions_only,
+            } => {
+                let fields = split_csv(line);
+                match parse_csv_symbol(&fields, name, address, size, kind, functions_only) {
+                    Some((name, symbol)) => {
+                        insert(&mut symbols, name, symbol);
+                        Some(Entry::Ignored)
+                    }
+                    None => None,
+                }
             }
+            Dialect::List => parse_list_symbol(line),
+        };
+
+        match entry {
+            Some(Entry::Symbol(name, symbol)) => insert(&mut symbols, name, symbol),
+            Some(Entry::Ignored) => {}
+            None => unparsed_lines += 1,
         }
     }
-    Ok(symbols)
+
+    Ok(ParsedMap {
+        symbols,
+        unparsed_lines,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_str(map: &str) -> ParsedMap {
+        let parsed = parse(map.as_bytes()).unwrap();
+        assert_eq!(parsed.unparsed_lines, 0);
+        parsed
+    }
+
+    #[test]
+    fn code_warrior() {
+        let map = "\
+Link map of __start
+ 1] __start (func,weak) found in os.a __start.o 
+  2] __init_registers (func,local) found in os.a __start.o 
+   3] _stack_addr found as linker generated symbol
+>>> SYMBOL NOT FOUND: __fini_cpp_exceptions
+  2] main (func,global) found in main.o 
+
+
+.init section layout
+  Starting        Virtual  File
+  address  Size   address  offset
+  ---------------------------------
+  00000000 000240 80003100 00000100  1 .init \tos.a __start.o
+  00000000 00000c 80003100 00000100  4 __start (entry of .init) \tos.a __start.o
+  UNUSED   000010 ........ ........    __unused os.a __start.o
+
+.text section layout
+  Starting        Virtual  File
+  address  Size   address  offset
+  ---------------------------------
+  00000000 000030 80006000 00003000  4 getLayerNo__14dComIfG_play_cFi \td_com_inf_game.o 
+  00000030 000010 80006030 00003030  4 main \tmain.o 
+
+.data section layout
+  Starting        Virtual
+  address  Size   address
+  -----------------------
+  00000000 000008 80300000 sInstance__7JKRHeap \tJKRHeap.o
+
+
+Memory map:
+                   Starting Size     File
+                   address           Offset
+           .init  80003100 00000240 00000100
+           .text  80006000 00000040 00003000
+
+Linker generated symbols:
+        _stack_addr 80400000
+";
+        let symbols = parse_str(map).symbols;
+        assert_eq!(symbols.len(), 5);
+
+        let start = &symbols["__start"];
+        assert_eq!(start.address, 0x8000_3100);
+        assert_eq!(start.size, Some(0xC));
+        assert_eq!(start.section.as_ref().unwrap(), ".init");
+        assert_eq!(start.object_file.as_ref().unwrap(), "os.a __start.o");
+
+        let get_layer_no = &symbols["dComIfG_play_c::getLayerNo(i32)"];
+        assert_eq!(get_layer_no.address, 0x8000_6000);
+        assert_eq!(get_layer_no.kind, SymbolKind::Function);
+        assert_eq!(
+            get_layer_no.mangled_name.as_ref().unwrap(),
+            "getLayerNo__14dComIfG_play_cFi"
+        );
+        assert_eq!(get_layer_no.object_file.as_ref().unwrap(), "d_com_inf_game.o");
+
+        let instance = &symbols["JKRHeap::sInstance"];
+        assert_eq!(instance.address, 0x8030_0000);
+        assert_eq!(instance.kind, SymbolKind::Object);
+
+        assert_eq!(symbols["main"].address, 0x8000_6030);
+        assert_eq!(symbols["_stack_addr"].address, 0x8040_0000);
+    }
+
+    #[test]
+    fn dolphin() {
+        let map = "\
+.text section layout
+80006000 000030 80006000 0 getLayerNo__14dComIfG_play_cFi
+80006030 000010 80006030 0 zz_80006030_
+
+.data section layout
+80300000 000008 80300000 0 some object
+";
+        let symbols = parse_str(map).symbols;
+        assert_eq!(symbols.len(), 3);
+        assert_eq!(
+            symbols["dComIfG_play_c::getLayerNo(i32)"].size,
+            Some(0x30)
+        );
+        assert_eq!(symbols["zz_80006030_"].kind, SymbolKind::Function);
+        let object = &symbols["some object"];
+        assert_eq!(object.address, 0x8030_0000);
+        assert_eq!(object.kind, SymbolKind::Object);
+    }
+
+    #[test]
+    fn ida_csv() {
+        let map = "\
+\"Function name\",\"Segment\",\"Start\",\"Length\",\"Locals\",\"Arguments\"
+\"main\",\".text\",80006030,00000010,00000008,00000000
+\"operator==\",\".text\",80006040,00000020,00000000,00000000
+";
+        let symbols = parse_str(map).symbols;
+        assert_eq!(symbols.len(), 2);
+        let main = &symbols["main"];
+        assert_eq!(main.address, 0x8000_6030);
+        assert_eq!(main.size, Some(0x10));
+        assert_eq!(main.kind, SymbolKind::Function);
+        assert_eq!(symbols["operator=="].size, Some(0x20));
+    }
+
+    #[test]
+    fn ghidra_csv() {
+        let map = "\
+\"Name\",\"Location\",\"Type\",\"Namespace\",\"Source\"
+\"main\",\"ram:80006030\",\"Function\",\"Global\",\"Imported\"
+\"gameInfo\",\"ram:80300000\",\"Data Label\",\"Global\",\"User Defined\"
+\"LAB_80006050\",\"ram:80006050\",\"Label\",\"Global\",\"Default\"
+";
+        let symbols = parse_str(map).symbols;
+        assert_eq!(symbols.len(), 3);
+        assert_eq!(symbols["main"].address, 0x8000_6030);
+        assert_eq!(symbols["main"].kind, SymbolKind::Function);
+        assert_eq!(symbols["gameInfo"].kind, SymbolKind::Object);
+        assert_eq!(symbols["LAB_80006050"].kind, SymbolKind::Unknown);
+    }
+
+    #[test]
+    fn list() {
+        let map = "\
+80006030 main
+0x80006000 getLayerNo__14dComIfG_play_cFi
+not a symbol
+";
+        let parsed = parse(map.as_bytes()).unwrap();
+        assert_eq!(parsed.unparsed_lines, 1);
+        assert_eq!(parsed.symbols.len(), 2);
+        assert_eq!(parsed.symbols["main"].address, 0x8000_6030);
+        assert_eq!(
+            parsed.symbols["dComIfG_play_c::getLayerNo(i32)"].address,
+            0x8000_6000
+        );
+    }
 }
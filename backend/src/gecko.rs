@@ -0,0 +1,134 @@
+//! Gecko codes allow running the Rom Hack through the cheat systems of Dolphin
+//! and Nintendont instead of a modified game. Based on
+//! http://www.geckocodes.org/index.php?arsenal=1
+
+use assembler::Instruction;
+use byteorder::{ByteOrder, BE};
+use dol::DolFile;
+
+const WRITE_32: u32 = 0x0400_0000;
+const WRITE_BLOCK: u32 = 0x0600_0000;
+const IF_NOT_EQUAL_32: u32 = 0x2200_0000;
+const INSERT_ASM: u32 = 0xC200_0000;
+const TERMINATOR: [u32; 2] = [0xE000_0000, 0x8000_8000];
+const ADDRESS_MASK: u32 = 0x01FF_FFFF;
+
+const GCT_HEADER: [u32; 2] = [0x00D0_C0DE, 0x00D0_C0DE];
+const GCT_FOOTER: [u32; 2] = [0xF000_0000, 0x0000_0000];
+
+const NOP: u32 = 0x6000_0000;
+
+fn to_words<'a>(data: &'a [u8]) -> impl Iterator<Item = u32> + 'a {
+    data.chunks(4).map(|chunk| {
+        chunk
+            .iter()
+            .chain(&[0; 4])
+            .take(4)
+            .fold(0, |word, &b| word << 8 | b as u32)
+    })
+}
+
+/// Decodes the destination of a `b` or `bl` instruction.
+fn branch_destination(instruction: &Instruction) -> Option<u32> {
+    let data = instruction.data;
+    if data >> 26 != 18 {
+        return None;
+    }
+    let offset = ((data & 0x03FF_FFFC) << 6) as i32 >> 6;
+    let is_absolute = data & 2 != 0;
+    Some(if is_absolute {
+        offset as u32
+    } else {
+        instruction.address.wrapping_add(offset as u32)
+    })
+}
+
+/// Creates the code lines that write the linked sections, apply the
+/// instructions of the patch and insert the hooks into the Rom Hack. The
+/// sections are only written once, so the state of the Rom Hack doesn't get
+/// reset every frame.
+pub fn create(linked: &DolFile, instructions: &[Instruction]) -> Vec<[u32; 2]> {
+    let mut lines = Vec::new();
+
+    let sections = linked
+        .text_sections
+        .iter()
+        .chain(&linked.data_sections)
+        .filter(|s| !s.data.is_empty())
+        .collect::<Vec<_>>();
+
+    if let Some(first) = sections.first() {
+        lines.push([
+            IF_NOT_EQUAL_32 | first.address & ADDRESS_MASK,
+            to_words(&first.data).next().unwrap_or(0),
+        ]);
+        for section in &sections {
+            lines.push([
+                WRITE_BLOCK | section.address & ADDRESS_MASK,
+                section.data.len() as u32,
+            ]);
+            let mut words = to_words(&section.data);
+            while let Some(first) = words.next() {
+                lines.push([first, words.next().unwrap_or(0)]);
+            }
+        }
+        lines.push(TERMINATOR);
+    }
+
+    let is_linked = |address: u32| {
+        sections
+            .iter()
+            .any(|s| s.address <= address && address < s.address + s.data.len() as u32)
+    };
+
+    for instruction in instructions {
+        match branch_destination(instruction) {
+            Some(destination) if is_linked(destination) => {
+                // Branches into the Rom Hack get inserted as hooks that call
+                // the Rom Hack through the count register.
+                let link = instruction.data & 1;
+                lines.push([INSERT_ASM | instruction.address & ADDRESS_MASK, 3]);
+                // lis r12, destination@h; ori r12, r12, destination@l
+                lines.push([
+                    0x3D80_0000 | destination >> 16,
+                    0x618C_0000 | destination & 0xFFFF,
+                ]);
+                // mtctr r12; bctr or bctrl
+                lines.push([0x7D89_03A6, 0x4E80_0420 | link]);
+                lines.push([NOP, 0]);
+            }
+            _ => lines.push([
+                WRITE_32 | instruction.address & ADDRESS_MASK,
+                instruction.data,
+            ]),
+        }
+    }
+
+    lines
+}
+
+/// Creates a GCT file, which Nintendont and the Gecko OS load the codes from.
+pub fn to_gct(lines: &[[u32; 2]]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(8 * (lines.len() + 2));
+    for line in Some(&GCT_HEADER)
+        .into_iter()
+        .chain(lines)
+        .chain(Some(&GCT_FOOTER))
+    {
+        let mut bytes = [0; 8];
+        BE::write_u32_into(line, &mut bytes);
+        buf.extend_from_slice(&bytes);
+    }
+    buf
+}
+
+/// Creates the sections of a Dolphin game settings file that add the codes
+/// and enable them.
+pub fn to_ini(name: &str, lines: &[[u32; 2]]) -> String {
+    let mut ini = format!("[Gecko]\n${}\n", name);
+    for line in lines {
+        ini.push_str(&format!("{:08X} {:08X}\n", line[0], line[1]));
+    }
+    ini.push_str(&format!("[Gecko_Enabled]\n${}\n", name));
+    ini
+}
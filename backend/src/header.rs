@@ -0,0 +1,115 @@
+//! Based on http://www.gc-forever.com/yagcd/chap13.html#sec13
+
+use byteorder::{ByteOrder, BE};
+use config::{Disc, Region};
+use encoding_rs::{SHIFT_JIS, UTF_8};
+use failure::Error;
+use iso::consts::HEADER_LENGTH;
+
+const OFFSET_GAME_ID: usize = 0x0;
+const OFFSET_MAKER_CODE: usize = 0x4;
+const OFFSET_DISC_NUMBER: usize = 0x6;
+const OFFSET_REVISION: usize = 0x7;
+const OFFSET_INTERNAL_NAME: usize = 0x20;
+const INTERNAL_NAME_LEN: usize = 0x3E0;
+
+const OFFSET_BI2: usize = 0x440;
+const OFFSET_DEBUG_MONITOR_SIZE: usize = OFFSET_BI2;
+const OFFSET_SIMULATED_MEMORY_SIZE: usize = OFFSET_BI2 + 0x4;
+const OFFSET_ARGUMENT_OFFSET: usize = OFFSET_BI2 + 0x8;
+const OFFSET_DEBUG_FLAG: usize = OFFSET_BI2 + 0xC;
+const OFFSET_TRACK_LOCATION: usize = OFFSET_BI2 + 0x10;
+const OFFSET_TRACK_SIZE: usize = OFFSET_BI2 + 0x14;
+const OFFSET_REGION: usize = OFFSET_BI2 + 0x18;
+
+fn write_code(
+    header: &mut [u8],
+    offset: usize,
+    code: &str,
+    len: usize,
+    kind: &str,
+) -> Result<(), Error> {
+    let is_valid = code
+        .bytes()
+        .all(|b| b.is_ascii_uppercase() || b.is_ascii_digit());
+    ensure!(
+        code.len() == len && is_valid,
+        "The {} \"{}\" needs to consist of {} upper case letters or digits",
+        kind,
+        code,
+        len
+    );
+    header[offset..][..len].copy_from_slice(code.as_bytes());
+    Ok(())
+}
+
+/// Applies the `[disc]` options to the disc header, which consists of the
+/// boot.bin followed by the bi2.bin.
+pub fn patch(header: &mut [u8], disc: &Disc) -> Result<(), Error> {
+    ensure!(header.len() >= HEADER_LENGTH, "The disc header is too small");
+
+    if let Some(game_id) = &disc.game_id {
+        // The maker code may directly be specified as part of the game ID.
+        if game_id.len() == 6 {
+            write_code(header, OFFSET_GAME_ID, game_id, 6, "game ID")?;
+        } else {
+            write_code(header, OFFSET_GAME_ID, game_id, 4, "game ID")?;
+        }
+    }
+    if let Some(maker_code) = &disc.maker_code {
+        write_code(header, OFFSET_MAKER_CODE, maker_code, 2, "maker code")?;
+    }
+    if let Some(disc_number) = disc.disc_number {
+        header[OFFSET_DISC_NUMBER] = disc_number;
+    }
+    if let Some(revision) = disc.revision {
+        header[OFFSET_REVISION] = revision;
+    }
+
+    let bi2 = &disc.bi2;
+    let fields = [
+        (OFFSET_DEBUG_MONITOR_SIZE, bi2.debug_monitor_size),
+        (OFFSET_SIMULATED_MEMORY_SIZE, bi2.simulated_memory_size),
+        (OFFSET_ARGUMENT_OFFSET, bi2.argument_offset),
+        (OFFSET_DEBUG_FLAG, bi2.debug_flag),
+        (OFFSET_TRACK_LOCATION, bi2.track_location),
+        (OFFSET_TRACK_SIZE, bi2.track_size),
+        (OFFSET_REGION, bi2.region.map(|r| r as u32)),
+    ];
+    for &(offset, value) in &fields {
+        if let Some(value) = value {
+            BE::write_u32(&mut header[offset..], value);
+        }
+    }
+
+    if let Some(internal_name) = &disc.internal_name {
+        let is_japanese = BE::read_u32(&header[OFFSET_REGION..]) == Region::Japan as u32;
+        let encoding = if is_japanese { SHIFT_JIS } else { UTF_8 };
+        let (encoded, _, had_errors) = encoding.encode(internal_name);
+        ensure!(
+            !had_errors,
+            "The internal name \"{}\" can't be encoded for the disc's region",
+            internal_name
+        );
+        ensure!(
+            encoded.len() < INTERNAL_NAME_LEN,
+            "The internal name \"{}\" is too long",
+            internal_name
+        );
+
+        let name = &mut header[OFFSET_INTERNAL_NAME..][..INTERNAL_NAME_LEN];
+        for b in name.iter_mut() {
+            *b = 0;
+        }
+        name[..encoded.len()].copy_from_slice(&encoded);
+    }
+
+    Ok(())
+}
+
+/// Reads the game ID, including the maker code, and the revision of the game.
+pub fn identify(header: &[u8]) -> Result<(String, u8), Error> {
+    ensure!(header.len() >= HEADER_LENGTH, "The disc header is too small");
+    let game_id = String::from_utf8_lossy(&header[OFFSET_GAME_ID..][..6]).into_owned();
+    Ok((game_id, header[OFFSET_REVISION]))
+}
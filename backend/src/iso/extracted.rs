@@ -0,0 +1,207 @@
+//! Reads and writes the extracted disc layout used by Dolphin:
+//!
+//! ```text
+//! sys/boot.bin
+//! sys/bi2.bin
+//! sys/apploader.img
+//! sys/main.dol
+//! sys/fst.bin
+//! files/...
+//! ```
+
+use super::consts::*;
+use super::virtual_file_system::{Directory, File, FileData, Node};
+use byteorder::{ByteOrder, BE};
+use failure::{err_msg, Error, ResultExt};
+use std::borrow::Cow;
+use std::cmp;
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+const BOOT_LENGTH: usize = 0x440;
+
+pub fn export_to_disk<P: AsRef<Path>>(root: &Directory, path: P) -> Result<(), Error> {
+    let path = path.as_ref();
+
+    let sys_dir = root
+        .children
+        .iter()
+        .filter_map(|c| c.as_directory())
+        .find(|d| d.name == "&&systemdata")
+        .ok_or_else(|| err_msg("The virtual file system contains no &&systemdata folder"))?;
+
+    let sys_path = path.join("sys");
+    fs::create_dir_all(&sys_path).context("Couldn't create the sys folder")?;
+
+    let header = find_file(sys_dir, "iso.hdr")?.data.load()?;
+    ensure!(
+        header.len() == HEADER_LENGTH,
+        "The iso.hdr has an invalid size"
+    );
+    fs::write(sys_path.join("boot.bin"), &header[..BOOT_LENGTH])
+        .context("Couldn't write the boot.bin")?;
+    fs::write(sys_path.join("bi2.bin"), &header[BOOT_LENGTH..])
+        .context("Couldn't write the bi2.bin")?;
+
+    let apploader = find_file(sys_dir, "AppLoader.ldr")?.data.load()?;
+    fs::write(
+        sys_path.join("apploader.img"),
+        &apploader[..apploader_len(&apploader)],
+    ).context("Couldn't write the apploader.img")?;
+
+    let dol = sys_dir
+        .children
+        .iter()
+        .filter_map(|c| c.as_file())
+        .find(|f| f.name.ends_with(".dol"))
+        .ok_or_else(|| err_msg("The &&systemdata folder contains no dol file"))?
+        .data
+        .load()?;
+    fs::write(sys_path.join("main.dol"), &dol[..dol_len(&dol)])
+        .context("Couldn't write the main.dol")?;
+
+    if let Ok(fst) = find_file(sys_dir, "Game.toc") {
+        fs::write(sys_path.join("fst.bin"), &fst.data.load()?)
+            .context("Couldn't write the fst.bin")?;
+    }
+
+    let files_path = path.join("files");
+    fs::create_dir_all(&files_path).context("Couldn't create the files folder")?;
+
+    for child in &root.children {
+        if child.as_directory().map_or(false, |d| d.name == "&&systemdata") {
+            continue;
+        }
+        export_node(child, &files_path)?;
+    }
+
+    Ok(())
+}
+
+fn export_node(node: &Node, path: &Path) -> Result<(), Error> {
+    match *node {
+        Node::Directory(ref dir) => {
+            let path = path.join(&*dir.name);
+            fs::create_dir_all(&path)
+                .with_context(|_| format!("Couldn't create the folder \"{}\"", path.display()))?;
+            for child in &dir.children {
+                export_node(child, &path)?;
+            }
+        }
+        Node::File(ref file) => {
+            let path = path.join(&*file.name);
+            let mut writer = BufWriter::new(
+                fs::File::create(&path)
+                    .with_context(|_| format!("Couldn't create the file \"{}\"", path.display()))?,
+            );
+            file.data
+                .write_to(&mut writer)
+                .and_then(|_| writer.flush())
+                .with_context(|_| format!("Couldn't write the file \"{}\"", path.display()))?;
+        }
+    }
+    Ok(())
+}
+
+pub fn import_from_disk<'a, P: AsRef<Path>>(path: P) -> Result<Directory<'a>, Error> {
+    let path = path.as_ref();
+    let sys_path = path.join("sys");
+
+    let mut header = fs::read(sys_path.join("boot.bin")).context("Couldn't read the boot.bin")?;
+    header.extend(fs::read(sys_path.join("bi2.bin")).context("Couldn't read the bi2.bin")?);
+    ensure!(
+        header.len() == HEADER_LENGTH,
+        "The boot.bin and bi2.bin need to be {:#x} bytes in total",
+        HEADER_LENGTH
+    );
+
+    let mut sys_data = Directory::new("&&systemdata");
+    sys_data
+        .children
+        .push(Node::File(File::new("iso.hdr", header)));
+    sys_data.children.push(Node::File(File::new(
+        "AppLoader.ldr",
+        fs::read(sys_path.join("apploader.img")).context("Couldn't read the apploader.img")?,
+    )));
+    sys_data.children.push(Node::File(File::new(
+        "Start.dol",
+        fs::read(sys_path.join("main.dol")).context("Couldn't read the main.dol")?,
+    )));
+    if let Ok(fst) = fs::read(sys_path.join("fst.bin")) {
+        sys_data
+            .children
+            .push(Node::File(File::new("Game.toc", fst)));
+    }
+
+    let mut root_dir = import_dir("root", &path.join("files"))?;
+    root_dir
+        .children
+        .insert(0, Node::Directory(Box::new(sys_data)));
+
+    Ok(root_dir)
+}
+
+fn import_dir<'a, N: Into<Cow<'a, str>>>(name: N, path: &Path) -> Result<Directory<'a>, Error> {
+    let mut dir = Directory::new(name);
+
+    let mut entries = fs::read_dir(path)
+        .with_context(|_| format!("Couldn't list the folder \"{}\"", path.display()))?
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|_| format!("Couldn't list the folder \"{}\"", path.display()))?;
+
+    // Dolphin sorts the entries case insensitively, so the FST ends up in a
+    // stable order regardless of the host file system.
+    entries.sort_by_key(|e| e.file_name().to_string_lossy().to_lowercase());
+
+    for entry in entries {
+        let name = entry
+            .file_name()
+            .into_string()
+            .map_err(|n| format_err!("The file name {:?} is not valid UTF-8", n))?;
+        let path = entry.path();
+
+        if entry.file_type()?.is_dir() {
+            let child = import_dir(name, &path)?;
+            dir.children.push(Node::Directory(Box::new(child)));
+        } else {
+            let len = entry.metadata()?.len() as usize;
+            dir.children
+                .push(Node::File(File::new(name, FileData::Path { path, len })));
+        }
+    }
+
+    Ok(dir)
+}
+
+fn find_file<'b, 'a>(dir: &'b Directory<'a>, name: &str) -> Result<&'b File<'a>, Error> {
+    dir.children
+        .iter()
+        .filter_map(|c| c.as_file())
+        .find(|f| f.name == name)
+        .ok_or_else(|| format_err!("The &&systemdata folder contains no {}", name))
+}
+
+fn apploader_len(data: &[u8]) -> usize {
+    if data.len() < 0x20 {
+        return data.len();
+    }
+    let len = 0x20 + BE::read_u32(&data[0x14..]) as usize + BE::read_u32(&data[0x18..]) as usize;
+    cmp::min(len, data.len())
+}
+
+/// The length of the DOL according to its section table, without the padding
+/// that follows it on the disc.
+pub fn dol_len(data: &[u8]) -> usize {
+    if data.len() < 0x100 {
+        return data.len();
+    }
+    let len = (0..18)
+        .map(|i| {
+            let offset = BE::read_u32(&data[4 * i..]) as usize;
+            let size = BE::read_u32(&data[0x90 + 4 * i..]) as usize;
+            offset + size
+        }).max()
+        .unwrap_or(0);
+    cmp::min(cmp::max(len, 0x100), data.len())
+}
@@ -0,0 +1,199 @@
+//! CISO images store a map of which blocks of the disc are used, followed by
+//! only the used blocks. Based on Dolphin's CISOBlob.
+
+use byteorder::{ByteOrder, LE};
+use failure::Error;
+use std::cmp;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+pub const MAGIC: &[u8; 4] = b"CISO";
+const HEADER_SIZE: usize = 0x8000;
+const MAP_SIZE: usize = HEADER_SIZE - 8;
+const DEFAULT_BLOCK_SIZE: usize = 0x20_0000;
+
+pub struct CisoReader<R> {
+    reader: R,
+    block_size: u64,
+    block_offsets: Vec<Option<u64>>,
+    pos: u64,
+}
+
+impl<R: Read + Seek> CisoReader<R> {
+    pub fn new(mut reader: R) -> Result<Self, Error> {
+        let mut header = vec![0; HEADER_SIZE];
+        reader.seek(SeekFrom::Start(0))?;
+        reader.read_exact(&mut header)?;
+
+        ensure!(&header[..4] == MAGIC, "The file is not a CISO image");
+
+        let block_size = LE::read_u32(&header[4..]) as u64;
+        ensure!(block_size != 0, "The CISO image has an invalid block size");
+
+        let map = &header[8..];
+        let used_blocks = map.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+
+        let mut offset = HEADER_SIZE as u64;
+        let block_offsets = map[..used_blocks]
+            .iter()
+            .map(|&used| {
+                if used != 0 {
+                    let block_offset = offset;
+                    offset += block_size;
+                    Some(block_offset)
+                } else {
+                    None
+                }
+            }).collect();
+
+        Ok(Self {
+            reader,
+            block_size,
+            block_offsets,
+            pos: 0,
+        })
+    }
+
+    fn len(&self) -> u64 {
+        self.block_offsets.len() as u64 * self.block_size
+    }
+}
+
+impl<R: Read + Seek> Read for CisoReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let block = (self.pos / self.block_size) as usize;
+        let in_block = self.pos % self.block_size;
+        let len = cmp::min(buf.len() as u64, self.block_size - in_block) as usize;
+        let buf = &mut buf[..len];
+
+        let read = match self.block_offsets.get(block) {
+            Some(&Some(offset)) => {
+                self.reader.seek(SeekFrom::Start(offset + in_block))?;
+                self.reader.read(buf)?
+            }
+            Some(&None) => {
+                for b in buf.iter_mut() {
+                    *b = 0;
+                }
+                len
+            }
+            None => 0,
+        };
+
+        self.pos += read as u64;
+        Ok(read)
+    }
+}
+
+impl<R: Read + Seek> Seek for CisoReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let len = self.len();
+        self.pos = super::seek_position(self.pos, len, pos)?;
+        Ok(self.pos)
+    }
+}
+
+/// Writes a CISO image. Blocks that only consist of zeros are left out.
+pub struct CisoWriter<W: Write + Seek> {
+    writer: W,
+    block: Vec<u8>,
+    map: Vec<u8>,
+}
+
+impl<W: Write + Seek> CisoWriter<W> {
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        writer.write_all(&[0; HEADER_SIZE])?;
+        Ok(Self {
+            writer,
+            block: Vec::with_capacity(DEFAULT_BLOCK_SIZE),
+            map: Vec::new(),
+        })
+    }
+
+    fn flush_block(&mut self) -> io::Result<()> {
+        if self.map.len() >= MAP_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "The disc is too large for a CISO image",
+            ));
+        }
+        if self.block.iter().all(|&b| b == 0) {
+            self.map.push(0);
+        } else {
+            self.block.resize(DEFAULT_BLOCK_SIZE, 0);
+            self.writer.write_all(&self.block)?;
+            self.map.push(1);
+        }
+        self.block.clear();
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        if !self.block.is_empty() {
+            self.flush_block()?;
+        }
+
+        let mut header = vec![0; HEADER_SIZE];
+        header[..4].copy_from_slice(MAGIC);
+        LE::write_u32(&mut header[4..], DEFAULT_BLOCK_SIZE as u32);
+        header[8..][..self.map.len()].copy_from_slice(&self.map);
+
+        self.writer.seek(SeekFrom::Start(0))?;
+        self.writer.write_all(&header)?;
+        self.writer.flush()?;
+
+        Ok(self.writer)
+    }
+}
+
+impl<W: Write + Seek> Write for CisoWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = cmp::min(buf.len(), DEFAULT_BLOCK_SIZE - self.block.len());
+        self.block.extend_from_slice(&buf[..len]);
+        if self.block.len() == DEFAULT_BLOCK_SIZE {
+            self.flush_block()?;
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn round_trip() {
+        // Two and a half blocks, where the block in the middle is empty and
+        // gets left out of the image.
+        let mut image = vec![0; 5 * DEFAULT_BLOCK_SIZE / 2];
+        for (i, b) in image[..DEFAULT_BLOCK_SIZE].iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        for (i, b) in image[2 * DEFAULT_BLOCK_SIZE..].iter_mut().enumerate() {
+            *b = (i / 3) as u8 | 1;
+        }
+
+        let mut writer = CisoWriter::new(Cursor::new(Vec::new())).unwrap();
+        writer.write_all(&image).unwrap();
+        let ciso = writer.finish().unwrap().into_inner();
+        assert_eq!(ciso.len(), HEADER_SIZE + 2 * DEFAULT_BLOCK_SIZE);
+
+        let mut reader = CisoReader::new(Cursor::new(ciso)).unwrap();
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).unwrap();
+        // The last block is padded to the full block size.
+        assert_eq!(read.len(), 3 * DEFAULT_BLOCK_SIZE);
+        assert!(read[..image.len()] == image[..]);
+        assert!(read[image.len()..].iter().all(|&b| b == 0));
+
+        let offset = 2 * DEFAULT_BLOCK_SIZE + 5;
+        reader.seek(SeekFrom::Start(offset as u64)).unwrap();
+        let mut buf = [0; 4];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, image[offset..][..4]);
+    }
+}
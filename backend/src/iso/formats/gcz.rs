@@ -0,0 +1,205 @@
+//! GCZ images consist of zlib compressed blocks of the disc. Based on
+//! Dolphin's CompressedBlob.
+
+use byteorder::{ByteOrder, LE};
+use failure::{Error, ResultExt};
+use flate2::read::ZlibDecoder;
+use std::cmp;
+use std::io::{self, Read, Seek, SeekFrom};
+
+pub const MAGIC: u32 = 0xB10B_C001;
+const HEADER_SIZE: usize = 0x20;
+const UNCOMPRESSED_FLAG: u64 = 1 << 63;
+
+pub struct GczReader<R> {
+    reader: R,
+    data_offset: u64,
+    compressed_data_size: u64,
+    data_size: u64,
+    block_size: u64,
+    block_pointers: Vec<u64>,
+    cached_block: Option<usize>,
+    block_buf: Vec<u8>,
+    pos: u64,
+}
+
+impl<R: Read + Seek> GczReader<R> {
+    pub fn new(mut reader: R) -> Result<Self, Error> {
+        let mut header = [0; HEADER_SIZE];
+        reader.seek(SeekFrom::Start(0))?;
+        reader.read_exact(&mut header)?;
+
+        ensure!(
+            LE::read_u32(&header) == MAGIC,
+            "The file is not a GCZ image"
+        );
+
+        let compressed_data_size = LE::read_u64(&header[8..]);
+        let data_size = LE::read_u64(&header[16..]);
+        let block_size = LE::read_u32(&header[24..]) as u64;
+        let num_blocks = LE::read_u32(&header[28..]) as usize;
+
+        ensure!(block_size != 0, "The GCZ image has an invalid block size");
+
+        let mut pointers = vec![0; 8 * num_blocks];
+        reader
+            .read_exact(&mut pointers)
+            .context("Couldn't read the GCZ block pointers")?;
+        let block_pointers = pointers.chunks(8).map(LE::read_u64).collect();
+
+        Ok(Self {
+            reader,
+            // The block pointers are followed by a hash for each block.
+            data_offset: (HEADER_SIZE + 12 * num_blocks) as u64,
+            compressed_data_size,
+            data_size,
+            block_size,
+            block_pointers,
+            cached_block: None,
+            block_buf: Vec::new(),
+            pos: 0,
+        })
+    }
+
+    fn load_block(&mut self, block: usize) -> io::Result<()> {
+        if self.cached_block == Some(block) {
+            return Ok(());
+        }
+        self.cached_block = None;
+
+        let pointer = *self
+            .block_pointers
+            .get(block)
+            .ok_or_else(|| invalid_data("The GCZ image is missing a block"))?;
+        let start = pointer & !UNCOMPRESSED_FLAG;
+        let end = self
+            .block_pointers
+            .get(block + 1)
+            .map_or(self.compressed_data_size, |p| p & !UNCOMPRESSED_FLAG);
+        if end < start {
+            return Err(invalid_data("A GCZ block pointer is invalid"));
+        }
+        let uncompressed_len = cmp::min(
+            self.block_size,
+            self.data_size - block as u64 * self.block_size,
+        );
+
+        self.reader
+            .seek(SeekFrom::Start(self.data_offset + start))?;
+        let mut compressed = (&mut self.reader).take(end - start);
+
+        self.block_buf.clear();
+        if pointer & UNCOMPRESSED_FLAG != 0 {
+            compressed.read_to_end(&mut self.block_buf)?;
+        } else {
+            ZlibDecoder::new(compressed).read_to_end(&mut self.block_buf)?;
+        }
+
+        if self.block_buf.len() as u64 != uncompressed_len {
+            return Err(invalid_data("A GCZ block has an invalid size"));
+        }
+
+        self.cached_block = Some(block);
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> Read for GczReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.data_size {
+            return Ok(0);
+        }
+
+        let block = (self.pos / self.block_size) as usize;
+        let in_block = (self.pos % self.block_size) as usize;
+        self.load_block(block)?;
+
+        let len = cmp::min(buf.len(), self.block_buf.len() - in_block);
+        buf[..len].copy_from_slice(&self.block_buf[in_block..][..len]);
+
+        self.pos += len as u64;
+        Ok(len)
+    }
+}
+
+impl<R: Read + Seek> Seek for GczReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.pos = super::seek_position(self.pos, self.data_size, pos)?;
+        Ok(self.pos)
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use byteorder::WriteBytesExt;
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use std::io::{Cursor, Write};
+
+    const BLOCK_SIZE: usize = 0x4000;
+
+    /// Creates a GCZ image with a compressed and an uncompressed block.
+    /// Additional block pointers or a larger data size can be specified to
+    /// produce corrupted images.
+    fn image(block_pointers: &[u64], data_size: u64) -> Vec<u8> {
+        let first = (0..BLOCK_SIZE).map(|i| (i / 0x100) as u8).collect::<Vec<_>>();
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&first).unwrap();
+        let mut data = encoder.finish().unwrap();
+        let second_pointer = data.len() as u64 | UNCOMPRESSED_FLAG;
+        data.extend((0..BLOCK_SIZE / 2).map(|i| i as u8));
+
+        let mut pointers = vec![0, second_pointer];
+        pointers.extend_from_slice(block_pointers);
+
+        let mut gcz = Vec::new();
+        gcz.write_u32::<LE>(MAGIC).unwrap();
+        gcz.write_u32::<LE>(0).unwrap();
+        gcz.write_u64::<LE>(data.len() as u64).unwrap();
+        gcz.write_u64::<LE>(data_size).unwrap();
+        gcz.write_u32::<LE>(BLOCK_SIZE as u32).unwrap();
+        gcz.write_u32::<LE>(pointers.len() as u32).unwrap();
+        for &pointer in &pointers {
+            gcz.write_u64::<LE>(pointer).unwrap();
+        }
+        for _ in &pointers {
+            gcz.write_u32::<LE>(0).unwrap();
+        }
+        gcz.extend_from_slice(&data);
+        gcz
+    }
+
+    #[test]
+    fn read() {
+        let len = BLOCK_SIZE + BLOCK_SIZE / 2;
+        let mut reader = GczReader::new(Cursor::new(image(&[], len as u64))).unwrap();
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).unwrap();
+        assert_eq!(read.len(), len);
+        assert!((0..BLOCK_SIZE).all(|i| read[i] == (i / 0x100) as u8));
+        assert!((0..BLOCK_SIZE / 2).all(|i| read[BLOCK_SIZE + i] == i as u8));
+    }
+
+    #[test]
+    fn missing_block() {
+        let image = image(&[], 3 * BLOCK_SIZE as u64);
+        let mut reader = GczReader::new(Cursor::new(image)).unwrap();
+        reader.seek(SeekFrom::Start(2 * BLOCK_SIZE as u64)).unwrap();
+        let err = reader.read(&mut [0; 4]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn invalid_block_pointer() {
+        let image = image(&[1], 3 * BLOCK_SIZE as u64);
+        let mut reader = GczReader::new(Cursor::new(image)).unwrap();
+        reader.seek(SeekFrom::Start(BLOCK_SIZE as u64)).unwrap();
+        let err = reader.read(&mut [0; 4]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,118 @@
+//! Support for the compressed disc image formats commonly used for GameCube
+//! games. All of them get exposed as a reader of the plain GCM image.
+
+pub mod ciso;
+pub mod gcz;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod rvz_writer;
+pub mod wia;
+
+use super::virtual_file_system::{Directory, ReadSeek};
+use super::writer::{write_iso, WriteOptions};
+use byteorder::{ByteOrder, LE};
+use failure::{Error, ResultExt};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+#[derive(Deserialize, Serialize, Copy, Clone, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum DiscFormat {
+    Iso,
+    Ciso,
+    Rvz,
+}
+
+impl Default for DiscFormat {
+    fn default() -> Self {
+        DiscFormat::Iso
+    }
+}
+
+impl DiscFormat {
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Self {
+        let extension = path
+            .as_ref()
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase());
+
+        match extension.as_ref().map(|e| &**e) {
+            Some("ciso") => DiscFormat::Ciso,
+            Some("rvz") => DiscFormat::Rvz,
+            _ => DiscFormat::Iso,
+        }
+    }
+}
+
+/// Detects the format of the disc image and wraps the reader, so it reads the
+/// plain GCM image.
+pub fn open<'a, R: Read + Seek + 'a>(mut reader: R) -> Result<Box<ReadSeek + 'a>, Error> {
+    let mut magic = [0; 4];
+    reader.seek(SeekFrom::Start(0))?;
+    reader
+        .read_exact(&mut magic)
+        .context("Couldn't read the disc image")?;
+    reader.seek(SeekFrom::Start(0))?;
+
+    Ok(if &magic == ciso::MAGIC {
+        Box::new(ciso::CisoReader::new(reader).context("Couldn't parse the CISO image")?)
+    } else if LE::read_u32(&magic) == gcz::MAGIC {
+        Box::new(gcz::GczReader::new(reader).context("Couldn't parse the GCZ image")?)
+    } else if &magic == wia::WIA_MAGIC || &magic == wia::RVZ_MAGIC {
+        Box::new(wia::WiaReader::new(reader).context("Couldn't parse the WIA / RVZ image")?)
+    } else {
+        Box::new(reader)
+    })
+}
+
+pub fn write_disc<W>(
+    writer: W,
+    root: &Directory,
+    format: DiscFormat,
+    options: &WriteOptions,
+) -> Result<(), Error>
+where
+    W: Write + Seek,
+{
+    // The RVZ writer doesn't detect junk data, so it would end up compressing
+    // the random data instead of storing just the seeds of the generator.
+    ensure!(
+        !(format == DiscFormat::Rvz && options.junk_padding),
+        "Junk padding isn't supported for RVZ images. Either disable it or choose a different \
+         disc format."
+    );
+
+    let writer = BufWriter::with_capacity(4 << 20, writer);
+    match format {
+        DiscFormat::Iso => write_iso(writer, root, options),
+        DiscFormat::Ciso => {
+            let mut writer = ciso::CisoWriter::new(writer)?;
+            write_iso(&mut writer, root, options)?;
+            writer.finish()?;
+            Ok(())
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        DiscFormat::Rvz => {
+            let mut writer = rvz_writer::RvzWriter::new(writer)?;
+            write_iso(&mut writer, root, options)?;
+            writer.finish()?;
+            Ok(())
+        }
+        #[cfg(target_arch = "wasm32")]
+        DiscFormat::Rvz => bail!("Writing RVZ images is not supported on this platform"),
+    }
+}
+
+fn seek_position(current: u64, len: u64, pos: SeekFrom) -> io::Result<u64> {
+    let new_pos = match pos {
+        SeekFrom::Start(offset) => offset as i64,
+        SeekFrom::End(offset) => len as i64 + offset,
+        SeekFrom::Current(offset) => current as i64 + offset,
+    };
+    if new_pos < 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Invalid seek to a negative position",
+        ));
+    }
+    Ok(new_pos as u64)
+}
@@ -0,0 +1,212 @@
+//! Writes GameCube discs as RVZ images, compressing each chunk with Zstandard.
+//! See `wia.rs` for the format itself.
+
+use super::wia::RVZ_MAGIC;
+use byteorder::{ByteOrder, WriteBytesExt, BE};
+use sha1::Sha1;
+use std::cmp;
+use std::io::{self, Seek, SeekFrom, Write};
+use zstd;
+
+const RVZ_VERSION: u32 = 0x0100_0000;
+const RVZ_VERSION_WRITE_COMPATIBLE: u32 = 0x0003_0000;
+const HEADER_1_SIZE: usize = 0x48;
+const HEADER_2_SIZE: usize = 0xDC;
+const DISC_HEADER_SIZE: usize = 0x80;
+const PARTITION_ENTRY_SIZE: u32 = 0x30;
+const DISC_TYPE_GAMECUBE: u32 = 1;
+const COMPRESSION_ZSTD: u32 = 5;
+const COMPRESSION_LEVEL: i32 = 5;
+const CHUNK_SIZE: usize = 0x2_0000;
+const COMPRESSED_FLAG: u32 = 0x8000_0000;
+
+struct GroupEntry {
+    data_offset: u32,
+    data_size: u32,
+}
+
+pub struct RvzWriter<W: Write + Seek> {
+    writer: W,
+    chunk: Vec<u8>,
+    disc_header: [u8; DISC_HEADER_SIZE],
+    groups: Vec<GroupEntry>,
+    file_pos: u64,
+    iso_size: u64,
+}
+
+impl<W: Write + Seek> RvzWriter<W> {
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        writer.write_all(&[0; HEADER_1_SIZE + HEADER_2_SIZE])?;
+        Ok(Self {
+            writer,
+            chunk: Vec::with_capacity(CHUNK_SIZE),
+            disc_header: [0; DISC_HEADER_SIZE],
+            groups: Vec::new(),
+            file_pos: (HEADER_1_SIZE + HEADER_2_SIZE) as u64,
+            iso_size: 0,
+        })
+    }
+
+    fn flush_chunk(&mut self) -> io::Result<()> {
+        if self.groups.is_empty() {
+            let len = cmp::min(self.chunk.len(), DISC_HEADER_SIZE);
+            self.disc_header[..len].copy_from_slice(&self.chunk[..len]);
+        }
+
+        let data_offset = (self.file_pos >> 2) as u32;
+
+        let data_size = if self.chunk.iter().all(|&b| b == 0) {
+            0
+        } else {
+            let compressed = zstd::stream::encode_all(&self.chunk[..], COMPRESSION_LEVEL)?;
+            if compressed.len() < self.chunk.len() {
+                self.write_data(&compressed)?;
+                compressed.len() as u32 | COMPRESSED_FLAG
+            } else {
+                let len = self.chunk.len();
+                self.writer.write_all(&self.chunk)?;
+                self.file_pos += len as u64;
+                self.align_data()?;
+                len as u32
+            }
+        };
+
+        self.groups.push(GroupEntry {
+            data_offset,
+            data_size,
+        });
+        self.chunk.clear();
+
+        Ok(())
+    }
+
+    fn write_data(&mut self, data: &[u8]) -> io::Result<()> {
+        self.writer.write_all(data)?;
+        self.file_pos += data.len() as u64;
+        self.align_data()
+    }
+
+    /// The offsets of the groups are stored divided by 4, so all the data
+    /// needs to be aligned to 4 bytes.
+    fn align_data(&mut self) -> io::Result<()> {
+        while self.file_pos % 4 != 0 {
+            self.writer.write_all(&[0])?;
+            self.file_pos += 1;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        if !self.chunk.is_empty() {
+            self.flush_chunk()?;
+        }
+
+        let mut raw_data_entries = Vec::new();
+        raw_data_entries.write_u64::<BE>(DISC_HEADER_SIZE as u64)?;
+        raw_data_entries
+            .write_u64::<BE>(self.iso_size.saturating_sub(DISC_HEADER_SIZE as u64))?;
+        raw_data_entries.write_u32::<BE>(0)?;
+        raw_data_entries.write_u32::<BE>(self.groups.len() as u32)?;
+        let raw_data_entries =
+            zstd::stream::encode_all(&raw_data_entries[..], COMPRESSION_LEVEL)?;
+        let raw_data_entries_offset = self.file_pos;
+        self.write_data(&raw_data_entries)?;
+
+        let mut group_entries = Vec::with_capacity(12 * self.groups.len());
+        for group in &self.groups {
+            group_entries.write_u32::<BE>(group.data_offset)?;
+            group_entries.write_u32::<BE>(group.data_size)?;
+            group_entries.write_u32::<BE>(0)?;
+        }
+        let group_entries = zstd::stream::encode_all(&group_entries[..], COMPRESSION_LEVEL)?;
+        let group_entries_offset = self.file_pos;
+        self.write_data(&group_entries)?;
+
+        let mut header_2 = vec![0; HEADER_2_SIZE];
+        BE::write_u32(&mut header_2, DISC_TYPE_GAMECUBE);
+        BE::write_u32(&mut header_2[0x4..], COMPRESSION_ZSTD);
+        BE::write_i32(&mut header_2[0x8..], COMPRESSION_LEVEL);
+        BE::write_u32(&mut header_2[0xC..], CHUNK_SIZE as u32);
+        header_2[0x10..][..DISC_HEADER_SIZE].copy_from_slice(&self.disc_header);
+        BE::write_u32(&mut header_2[0x94..], PARTITION_ENTRY_SIZE);
+        BE::write_u64(&mut header_2[0x98..], (HEADER_1_SIZE + HEADER_2_SIZE) as u64);
+        header_2[0xA0..][..20].copy_from_slice(&Sha1::new().digest().bytes());
+        BE::write_u32(&mut header_2[0xB4..], 1);
+        BE::write_u64(&mut header_2[0xB8..], raw_data_entries_offset);
+        BE::write_u32(&mut header_2[0xC0..], raw_data_entries.len() as u32);
+        BE::write_u32(&mut header_2[0xC4..], self.groups.len() as u32);
+        BE::write_u64(&mut header_2[0xC8..], group_entries_offset);
+        BE::write_u32(&mut header_2[0xD0..], group_entries.len() as u32);
+
+        let mut header_1 = vec![0; HEADER_1_SIZE];
+        header_1[..4].copy_from_slice(RVZ_MAGIC);
+        BE::write_u32(&mut header_1[0x4..], RVZ_VERSION);
+        BE::write_u32(&mut header_1[0x8..], RVZ_VERSION_WRITE_COMPATIBLE);
+        BE::write_u32(&mut header_1[0xC..], HEADER_2_SIZE as u32);
+        header_1[0x10..][..20].copy_from_slice(&Sha1::from(&header_2).digest().bytes());
+        BE::write_u64(&mut header_1[0x24..], self.iso_size);
+        BE::write_u64(&mut header_1[0x2C..], self.file_pos);
+        let header_1_hash = Sha1::from(&header_1[..HEADER_1_SIZE - 20]).digest().bytes();
+        header_1[HEADER_1_SIZE - 20..].copy_from_slice(&header_1_hash);
+
+        self.writer.seek(SeekFrom::Start(0))?;
+        self.writer.write_all(&header_1)?;
+        self.writer.write_all(&header_2)?;
+        self.writer.flush()?;
+
+        Ok(self.writer)
+    }
+}
+
+impl<W: Write + Seek> Write for RvzWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = cmp::min(buf.len(), CHUNK_SIZE - self.chunk.len());
+        self.chunk.extend_from_slice(&buf[..len]);
+        self.iso_size += len as u64;
+        if self.chunk.len() == CHUNK_SIZE {
+            self.flush_chunk()?;
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::wia::WiaReader;
+    use super::*;
+    use std::io::{Cursor, Read};
+
+    #[test]
+    fn round_trip() {
+        // A compressible chunk, an empty chunk, a chunk that doesn't compress
+        // and a partial chunk at the end.
+        let mut image = (0..CHUNK_SIZE).map(|i| (i / 0x100) as u8).collect::<Vec<_>>();
+        image.resize(2 * CHUNK_SIZE, 0);
+        let mut state = 1u32;
+        image.extend((0..CHUNK_SIZE).map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (state >> 16) as u8
+        }));
+        image.extend((0..0x1234).map(|i| i as u8));
+
+        let mut writer = RvzWriter::new(Cursor::new(Vec::new())).unwrap();
+        writer.write_all(&image).unwrap();
+        let rvz = writer.finish().unwrap().into_inner();
+        assert!(rvz.len() < image.len());
+
+        let mut reader = WiaReader::new(Cursor::new(rvz)).unwrap();
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).unwrap();
+        assert!(read == image);
+
+        let offset = 2 * CHUNK_SIZE + 0x123;
+        reader.seek(SeekFrom::Start(offset as u64)).unwrap();
+        let mut buf = [0; 4];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, image[offset..][..4]);
+    }
+}
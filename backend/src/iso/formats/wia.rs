@@ -0,0 +1,455 @@
+//! WIA and RVZ images split the disc into chunks that are compressed
+//! individually. RVZ additionally stores the junk data of the disc as just
+//! the seed of the generator. Only GameCube discs are supported. Based on
+//! Dolphin's WIABlob and https://github.com/dolphin-emu/dolphin/blob/master/docs/WiaAndRvz.md
+
+use super::super::junk::{LaggedFibonacci, SEED_SIZE};
+use byteorder::{ByteOrder, BE, LE};
+use bzip2_rs::DecoderReader;
+use failure::{Error, ResultExt};
+use lzma_rs;
+use ruzstd::StreamingDecoder;
+use std::cmp::{self, Ordering};
+use std::io::{self, Read, Seek, SeekFrom};
+
+pub const WIA_MAGIC: &[u8; 4] = b"WIA\x01";
+pub const RVZ_MAGIC: &[u8; 4] = b"RVZ\x01";
+
+const HEADER_1_SIZE: usize = 0x48;
+const HEADER_2_SIZE: usize = 0xDC;
+const DISC_HEADER_SIZE: usize = 0x80;
+const RAW_DATA_ENTRY_SIZE: usize = 0x18;
+const WIA_GROUP_ENTRY_SIZE: usize = 0x8;
+const RVZ_GROUP_ENTRY_SIZE: usize = 0xC;
+const BLOCK_SIZE: u64 = 0x8000;
+const DISC_TYPE_GAMECUBE: u32 = 1;
+const COMPRESSED_FLAG: u32 = 0x8000_0000;
+const JUNK_FLAG: u32 = 0x8000_0000;
+const LZMA_PROPERTIES_SIZE: usize = 5;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Compression {
+    None,
+    Purge,
+    Bzip2,
+    Lzma,
+    Lzma2,
+    Zstd,
+}
+
+impl Compression {
+    fn from_u32(value: u32) -> Option<Self> {
+        Some(match value {
+            0 => Compression::None,
+            1 => Compression::Purge,
+            2 => Compression::Bzip2,
+            3 => Compression::Lzma,
+            4 => Compression::Lzma2,
+            5 => Compression::Zstd,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Group {
+    data_offset: u64,
+    data_size: u32,
+    is_compressed: bool,
+    packed_size: u32,
+}
+
+struct Chunk {
+    offset: u64,
+    len: u64,
+    group: Group,
+}
+
+pub struct WiaReader<R> {
+    reader: R,
+    compression: Compression,
+    compressor_data: Vec<u8>,
+    disc_header: [u8; DISC_HEADER_SIZE],
+    chunks: Vec<Chunk>,
+    iso_size: u64,
+    cached_chunk: Option<usize>,
+    chunk_buf: Vec<u8>,
+    pos: u64,
+}
+
+impl<R: Read + Seek> WiaReader<R> {
+    pub fn new(mut reader: R) -> Result<Self, Error> {
+        let mut header_1 = [0; HEADER_1_SIZE];
+        reader.seek(SeekFrom::Start(0))?;
+        reader.read_exact(&mut header_1)?;
+
+        let is_rvz = if &header_1[..4] == RVZ_MAGIC {
+            true
+        } else if &header_1[..4] == WIA_MAGIC {
+            false
+        } else {
+            bail!("The file is not a WIA or RVZ image");
+        };
+
+        let header_2_size = BE::read_u32(&header_1[0xC..]) as usize;
+        let iso_size = BE::read_u64(&header_1[0x24..]);
+        ensure!(
+            header_2_size >= HEADER_2_SIZE,
+            "The WIA header is too small"
+        );
+
+        let mut header_2 = vec![0; header_2_size];
+        reader.read_exact(&mut header_2)?;
+
+        ensure!(
+            BE::read_u32(&header_2) == DISC_TYPE_GAMECUBE,
+            "Only GameCube discs are supported"
+        );
+
+        let compression = Compression::from_u32(BE::read_u32(&header_2[0x4..]))
+            .ok_or_else(|| format_err!("Unknown compression method"))?;
+        let chunk_size = BE::read_u32(&header_2[0xC..]) as u64;
+        ensure!(chunk_size != 0, "The chunk size is invalid");
+
+        // LZMA stores the properties of the encoder in the header rather than
+        // at the start of every compressed group.
+        let compressor_data_size = cmp::min(header_2[0xD4] as usize, 7);
+        let compressor_data = header_2[0xD5..][..compressor_data_size].to_vec();
+        ensure!(
+            compression != Compression::Lzma || compressor_data.len() == LZMA_PROPERTIES_SIZE,
+            "The LZMA properties are missing"
+        );
+
+        let mut disc_header = [0; DISC_HEADER_SIZE];
+        disc_header.copy_from_slice(&header_2[0x10..][..DISC_HEADER_SIZE]);
+
+        let raw_data_entry_count = BE::read_u32(&header_2[0xB4..]) as usize;
+        let raw_data_entries_offset = BE::read_u64(&header_2[0xB8..]);
+        let raw_data_entries_size = BE::read_u32(&header_2[0xC0..]) as usize;
+        let group_entry_count = BE::read_u32(&header_2[0xC4..]) as usize;
+        let group_entries_offset = BE::read_u64(&header_2[0xC8..]);
+        let group_entries_size = BE::read_u32(&header_2[0xD0..]) as usize;
+
+        let raw_data_entries = read_compressed(
+            &mut reader,
+            compression,
+            &compressor_data,
+            raw_data_entries_offset,
+            raw_data_entries_size,
+            raw_data_entry_count * RAW_DATA_ENTRY_SIZE,
+        ).context("Couldn't read the raw data entries")?;
+
+        let group_entry_size = if is_rvz {
+            RVZ_GROUP_ENTRY_SIZE
+        } else {
+            WIA_GROUP_ENTRY_SIZE
+        };
+        let group_entries = read_compressed(
+            &mut reader,
+            compression,
+            &compressor_data,
+            group_entries_offset,
+            group_entries_size,
+            group_entry_count * group_entry_size,
+        ).context("Couldn't read the group entries")?;
+
+        let groups = group_entries
+            .chunks(group_entry_size)
+            .map(|entry| {
+                let data_size = BE::read_u32(&entry[4..]);
+                Group {
+                    data_offset: (BE::read_u32(entry) as u64) << 2,
+                    data_size: if is_rvz {
+                        data_size & !COMPRESSED_FLAG
+                    } else {
+                        data_size
+                    },
+                    is_compressed: !is_rvz || data_size & COMPRESSED_FLAG != 0,
+                    packed_size: if is_rvz { BE::read_u32(&entry[8..]) } else { 0 },
+                }
+            }).collect::<Vec<_>>();
+
+        let mut chunks = Vec::new();
+        for entry in raw_data_entries.chunks(RAW_DATA_ENTRY_SIZE) {
+            let data_offset = BE::read_u64(entry);
+            let data_size = BE::read_u64(&entry[0x8..]);
+            let group_index = BE::read_u32(&entry[0x10..]) as usize;
+            let group_count = BE::read_u32(&entry[0x14..]) as usize;
+
+            // The raw data always starts at a block boundary, even if the
+            // entry itself doesn't.
+            let skipped = data_offset % BLOCK_SIZE;
+            let data_offset = data_offset - skipped;
+            let data_end = data_offset + data_size + skipped;
+
+            for i in 0..group_count {
+                let offset = data_offset + i as u64 * chunk_size;
+                ensure!(
+                    offset < data_end,
+                    "The raw data refers to more groups than it covers"
+                );
+                let group = *groups
+                    .get(group_index + i)
+                    .ok_or_else(|| format_err!("The raw data refers to a missing group"))?;
+                chunks.push(Chunk {
+                    offset,
+                    len: cmp::min(chunk_size, data_end - offset),
+                    group,
+                });
+            }
+        }
+        chunks.sort_by_key(|c| c.offset);
+        ensure!(
+            chunks.windows(2).all(|w| w[0].offset + w[0].len <= w[1].offset),
+            "The raw data entries overlap"
+        );
+
+        Ok(Self {
+            reader,
+            compression,
+            compressor_data,
+            disc_header,
+            chunks,
+            iso_size,
+            cached_chunk: None,
+            chunk_buf: Vec::new(),
+            pos: 0,
+        })
+    }
+
+    fn load_chunk(&mut self, index: usize) -> io::Result<()> {
+        if self.cached_chunk == Some(index) {
+            return Ok(());
+        }
+        self.cached_chunk = None;
+
+        let chunk = &self.chunks[index];
+        let group = chunk.group;
+        let len = chunk.len as usize;
+
+        self.chunk_buf = if group.data_size == 0 {
+            vec![0; len]
+        } else {
+            let compression = if group.is_compressed {
+                self.compression
+            } else {
+                Compression::None
+            };
+            let unpacked_len = if group.packed_size != 0 {
+                group.packed_size as usize
+            } else {
+                len
+            };
+            let data = read_compressed(
+                &mut self.reader,
+                compression,
+                &self.compressor_data,
+                group.data_offset,
+                group.data_size as usize,
+                unpacked_len,
+            )?;
+            if group.packed_size != 0 {
+                unpack(&data, chunk.offset, len)?
+            } else {
+                data
+            }
+        };
+
+        if self.chunk_buf.len() != len {
+            return Err(invalid_data("A chunk has an invalid size"));
+        }
+
+        self.cached_chunk = Some(index);
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> Read for WiaReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.iso_size {
+            return Ok(0);
+        }
+
+        let max_len = cmp::min(buf.len() as u64, self.iso_size - self.pos) as usize;
+        let buf = &mut buf[..max_len];
+
+        if self.pos < DISC_HEADER_SIZE as u64 {
+            let header = &self.disc_header[self.pos as usize..];
+            let len = cmp::min(header.len(), buf.len());
+            buf[..len].copy_from_slice(&header[..len]);
+            self.pos += len as u64;
+            return Ok(len);
+        }
+
+        let pos = self.pos;
+        let found = self.chunks.binary_search_by(|c| {
+            if c.offset + c.len <= pos {
+                Ordering::Less
+            } else if c.offset > pos {
+                Ordering::Greater
+            } else {
+                Ordering::Equal
+            }
+        });
+
+        let len = match found {
+            Ok(index) => {
+                self.load_chunk(index)?;
+                let in_chunk = (pos - self.chunks[index].offset) as usize;
+                let len = cmp::min(buf.len(), self.chunk_buf.len() - in_chunk);
+                buf[..len].copy_from_slice(&self.chunk_buf[in_chunk..][..len]);
+                len
+            }
+            Err(next) => {
+                // Areas that aren't covered by any chunk are zeroes.
+                let end = self.chunks.get(next).map_or(self.iso_size, |c| c.offset);
+                let len = cmp::min(buf.len() as u64, end - pos) as usize;
+                for b in &mut buf[..len] {
+                    *b = 0;
+                }
+                len
+            }
+        };
+
+        self.pos += len as u64;
+        Ok(len)
+    }
+}
+
+impl<R: Read + Seek> Seek for WiaReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.pos = super::seek_position(self.pos, self.iso_size, pos)?;
+        Ok(self.pos)
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_compressed<R: Read + Seek>(
+    reader: &mut R,
+    compression: Compression,
+    compressor_data: &[u8],
+    offset: u64,
+    size: usize,
+    decompressed_size: usize,
+) -> io::Result<Vec<u8>> {
+    let mut data = vec![0; size];
+    reader.seek(SeekFrom::Start(offset))?;
+    reader.read_exact(&mut data)?;
+
+    let decompressed = match compression {
+        Compression::None => data,
+        Compression::Purge => unpurge(&data, decompressed_size)?,
+        Compression::Zstd => {
+            let mut source = &data[..];
+            let mut decoder = StreamingDecoder::new(&mut source)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            let mut decompressed = Vec::with_capacity(decompressed_size);
+            decoder.read_to_end(&mut decompressed)?;
+            decompressed
+        }
+        Compression::Bzip2 => {
+            let mut decompressed = Vec::with_capacity(decompressed_size);
+            DecoderReader::new(&data[..]).read_to_end(&mut decompressed)?;
+            decompressed
+        }
+        Compression::Lzma => {
+            // The groups are raw LZMA streams, so the header of a regular
+            // LZMA stream is put in front of them.
+            let mut stream = Vec::with_capacity(LZMA_PROPERTIES_SIZE + 8 + data.len());
+            stream.extend_from_slice(compressor_data);
+            let mut size = [0; 8];
+            LE::write_u64(&mut size, decompressed_size as u64);
+            stream.extend_from_slice(&size);
+            stream.extend_from_slice(&data);
+            let mut decompressed = Vec::with_capacity(decompressed_size);
+            lzma_rs::lzma_decompress(&mut &stream[..], &mut decompressed)
+                .map_err(|e| invalid_data(&format!("The LZMA data is corrupted: {:?}", e)))?;
+            decompressed
+        }
+        Compression::Lzma2 => {
+            let mut decompressed = Vec::with_capacity(decompressed_size);
+            lzma_rs::lzma2_decompress(&mut &data[..], &mut decompressed)
+                .map_err(|e| invalid_data(&format!("The LZMA2 data is corrupted: {:?}", e)))?;
+            decompressed
+        }
+    };
+
+    if decompressed.len() != decompressed_size {
+        return Err(invalid_data("The decompressed data has an invalid size"));
+    }
+
+    Ok(decompressed)
+}
+
+/// Purged data consists of segments of actual data, followed by a SHA-1 hash.
+/// Everything that is not covered by a segment is zeroes.
+fn unpurge(data: &[u8], decompressed_size: usize) -> io::Result<Vec<u8>> {
+    const HASH_SIZE: usize = 20;
+
+    if data.len() < HASH_SIZE {
+        return Err(invalid_data("The purged data is too small"));
+    }
+
+    let mut output = vec![0; decompressed_size];
+    let mut data = &data[..data.len() - HASH_SIZE];
+
+    while !data.is_empty() {
+        if data.len() < 8 {
+            return Err(invalid_data("The purged data is corrupted"));
+        }
+        let offset = BE::read_u32(data) as usize;
+        let size = BE::read_u32(&data[4..]) as usize;
+        data = &data[8..];
+
+        if data.len() < size || offset + size > decompressed_size {
+            return Err(invalid_data("The purged data is corrupted"));
+        }
+        output[offset..][..size].copy_from_slice(&data[..size]);
+        data = &data[size..];
+    }
+
+    Ok(output)
+}
+
+/// RVZ packs the data into a sequence of either actual data or junk data. The
+/// junk data is stored as just the seed of the generator.
+fn unpack(data: &[u8], mut offset: u64, len: usize) -> io::Result<Vec<u8>> {
+    let mut output = Vec::with_capacity(len);
+    let mut data = data;
+
+    while !data.is_empty() {
+        if data.len() < 4 {
+            return Err(invalid_data("The packed data is corrupted"));
+        }
+        let size = BE::read_u32(data);
+        data = &data[4..];
+
+        if size & JUNK_FLAG != 0 {
+            let size = (size & !JUNK_FLAG) as usize;
+            if data.len() < 4 * SEED_SIZE || output.len() + size > len {
+                return Err(invalid_data("The packed data is corrupted"));
+            }
+            let mut lfg = LaggedFibonacci::from_seed(&data[..4 * SEED_SIZE]);
+            data = &data[4 * SEED_SIZE..];
+
+            lfg.skip((offset % BLOCK_SIZE) as usize);
+            let start = output.len();
+            output.resize(start + size, 0);
+            lfg.fill(&mut output[start..]);
+            offset += size as u64;
+        } else {
+            let size = size as usize;
+            if data.len() < size || output.len() + size > len {
+                return Err(invalid_data("The packed data is corrupted"));
+            }
+            output.extend_from_slice(&data[..size]);
+            data = &data[size..];
+            offset += size as u64;
+        }
+    }
+
+    Ok(output)
+}
@@ -0,0 +1,158 @@
+//! The unused areas of a GameCube disc are filled with pseudo random junk
+//! data, generated by a lagged Fibonacci generator. Based on Dolphin's
+//! LaggedFibonacciGenerator.
+
+use byteorder::{ByteOrder, BE};
+use std::cmp;
+
+pub const SEED_SIZE: usize = 17;
+const LFG_K: usize = 521;
+const LFG_J: usize = 32;
+const BUFFER_LEN: usize = LFG_K * 4;
+
+pub struct LaggedFibonacci {
+    buffer: [u32; LFG_K],
+    bytes: [u8; BUFFER_LEN],
+    position: usize,
+}
+
+impl LaggedFibonacci {
+    /// Creates the generator from a seed of 17 big endian words, like they
+    /// are stored in RVZ files.
+    pub fn from_seed(seed: &[u8]) -> Self {
+        let mut words = [0; SEED_SIZE];
+        for (word, bytes) in words.iter_mut().zip(seed.chunks(4)) {
+            *word = BE::read_u32(bytes);
+        }
+        Self::from_words(&words)
+    }
+
+    pub fn from_words(seed: &[u32; SEED_SIZE]) -> Self {
+        let mut lfg = LaggedFibonacci {
+            buffer: [0; LFG_K],
+            bytes: [0; BUFFER_LEN],
+            position: 0,
+        };
+
+        lfg.buffer[..SEED_SIZE].copy_from_slice(seed);
+
+        for i in SEED_SIZE..LFG_K {
+            lfg.buffer[i] =
+                (lfg.buffer[i - 17] << 23) ^ (lfg.buffer[i - 16] >> 9) ^ lfg.buffer[i - 1];
+        }
+
+        // The output uses bits 18 to 25 instead of 16 to 23 for the second
+        // byte, so instead of doing that when outputting the data, we do the
+        // shifting right away. This is a linear transformation, so it doesn't
+        // affect the generator itself.
+        for x in lfg.buffer.iter_mut() {
+            *x = (*x & 0xFF00_FFFF) | ((*x >> 2) & 0x00FF_0000);
+        }
+
+        for _ in 0..4 {
+            lfg.forward();
+        }
+
+        lfg
+    }
+
+    fn forward(&mut self) {
+        for i in 0..LFG_J {
+            self.buffer[i] ^= self.buffer[i + LFG_K - LFG_J];
+        }
+        for i in LFG_J..LFG_K {
+            self.buffer[i] ^= self.buffer[i - LFG_J];
+        }
+        for (bytes, &word) in self.bytes.chunks_mut(4).zip(self.buffer.iter()) {
+            BE::write_u32(bytes, word);
+        }
+    }
+
+    /// Skips the given amount of bytes of the stream.
+    pub fn skip(&mut self, count: usize) {
+        self.position += count;
+        while self.position >= BUFFER_LEN {
+            self.forward();
+            self.position -= BUFFER_LEN;
+        }
+    }
+
+    pub fn fill(&mut self, mut buf: &mut [u8]) {
+        while !buf.is_empty() {
+            let len = cmp::min(buf.len(), BUFFER_LEN - self.position);
+            let (dst, rest) = { buf }.split_at_mut(len);
+            dst.copy_from_slice(&self.bytes[self.position..][..len]);
+            buf = rest;
+            self.position += len;
+            if self.position == BUFFER_LEN {
+                self.forward();
+                self.position = 0;
+            }
+        }
+    }
+}
+
+/// The junk data on GameCube discs is reseeded every 256 KiB.
+const JUNK_BLOCK_SIZE: u64 = 0x4_0000;
+
+/// Generates the junk data that Nintendo's mastering tools put into the unused
+/// areas of GameCube discs. The seed is derived from the game ID, the disc
+/// number and the offset of the block on the disc.
+pub struct JunkStream {
+    id: u32,
+    disc_number: u8,
+    lfg: Option<LaggedFibonacci>,
+    position: u64,
+}
+
+impl JunkStream {
+    pub fn new(game_id: &[u8], disc_number: u8) -> Self {
+        Self {
+            id: BE::read_u32(game_id),
+            disc_number,
+            lfg: None,
+            position: 0,
+        }
+    }
+
+    fn seed(&self, block: u64) -> LaggedFibonacci {
+        let mut n = (self.id ^ self.disc_number as u32).wrapping_mul(0x260_BCD5)
+            ^ (block as u32).wrapping_mul(0x1EF2_9123);
+
+        let mut seed = [0; SEED_SIZE];
+        for word in seed.iter_mut() {
+            for _ in 0..32 {
+                n = n.wrapping_mul(0x5D58_8B65).wrapping_add(1);
+                *word = (*word >> 1) | (n & 0x8000_0000);
+            }
+        }
+        seed[16] ^= (seed[0] >> 9) ^ (seed[16] << 23);
+
+        LaggedFibonacci::from_words(&seed)
+    }
+
+    /// Fills the buffer with the junk data found at the given offset of the
+    /// disc. Sequential calls are cheap, as the generator only needs to be
+    /// reseeded when jumping around or crossing a block boundary.
+    pub fn fill(&mut self, mut offset: u64, mut buf: &mut [u8]) {
+        while !buf.is_empty() {
+            let block = offset / JUNK_BLOCK_SIZE;
+            let in_block = offset % JUNK_BLOCK_SIZE;
+
+            if self.lfg.is_none() || self.position != offset || in_block == 0 {
+                let mut lfg = self.seed(block);
+                lfg.skip(in_block as usize);
+                self.lfg = Some(lfg);
+            }
+
+            let len = cmp::min(buf.len() as u64, JUNK_BLOCK_SIZE - in_block) as usize;
+            let (dst, rest) = { buf }.split_at_mut(len);
+            if let Some(ref mut lfg) = self.lfg {
+                lfg.fill(dst);
+            }
+            buf = rest;
+            offset += len as u64;
+            self.position = offset;
+        }
+    }
+}
@@ -1,6 +1,9 @@
 //! Based on http://www.gc-forever.com/yagcd/chap13.html#sec13
 //! and https://github.com/LordNed/WArchive-Tools
 
+pub mod extracted;
+pub mod formats;
+pub mod junk;
 pub mod reader;
 pub mod virtual_file_system;
 pub mod writer;
@@ -18,6 +21,10 @@ pub mod consts {
     pub const HEADER_LENGTH: usize = 0x2440;
     pub const DOL_ALIGNMENT: usize = 1024;
     pub const FST_ALIGNMENT: usize = 256;
+    /// The default alignment of the files on the disc.
+    pub const FILE_ALIGNMENT: usize = 32;
+    /// The size of a full GameCube disc.
+    pub const DISC_SIZE: usize = 1_459_978_240;
 }
 
 #[derive(Copy, Clone, PartialEq)]
@@ -1,134 +1,195 @@
-use super::virtual_file_system::{Directory, File, Node};
+use super::{extracted, formats};
+use super::virtual_file_system::{Directory, File, FileData, Node, SharedReader};
 use super::{consts::*, FstEntry, FstNodeType};
 use byteorder::{ByteOrder, BE};
 use failure::{Error, ResultExt};
+use std::cell::RefCell;
 use std::fs;
-use std::io::{Read, Result as IOResult};
+use std::io::{BufReader, Read, Seek, SeekFrom};
 use std::path::Path;
+use std::rc::Rc;
 use std::str;
 
-pub fn load_iso_buf<P: AsRef<Path>>(path: P) -> IOResult<Vec<u8>> {
-    let mut file = fs::File::open(path)?;
-    let len = file.metadata()?.len();
-    let mut buf = Vec::with_capacity(len as usize + 1);
-    file.read_to_end(&mut buf)?;
-    Ok(buf)
+const NKIT_MAGIC: &[u8; 4] = b"NKIT";
+const NKIT_HEADER_OFFSET: usize = 0x200;
+
+pub fn load_iso_file<'a, P: AsRef<Path>>(path: P) -> Result<Directory<'a>, Error> {
+    let file = fs::File::open(path).context("Couldn't open the ISO")?;
+    load_iso(BufReader::new(file))
 }
 
-pub fn load_iso<'a>(buf: &'a [u8]) -> Result<Directory<'a>, Error> {
-    let fst_offset = BE::read_u32(&buf[OFFSET_FST_OFFSET..]) as usize;
-    let mut pos = fst_offset;
-    let num_entries = BE::read_u32(&buf[fst_offset + 8..]) as usize;
-    let string_table_offset = num_entries * 0xC;
-
-    let mut fst_entries = Vec::with_capacity(num_entries);
-    for _ in 0..num_entries {
-        let kind = if buf[pos] == 0 {
-            FstNodeType::File
-        } else {
-            FstNodeType::Directory
-        };
-        pos += 2;
-
-        let cur_pos = pos;
-        let string_offset = BE::read_u16(&buf[pos..]) as usize;
-
-        pos = string_offset + string_table_offset + fst_offset;
-        let mut end = pos;
-        while buf[end] != 0 {
-            end += 1;
-        }
-        let relative_file_name =
-            str::from_utf8(&buf[pos..end]).context("Couldn't parse the relative file name")?;
-
-        pos = cur_pos + 2;
-        let file_offset_parent_dir = BE::read_u32(&buf[pos..]) as usize;
-        let file_size_next_dir_index = BE::read_u32(&buf[pos + 4..]) as usize;
-        pos += 8;
-
-        fst_entries.push(FstEntry {
-            kind,
-            relative_file_name,
-            file_offset_parent_dir,
-            file_size_next_dir_index,
-            file_name_offset: 0,
-        });
-    }
+/// Checks whether the plain image is an NKit image, which misses the junk
+/// data and has its files moved.
+pub fn is_nkit<R: Read + Seek>(reader: &mut R) -> Result<bool, Error> {
+    let mut magic = [0; 4];
+    reader.seek(SeekFrom::Start(NKIT_HEADER_OFFSET as u64))?;
+    reader
+        .read_exact(&mut magic)
+        .context("Couldn't read the ISO header")?;
+    reader.seek(SeekFrom::Start(0))?;
+    Ok(&magic == NKIT_MAGIC)
+}
+
+/// Parses the header and the FST of the ISO. The contents of the files are
+/// only read from the reader once they are actually needed.
+pub fn load_iso<'a, R: Read + Seek + 'a>(reader: R) -> Result<Directory<'a>, Error> {
+    let reader: SharedReader<'a> = Rc::new(RefCell::new(formats::open(reader)?));
+
+    let header = read_range(&reader, 0, HEADER_LENGTH).context("Couldn't read the ISO header")?;
+
+    // NKit images have the junk data removed and their files moved closer
+    // together. Restoring the retail layout isn't supported, and building from
+    // the moved files would produce a disc that doesn't match the retail one.
+    ensure!(
+        &header[NKIT_HEADER_OFFSET..][..4] != NKIT_MAGIC,
+        "NKit images aren't supported. Please convert the image back to a regular disc image."
+    );
+
+    let dol_offset = BE::read_u32(&header[OFFSET_DOL_OFFSET..]) as usize;
+    let fst_offset = BE::read_u32(&header[OFFSET_FST_OFFSET..]) as usize;
+    let fst_size = BE::read_u32(&header[OFFSET_FST_SIZE..]) as usize;
+
+    ensure!(
+        HEADER_LENGTH <= dol_offset && dol_offset <= fst_offset,
+        "The ISO header is corrupted"
+    );
+
+    let apploader = read_range(&reader, HEADER_LENGTH as u64, dol_offset - HEADER_LENGTH)
+        .context("Couldn't read the apploader")?;
+    let mut dol = read_range(&reader, dol_offset as u64, fst_offset - dol_offset)
+        .context("Couldn't read the main dol")?;
+    // The DOL ends where its last section ends, just like when it's extracted,
+    // so the padding up to the FST isn't part of it.
+    let dol_len = extracted::dol_len(&dol);
+    dol.truncate(dol_len);
+    let fst = read_range(&reader, fst_offset as u64, fst_size).context("Couldn't read the FST")?;
 
-    let mut root_dir = Directory::new("root");
     let mut sys_data = Directory::new("&&systemdata");
 
     sys_data
         .children
-        .push(Node::File(File::new("iso.hdr", &buf[..HEADER_LENGTH])));
+        .push(Node::File(File::new("iso.hdr", header)));
 
-    let dol_offset = BE::read_u32(&buf[OFFSET_DOL_OFFSET..]) as usize;
+    sys_data
+        .children
+        .push(Node::File(File::new("AppLoader.ldr", apploader)));
 
-    sys_data.children.push(Node::File(File::new(
-        "AppLoader.ldr",
-        &buf[HEADER_LENGTH..dol_offset],
-    )));
+    sys_data
+        .children
+        .push(Node::File(File::new("Start.dol", dol)));
 
-    sys_data.children.push(Node::File(File::new(
-        "Start.dol",
-        &buf[dol_offset..fst_offset],
-    )));
+    let mut root_dir = Directory::new("root");
 
-    let fst_size = BE::read_u32(&buf[OFFSET_FST_SIZE..]) as usize;
-    sys_data.children.push(Node::File(File::new(
-        "Game.toc",
-        &buf[fst_offset..][..fst_size],
-    )));
+    {
+        let num_entries = BE::read_u32(&fst[8..]) as usize;
+        let string_table_offset = num_entries * 0xC;
+
+        let mut fst_entries = Vec::with_capacity(num_entries);
+        let mut pos = 0;
+        for _ in 0..num_entries {
+            let kind = if fst[pos] == 0 {
+                FstNodeType::File
+            } else {
+                FstNodeType::Directory
+            };
+            pos += 2;
+
+            let cur_pos = pos;
+            let string_offset = BE::read_u16(&fst[pos..]) as usize;
+
+            pos = string_offset + string_table_offset;
+            let mut end = pos;
+            while fst[end] != 0 {
+                end += 1;
+            }
+            let relative_file_name =
+                str::from_utf8(&fst[pos..end]).context("Couldn't parse the relative file name")?;
+
+            pos = cur_pos + 2;
+            let file_offset_parent_dir = BE::read_u32(&fst[pos..]) as usize;
+            let file_size_next_dir_index = BE::read_u32(&fst[pos + 4..]) as usize;
+            pos += 8;
+
+            fst_entries.push(FstEntry {
+                kind,
+                relative_file_name,
+                file_offset_parent_dir,
+                file_size_next_dir_index,
+                file_name_offset: 0,
+            });
+        }
 
-    root_dir.children.push(Node::Directory(Box::new(sys_data)));
+        let mut count = 1;
 
-    let mut count = 1;
+        while count < num_entries {
+            let entry = &fst_entries[count];
+            if fst_entries[count].kind == FstNodeType::Directory {
+                let mut dir = Directory::new(entry.relative_file_name.to_owned());
 
-    while count < num_entries {
-        let entry = &fst_entries[count];
-        if fst_entries[count].kind == FstNodeType::Directory {
-            let mut dir = Directory::new(entry.relative_file_name);
+                while count < entry.file_size_next_dir_index - 1 {
+                    count =
+                        get_dir_structure_recursive(count + 1, &fst_entries, &mut dir, &reader);
+                }
 
-            while count < entry.file_size_next_dir_index - 1 {
-                count = get_dir_structure_recursive(count + 1, &fst_entries, &mut dir, buf);
+                root_dir.children.push(Node::Directory(Box::new(dir)));
+            } else {
+                let file = get_file_data(&fst_entries[count], &reader);
+                root_dir.children.push(Node::File(file));
             }
-
-            root_dir.children.push(Node::Directory(Box::new(dir)));
-        } else {
-            let file = get_file_data(&fst_entries[count], buf);
-            root_dir.children.push(Node::File(file));
+            count += 1;
         }
-        count += 1;
     }
 
+    sys_data
+        .children
+        .push(Node::File(File::new("Game.toc", fst)));
+
+    root_dir
+        .children
+        .insert(0, Node::Directory(Box::new(sys_data)));
+
     Ok(root_dir)
 }
 
+fn read_range(reader: &SharedReader, offset: u64, len: usize) -> Result<Vec<u8>, Error> {
+    let mut reader = reader.borrow_mut();
+    reader.seek(SeekFrom::Start(offset))?;
+    let mut buf = vec![0; len];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
 fn get_dir_structure_recursive<'a>(
     mut cur_index: usize,
-    fst: &[FstEntry<'a>],
+    fst: &[FstEntry],
     parent_dir: &mut Directory<'a>,
-    buf: &'a [u8],
+    reader: &SharedReader<'a>,
 ) -> usize {
     let entry = &fst[cur_index];
 
     if entry.kind == FstNodeType::Directory {
-        let mut dir = Directory::new(entry.relative_file_name);
+        let mut dir = Directory::new(entry.relative_file_name.to_owned());
 
         while cur_index < entry.file_size_next_dir_index - 1 {
-            cur_index = get_dir_structure_recursive(cur_index + 1, fst, &mut dir, buf);
+            cur_index = get_dir_structure_recursive(cur_index + 1, fst, &mut dir, reader);
         }
 
         parent_dir.children.push(Node::Directory(Box::new(dir)));
     } else {
-        let file = get_file_data(entry, buf);
+        let file = get_file_data(entry, reader);
         parent_dir.children.push(Node::File(file));
     }
 
     cur_index
 }
 
-fn get_file_data<'a>(fst_data: &FstEntry<'a>, buf: &'a [u8]) -> File<'a> {
-    let data = &buf[fst_data.file_offset_parent_dir..][..fst_data.file_size_next_dir_index];
-    File::new(fst_data.relative_file_name, data)
+fn get_file_data<'a>(fst_data: &FstEntry, reader: &SharedReader<'a>) -> File<'a> {
+    let data = FileData::Reader {
+        reader: reader.clone(),
+        offset: fst_data.file_offset_parent_dir as u64,
+        len: fst_data.file_size_next_dir_index,
+    };
+    let mut file = File::new(fst_data.relative_file_name.to_owned(), data);
+    file.original_offset = Some(fst_data.file_offset_parent_dir);
+    file
 }
@@ -1,4 +1,10 @@
 use std::borrow::Cow;
+use std::cell::RefCell;
+use std::cmp;
+use std::fs;
+use std::io::{self, prelude::*, SeekFrom};
+use std::path::PathBuf;
+use std::rc::Rc;
 
 #[derive(Debug)]
 pub enum Node<'a> {
@@ -38,22 +44,50 @@ impl<'a> Node<'a> {
             None
         }
     }
+
+    pub fn name(&self) -> &str {
+        match *self {
+            Node::Directory(ref dir) => &dir.name,
+            Node::File(ref file) => &file.name,
+        }
+    }
+
+    pub fn set_name<N: Into<Cow<'a, str>>>(&mut self, name: N) {
+        match *self {
+            Node::Directory(ref mut dir) => dir.name = name.into(),
+            Node::File(ref mut file) => file.name = name.into(),
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct Directory<'a> {
-    pub name: &'a str,
+    pub name: Cow<'a, str>,
     pub children: Vec<Node<'a>>,
 }
 
 impl<'a> Directory<'a> {
-    pub fn new(name: &'a str) -> Directory<'a> {
+    pub fn new<N: Into<Cow<'a, str>>>(name: N) -> Directory<'a> {
         Self {
-            name,
+            name: name.into(),
             children: Vec::new(),
         }
     }
 
+    pub fn main_dol(&self) -> Option<&File<'a>> {
+        let sys_dir = self
+            .children
+            .iter()
+            .filter_map(|c| c.as_directory())
+            .find(|d| d.name == "&&systemdata")?;
+        let dol = sys_dir
+            .children
+            .iter()
+            .filter_map(|c| c.as_file())
+            .find(|f| f.name.ends_with(".dol"))?;
+        Some(dol)
+    }
+
     pub fn main_dol_mut(&mut self) -> Option<&mut File<'a>> {
         let sys_dir = self
             .children
@@ -68,6 +102,34 @@ impl<'a> Directory<'a> {
         Some(dol)
     }
 
+    pub fn header(&self) -> Option<&File<'a>> {
+        let sys_dir = self
+            .children
+            .iter()
+            .filter_map(|c| c.as_directory())
+            .find(|d| d.name == "&&systemdata")?;
+        let header = sys_dir
+            .children
+            .iter()
+            .filter_map(|c| c.as_file())
+            .find(|f| f.name == "iso.hdr")?;
+        Some(header)
+    }
+
+    pub fn header_mut(&mut self) -> Option<&mut File<'a>> {
+        let sys_dir = self
+            .children
+            .iter_mut()
+            .filter_map(|c| c.as_directory_mut())
+            .find(|d| d.name == "&&systemdata")?;
+        let header = sys_dir
+            .children
+            .iter_mut()
+            .filter_map(|c| c.as_file_mut())
+            .find(|f| f.name == "iso.hdr")?;
+        Some(header)
+    }
+
     pub fn banner_mut(&mut self) -> Option<&mut File<'a>> {
         let banner = self
             .children
@@ -100,8 +162,66 @@ impl<'a> Directory<'a> {
         None
     }
 
+    pub fn resolve_node(&self, path: &str) -> Option<&Node<'a>> {
+        let mut splits = path.splitn(2, '/');
+        let (name, sub_path) = (splits.next()?, splits.next());
+        match sub_path {
+            Some(sub_path) => self
+                .children
+                .iter()
+                .filter_map(|c| c.as_directory())
+                .find(|d| d.name == name)?
+                .resolve_node(sub_path),
+            None => self.children.iter().find(|c| c.name() == name),
+        }
+    }
+
+    /// Removes the file or directory at the given path from the tree and
+    /// returns it.
+    pub fn remove_path(&mut self, path: &str) -> Option<Node<'a>> {
+        let mut splits = path.splitn(2, '/');
+        let (name, sub_path) = (splits.next()?, splits.next());
+        match sub_path {
+            Some(sub_path) => self
+                .children
+                .iter_mut()
+                .filter_map(|c| c.as_directory_mut())
+                .find(|d| d.name == name)?
+                .remove_path(sub_path),
+            None => {
+                let index = self.children.iter().position(|c| c.name() == name)?;
+                Some(self.children.remove(index))
+            }
+        }
+    }
+
     // TODO NLL This is really bad
-    pub fn resolve_and_create_path(&mut self, path: &'a str) -> &mut File<'a> {
+    pub fn resolve_and_create_dir(&mut self, path: &'a str) -> &mut Directory<'a> {
+        if path.is_empty() {
+            return self;
+        }
+        let mut splits = path.splitn(2, '/');
+        let folder = splits.next().unwrap();
+        let sub_path = splits.next().unwrap_or("");
+        if !self
+            .children
+            .iter_mut()
+            .filter_map(|c| c.as_directory_mut())
+            .any(|d| d.name == folder)
+        {
+            self.children
+                .push(Node::Directory(Box::new(Directory::new(folder))));
+        }
+        self.children
+            .iter_mut()
+            .filter_map(|c| c.as_directory_mut())
+            .find(|d| d.name == folder)
+            .unwrap()
+            .resolve_and_create_dir(sub_path)
+    }
+
+    // TODO NLL This is really bad
+    pub fn resolve_and_create_path(&mut self, path: &str) -> &mut File<'a> {
         let mut splits = path.splitn(2, '/');
         if let (Some(folder), Some(sub_path)) = (splits.next(), splits.next()) {
             if !self
@@ -111,7 +231,7 @@ impl<'a> Directory<'a> {
                 .any(|d| d.name == folder)
             {
                 self.children
-                    .push(Node::Directory(Box::new(Directory::new(folder))));
+                    .push(Node::Directory(Box::new(Directory::new(folder.to_owned()))));
             }
             self.children
                 .iter_mut()
@@ -126,7 +246,8 @@ impl<'a> Directory<'a> {
                 .filter_map(|c| c.as_file_mut())
                 .any(|f| f.name == path)
             {
-                self.children.push(Node::File(File::new(path, Vec::new())));
+                self.children
+                    .push(Node::File(File::new(path.to_owned(), Vec::new())));
             }
             self.children
                 .iter_mut()
@@ -138,19 +259,151 @@ impl<'a> Directory<'a> {
 }
 
 pub struct File<'a> {
-    pub name: &'a str,
-    pub data: Cow<'a, [u8]>,
+    pub name: Cow<'a, str>,
+    pub data: FileData<'a>,
+    /// The offset of the file on the original disc, which allows the writer to
+    /// keep the file where it was.
+    pub original_offset: Option<usize>,
 }
 
 impl<'a> File<'a> {
-    pub fn new<A: Into<Cow<'a, [u8]>>>(name: &'a str, data: A) -> File<'a> {
+    pub fn new<N: Into<Cow<'a, str>>, A: Into<FileData<'a>>>(name: N, data: A) -> File<'a> {
         Self {
-            name,
+            name: name.into(),
             data: data.into(),
+            original_offset: None,
         }
     }
 }
 
+pub trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek> ReadSeek for T {}
+
+/// A reader that is shared by all the files that are lazily loaded from it.
+pub type SharedReader<'a> = Rc<RefCell<ReadSeek + 'a>>;
+
+/// The contents of a file. Only files that got replaced are actually kept in
+/// memory. All the other files are only loaded from their source when they
+/// are needed, so unchanged files can be streamed straight into the output.
+pub enum FileData<'a> {
+    Memory(Cow<'a, [u8]>),
+    Reader {
+        reader: SharedReader<'a>,
+        offset: u64,
+        len: usize,
+    },
+    Path {
+        path: PathBuf,
+        len: usize,
+    },
+}
+
+impl<'a> FileData<'a> {
+    pub fn len(&self) -> usize {
+        match *self {
+            FileData::Memory(ref data) => data.len(),
+            FileData::Reader { len, .. } | FileData::Path { len, .. } => len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn load(&self) -> io::Result<Cow<[u8]>> {
+        match *self {
+            FileData::Memory(ref data) => Ok(Cow::Borrowed(data)),
+            FileData::Reader {
+                ref reader,
+                offset,
+                len,
+            } => {
+                let mut reader = reader.borrow_mut();
+                reader.seek(SeekFrom::Start(offset))?;
+                let mut buf = vec![0; len];
+                reader.read_exact(&mut buf)?;
+                Ok(Cow::Owned(buf))
+            }
+            FileData::Path { ref path, .. } => fs::read(path).map(Cow::Owned),
+        }
+    }
+
+    /// Reads just the beginning of the file, which is useful for checking its
+    /// magic number without loading all of it.
+    pub fn read_prefix(&self, len: usize) -> io::Result<Cow<[u8]>> {
+        let len = cmp::min(len, self.len());
+        match *self {
+            FileData::Memory(ref data) => Ok(Cow::Borrowed(&data[..len])),
+            FileData::Reader {
+                ref reader, offset, ..
+            } => {
+                let mut reader = reader.borrow_mut();
+                reader.seek(SeekFrom::Start(offset))?;
+                let mut buf = vec![0; len];
+                reader.read_exact(&mut buf)?;
+                Ok(Cow::Owned(buf))
+            }
+            FileData::Path { ref path, .. } => {
+                let mut buf = vec![0; len];
+                fs::File::open(path)?.read_exact(&mut buf)?;
+                Ok(Cow::Owned(buf))
+            }
+        }
+    }
+
+    pub fn write_to<W: Write + ?Sized>(&self, writer: &mut W) -> io::Result<()> {
+        match *self {
+            FileData::Memory(ref data) => writer.write_all(data),
+            FileData::Reader {
+                ref reader,
+                offset,
+                len,
+            } => {
+                let mut reader = reader.borrow_mut();
+                reader.seek(SeekFrom::Start(offset))?;
+                copy_exact(&mut *reader, writer, len)
+            }
+            FileData::Path { ref path, len } => {
+                copy_exact(&mut fs::File::open(path)?, writer, len)
+            }
+        }
+    }
+}
+
+fn copy_exact<R, W>(reader: &mut R, writer: &mut W, len: usize) -> io::Result<()>
+where
+    R: Read + ?Sized,
+    W: Write + ?Sized,
+{
+    let copied = io::copy(&mut Read::take(reader, len as u64), writer)?;
+    if copied != len as u64 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "The file ended unexpectedly",
+        ));
+    }
+    Ok(())
+}
+
+impl<'a> From<Vec<u8>> for FileData<'a> {
+    fn from(data: Vec<u8>) -> Self {
+        FileData::Memory(data.into())
+    }
+}
+
+impl<'a> From<&'a [u8]> for FileData<'a> {
+    fn from(data: &'a [u8]) -> Self {
+        FileData::Memory(data.into())
+    }
+}
+
+impl<'a> From<Cow<'a, [u8]>> for FileData<'a> {
+    fn from(data: Cow<'a, [u8]>) -> Self {
+        FileData::Memory(data)
+    }
+}
+
 use std::fmt;
 
 impl<'a> fmt::Debug for File<'a> {
@@ -158,42 +411,3 @@ impl<'a> fmt::Debug for File<'a> {
         write!(f, "{}", self.name)
     }
 }
-
-// use std::path::Path;
-// use std::io::{Read, Result};
-// use std::fs::{read_dir, self};
-
-// pub fn import_from_disk<P: AsRef<Path>>(path: P) -> Result<Directory> {
-//     let path = path.as_ref();
-//     let name = path.file_name().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
-
-//     let mut directory = Directory {
-//         id: 0,
-//         name,
-//         children: Vec::new(),
-//     };
-
-//     for entry in read_dir(path)? {
-//         let entry = entry?;
-//         let file_type = entry.file_type()?;
-//         if file_type.is_dir() {
-//             let child = import_from_disk(entry.path())?;
-//             directory.children.push(Node::Directory(Box::new(child)));
-//         } else {
-//             let name = entry.file_name().to_string_lossy().into_owned();
-
-//             let mut file = fs::File::open(entry.path())?;
-//             let len = file.metadata()?.len();
-//             let mut data = Vec::with_capacity(len as usize + 1);
-//             file.read_to_end(&mut data)?;
-
-//             directory.children.push(Node::File(File {
-//                 id: 0,
-//                 name,
-//                 data,
-//             }));
-//         }
-//     }
-
-//     Ok(directory)
-// }
@@ -1,12 +1,61 @@
-use super::virtual_file_system::{Directory, Node};
+use super::junk::JunkStream;
+use super::virtual_file_system::{Directory, File, Node};
 use super::{consts::*, FstEntry, FstNodeType};
-use byteorder::{WriteBytesExt, BE};
+use byteorder::{ByteOrder, WriteBytesExt, BE};
 use failure::{err_msg, Error};
-use std::io::{Seek, SeekFrom, Write};
+use std::cmp;
+use std::io::{self, Write};
+
+#[derive(Deserialize, Serialize, Copy, Clone, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Layout {
+    /// All the files are packed right after each other in the order of the
+    /// FST.
+    Packed,
+    /// The files stay at their offsets on the original disc as long as they
+    /// still fit. Everything else gets appended after the last file.
+    Original,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Layout::Packed
+    }
+}
 
-pub fn write_iso<W>(mut writer: W, root: &Directory) -> Result<(), Error>
+#[derive(Copy, Clone, Debug)]
+pub struct WriteOptions {
+    /// Fills the unused areas between and after the files with the junk data
+    /// that retail discs use, instead of zeroes. The disc is then also padded
+    /// to its full size, just like a retail disc.
+    pub junk_padding: bool,
+    pub layout: Layout,
+    /// The alignment of the files that get placed by the writer.
+    pub file_alignment: usize,
+    /// Fails if the disc doesn't fit onto an actual GameCube disc.
+    pub limit_size: bool,
+}
+
+impl Default for WriteOptions {
+    fn default() -> Self {
+        Self {
+            junk_padding: false,
+            layout: Layout::Packed,
+            file_alignment: FILE_ALIGNMENT,
+            limit_size: false,
+        }
+    }
+}
+
+/// Writes the ISO strictly sequentially. The layout of the whole disc is
+/// determined upfront, so the writer never needs to seek.
+pub fn write_iso<W>(
+    mut writer: W,
+    root: &Directory,
+    options: &WriteOptions,
+) -> Result<(), Error>
 where
-    W: Write + Seek,
+    W: Write,
 {
     let (sys_index, sys_dir) = root
         .children
@@ -22,7 +71,6 @@ where
         .filter_map(|c| c.as_file())
         .find(|f| f.name == "iso.hdr")
         .ok_or_else(|| err_msg("The &&systemdata folder contains no iso.hdr"))?;
-    writer.write_all(&header.data)?;
 
     let apploader = sys_dir
         .children
@@ -30,15 +78,6 @@ where
         .filter_map(|c| c.as_file())
         .find(|f| f.name == "AppLoader.ldr")
         .ok_or_else(|| err_msg("The &&systemdata folder contains no AppLoader.ldr"))?;
-    writer.write_all(&apploader.data)?;
-
-    let dol_offset_without_padding = header.data.len() + apploader.data.len();
-    let dol_offset =
-        (dol_offset_without_padding + (DOL_ALIGNMENT - 1)) / DOL_ALIGNMENT * DOL_ALIGNMENT;
-
-    for _ in dol_offset_without_padding..dol_offset {
-        writer.write_all(&[0])?;
-    }
 
     let dol = sys_dir
         .children
@@ -46,15 +85,10 @@ where
         .filter_map(|c| c.as_file())
         .find(|f| f.name.ends_with(".dol"))
         .ok_or_else(|| err_msg("The &&systemdata folder contains no dol file"))?;
-    writer.write_all(&dol.data)?;
-
-    let fst_list_offset_without_padding = dol_offset + dol.data.len();
-    let fst_list_offset =
-        (fst_list_offset_without_padding + (FST_ALIGNMENT - 1)) / FST_ALIGNMENT * FST_ALIGNMENT;
 
-    for _ in fst_list_offset_without_padding..fst_list_offset {
-        writer.write_all(&[0])?;
-    }
+    let apploader_offset = header.data.len();
+    let dol_offset = align(apploader_offset + apploader.data.len(), DOL_ALIGNMENT);
+    let fst_list_offset = align(dol_offset + dol.data.len(), FST_ALIGNMENT);
 
     let mut fst_len = 12;
     for (_, node) in root
@@ -66,12 +100,6 @@ where
         fst_len = calculate_fst_len(fst_len, node);
     }
 
-    for _ in 0..fst_len {
-        // TODO Seems suboptimal
-        // Should not be a problem with BufWriter though
-        writer.write_all(&[0])?;
-    }
-
     let root_fst = FstEntry {
         kind: FstNodeType::Directory,
         ..Default::default()
@@ -80,6 +108,7 @@ where
     // Placeholder FST entry for the root
     let mut output_fst = vec![root_fst];
     let mut fst_name_bank = Vec::new();
+    let mut files = Vec::new();
 
     for (_, node) in root
         .children
@@ -87,33 +116,138 @@ where
         .enumerate()
         .filter(|&(i, _)| i != sys_index)
     {
-        do_output_prep(node, &mut output_fst, &mut fst_name_bank, &mut writer, 0)?;
+        do_output_prep(node, &mut output_fst, &mut fst_name_bank, &mut files, 0);
     }
 
     // Add actual root FST entry
     output_fst[0].file_size_next_dir_index = output_fst.len();
 
-    writer.seek(SeekFrom::Start(fst_list_offset as u64))?;
+    let mut files = layout_files(files, fst_list_offset + fst_len, options)?;
+    for &(offset, fst_index, _) in &files {
+        output_fst[fst_index].file_offset_parent_dir = offset;
+    }
+    // Empty files may share their offset with the following file, so they
+    // need to come first.
+    files.sort_by_key(|&(offset, _, file)| (offset, file.data.len()));
+
+    let disc_end = files
+        .iter()
+        .map(|&(offset, _, file)| offset + file.data.len())
+        .max()
+        .unwrap_or(fst_list_offset + fst_len);
+    if options.limit_size {
+        ensure!(
+            disc_end <= DISC_SIZE,
+            "The disc is {} bytes too large to fit onto a GameCube disc",
+            disc_end - DISC_SIZE
+        );
+    }
 
+    let mut fst = Vec::with_capacity(fst_len);
     for entry in &output_fst {
-        writer.write_u8(entry.kind as u8)?;
-        writer.write_u8(0)?;
-        writer.write_u16::<BE>(entry.file_name_offset as u16)?;
-        writer.write_i32::<BE>(entry.file_offset_parent_dir as i32)?;
-        writer.write_i32::<BE>(entry.file_size_next_dir_index as i32)?;
+        fst.write_u8(entry.kind as u8)?;
+        fst.write_u8(0)?;
+        fst.write_u16::<BE>(entry.file_name_offset as u16)?;
+        fst.write_i32::<BE>(entry.file_offset_parent_dir as i32)?;
+        fst.write_i32::<BE>(entry.file_size_next_dir_index as i32)?;
     }
+    fst.extend_from_slice(&fst_name_bank);
+
+    let mut header = header.data.load()?.into_owned();
+    ensure!(
+        header.len() >= OFFSET_FST_SIZE + 8,
+        "The iso.hdr is too small"
+    );
+    BE::write_u32(&mut header[OFFSET_DOL_OFFSET..], dol_offset as u32);
+    BE::write_u32(&mut header[OFFSET_FST_OFFSET..], fst_list_offset as u32);
+    BE::write_u32(&mut header[OFFSET_FST_SIZE..], fst_len as u32);
+    BE::write_u32(&mut header[OFFSET_FST_SIZE + 4..], fst_len as u32);
+
+    writer.write_all(&header)?;
+    apploader.data.write_to(&mut writer)?;
+    write_padding(&mut writer, dol_offset - apploader_offset - apploader.data.len())?;
+    dol.data.write_to(&mut writer)?;
+    write_padding(&mut writer, fst_list_offset - dol_offset - dol.data.len())?;
+    writer.write_all(&fst)?;
+
+    let mut padding = Padding {
+        junk: if options.junk_padding {
+            Some(JunkStream::new(&header[..4], header[6]))
+        } else {
+            None
+        },
+        buf: [0; 0x1000],
+    };
 
-    writer.write_all(&fst_name_bank)?;
+    let mut pos = fst_list_offset + fst_len;
+    for (offset, _, file) in files {
+        ensure!(
+            offset >= pos,
+            "The file at offset {:#x} overlaps with the data in front of it",
+            offset
+        );
+        padding.write(&mut writer, pos, offset - pos)?;
+        file.data.write_to(&mut writer)?;
+        pos = offset + file.data.len();
+    }
+
+    let end = if options.junk_padding {
+        cmp::max(DISC_SIZE, align(pos, 32))
+    } else {
+        align(pos, 32)
+    };
+    padding.write(&mut writer, pos, end - pos)?;
+
+    writer.flush()?;
+
+    Ok(())
+}
 
-    writer.seek(SeekFrom::Start(OFFSET_DOL_OFFSET as u64))?;
-    writer.write_u32::<BE>(dol_offset as u32)?;
-    writer.write_u32::<BE>(fst_list_offset as u32)?;
-    writer.write_u32::<BE>(fst_len as u32)?;
-    writer.write_u32::<BE>(fst_len as u32)?;
+fn align(value: usize, alignment: usize) -> usize {
+    (value + (alignment - 1)) / alignment * alignment
+}
 
+fn write_padding<W: Write>(writer: &mut W, len: usize) -> io::Result<()> {
+    static ZEROES: [u8; 0x1000] = [0; 0x1000];
+    let mut remaining = len;
+    while remaining > 0 {
+        let len = cmp::min(remaining, ZEROES.len());
+        writer.write_all(&ZEROES[..len])?;
+        remaining -= len;
+    }
     Ok(())
 }
 
+struct Padding {
+    junk: Option<JunkStream>,
+    buf: [u8; 0x1000],
+}
+
+impl Padding {
+    fn write<W: Write>(&mut self, writer: &mut W, offset: usize, len: usize) -> io::Result<()> {
+        let junk = match self.junk {
+            Some(ref mut junk) => junk,
+            None => return write_padding(writer, len),
+        };
+
+        // The junk data only starts at the next 4 byte boundary, the bytes
+        // right after a file are zeroes.
+        let zeroes = cmp::min(align(offset, 4) - offset, len);
+        write_padding(writer, zeroes)?;
+
+        let mut pos = offset + zeroes;
+        let end = offset + len;
+        while pos < end {
+            let len = cmp::min(end - pos, self.buf.len());
+            junk.fill(pos as u64, &mut self.buf[..len]);
+            writer.write_all(&self.buf[..len])?;
+            pos += len;
+        }
+
+        Ok(())
+    }
+}
+
 fn calculate_fst_len(mut cur_value: usize, node: &Node) -> usize {
     match *node {
         Node::Directory(ref dir) => {
@@ -130,16 +264,68 @@ fn calculate_fst_len(mut cur_value: usize, node: &Node) -> usize {
     cur_value
 }
 
-fn do_output_prep<W>(
-    node: &Node,
+/// Determines the offsets of all the files. Returns the offset, the index of
+/// the file's FST entry and the file itself.
+fn layout_files<'b, 'a: 'b>(
+    files: Vec<(usize, &'b File<'a>)>,
+    data_start: usize,
+    options: &WriteOptions,
+) -> Result<Vec<(usize, usize, &'b File<'a>)>, Error> {
+    ensure!(
+        options.file_alignment > 0,
+        "The file alignment needs to be larger than 0"
+    );
+
+    let mut placed = Vec::with_capacity(files.len());
+    let mut remaining = Vec::new();
+    let mut pos = data_start;
+
+    if options.layout == Layout::Original {
+        let mut originals = files
+            .iter()
+            .filter_map(|&(fst_index, file)| file.original_offset.map(|o| (o, fst_index, file)))
+            .collect::<Vec<_>>();
+        originals.sort_by_key(|&(offset, _, _)| offset);
+
+        // A file stays where it was if it starts behind everything that is
+        // already placed and fits in front of the file that originally
+        // followed it. The original offsets may overlap or be shared by
+        // empty files, so anything else gets appended instead.
+        for (index, &(offset, fst_index, file)) in originals.iter().enumerate() {
+            let slot_end = originals
+                .get(index + 1)
+                .map_or(usize::max_value(), |&(next, _, _)| next);
+            if offset >= pos && offset + file.data.len() <= slot_end {
+                placed.push((offset, fst_index, file));
+                pos = offset + file.data.len();
+            }
+        }
+
+        for &(fst_index, file) in &files {
+            if !placed.iter().any(|&(_, i, _)| i == fst_index) {
+                remaining.push((fst_index, file));
+            }
+        }
+    } else {
+        remaining = files;
+    }
+
+    for (fst_index, file) in remaining {
+        let offset = align(pos, options.file_alignment);
+        placed.push((offset, fst_index, file));
+        pos = offset + file.data.len();
+    }
+
+    Ok(placed)
+}
+
+fn do_output_prep<'b, 'a: 'b>(
+    node: &'b Node<'a>,
     output_fst: &mut Vec<FstEntry>,
     fst_name_bank: &mut Vec<u8>,
-    writer: &mut W,
+    files: &mut Vec<(usize, &'b File<'a>)>,
     mut cur_parent_dir_index: usize,
-) -> Result<(), Error>
-where
-    W: Write + Seek,
-{
+) {
     match *node {
         Node::Directory(ref dir) => {
             let fst_ent = FstEntry {
@@ -163,16 +349,17 @@ where
                     child,
                     output_fst,
                     fst_name_bank,
-                    writer,
+                    files,
                     cur_parent_dir_index,
-                )?;
+                );
             }
 
             let dir_end_index = output_fst.len();
             output_fst[this_dir_index].file_size_next_dir_index = dir_end_index;
         }
         Node::File(ref file) => {
-            let mut fst_ent = FstEntry {
+            // The offset gets filled in once the layout is determined.
+            let fst_ent = FstEntry {
                 kind: FstNodeType::File,
                 file_size_next_dir_index: file.data.len(),
                 file_name_offset: fst_name_bank.len(),
@@ -182,21 +369,9 @@ where
             fst_name_bank.extend_from_slice(file.name.as_bytes());
             fst_name_bank.push(0);
 
-            let pos = writer.seek(SeekFrom::Current(0))?;
-            let new_pos = pos + (32 - (pos % 32)) % 32;
-            writer.seek(SeekFrom::Start(new_pos))?;
-
-            fst_ent.file_offset_parent_dir = new_pos as usize;
-
-            writer.write_all(&file.data)?;
-
-            for _ in 0..(32 - (file.data.len() % 32)) % 32 {
-                writer.write_all(&[0])?;
-            }
+            files.push((output_fst.len(), file));
 
             output_fst.push(fst_ent);
         }
     }
-
-    Ok(())
 }
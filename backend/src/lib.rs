@@ -1,62 +1,160 @@
 extern crate byteorder;
+extern crate bzip2_rs;
 extern crate encoding_rs;
+extern crate flate2;
 #[macro_use]
 extern crate failure;
+extern crate glob;
 extern crate goblin;
 extern crate image;
-extern crate regex;
+extern crate lzma_rs;
+extern crate ruzstd;
 extern crate rustc_demangle;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde;
+extern crate sha1;
 extern crate standalone_syn as syn;
 extern crate toml;
 extern crate zip;
+#[cfg(not(target_arch = "wasm32"))]
+extern crate zstd;
 
+mod archive;
 mod assembler;
 mod banner;
+mod bindgen;
 mod config;
+mod delta;
 mod demangle;
 mod dol;
+mod dolphin;
 mod file_source;
 mod framework_map;
+mod gecko;
+mod header;
 pub mod iso;
 mod key_val_print;
 mod linker;
+mod mangle;
+mod original;
+mod yaz0;
 
+use archive::Archive;
 use assembler::Assembler;
 use assembler::Instruction;
 use banner::Banner;
-use config::Config;
+use config::{Build, Config, FileReplacement, Prelinked, RenamedFile, SymbolAddress};
+use delta::DeltaFormat;
 use dol::DolFile;
 use failure::{err_msg, Error, ResultExt};
 use file_source::{FileSource, FileSystem};
-use iso::virtual_file_system::Directory;
+use framework_map::{GameSymbol, SymbolKind};
+use iso::formats::DiscFormat;
+use iso::virtual_file_system::{Directory, ReadSeek};
+pub use iso::writer::WriteOptions;
 pub use key_val_print::{DontPrint, KeyValPrint, MessageKind};
 use std::collections::HashMap;
 use std::fs::{self, File, OpenOptions};
-use std::io::{prelude::*, BufReader, BufWriter};
+use std::io::{prelude::*, BufReader, BufWriter, Cursor};
 use std::mem;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 use zip::{write::FileOptions, ZipArchive, ZipWriter};
 
-pub fn build<P: KeyValPrint>(printer: &P, debug: bool, patch: bool) -> Result<(), Error> {
+pub fn build<P: KeyValPrint>(
+    printer: &P,
+    debug: bool,
+    patch: bool,
+    gecko: bool,
+    target: Option<String>,
+    all_targets: bool,
+) -> Result<(), Error> {
     let mut toml_buf = String::new();
     File::open("RomHack.toml")
         .context("Couldn't find \"RomHack.toml\".")?
         .read_to_string(&mut toml_buf)
         .context("Failed to read \"RomHack.toml\".")?;
 
-    let config: Config = toml::from_str(&toml_buf).context("Can't parse RomHack.toml")?;
+    let mut config: Config = toml::from_str(&toml_buf).context("Can't parse RomHack.toml")?;
+
+    if !all_targets {
+        let compiled_lib = compile(printer, debug, &mut config, target.as_ref().map(|t| &t[..]))?;
+        return if patch {
+            build_patch(printer, compiled_lib, config)
+        } else if gecko {
+            build_gecko(printer, compiled_lib, config)
+        } else {
+            build_and_emit_iso(printer, FileSystem, compiled_lib, config)
+        };
+    }
+
+    let mut targets = config.targets.keys().cloned().collect::<Vec<_>>();
+    ensure!(!targets.is_empty(), "RomHack.toml doesn't specify any targets");
+    targets.sort();
+
+    // All the targets get stored as variants of a single patch.
+    let mut zip = if patch {
+        printer.print(None, "Creating", "patch file");
+
+        config.build.iso.set_extension("patch");
+        Some(ZipWriter::new(BufWriter::new(
+            File::create(&config.build.iso).context("Couldn't create the patch file")?,
+        )))
+    } else {
+        None
+    };
+    let mut game_ids = Vec::new();
+
+    for target in targets {
+        // The configuration gets consumed by building a target, so every
+        // target starts from a freshly parsed one.
+        let mut config: Config = toml::from_str(&toml_buf).context("Can't parse RomHack.toml")?;
+        let compiled_lib = compile(printer, debug, &mut config, Some(&target))
+            .with_context(|_| format!("Couldn't build the target \"{}\"", target))?;
+
+        if let Some(zip) = &mut zip {
+            let game_id = store_patch(printer, zip, compiled_lib, config, true)
+                .with_context(|_| format!("Couldn't build the target \"{}\"", target))?;
+            ensure!(
+                !game_ids.contains(&game_id),
+                "Multiple targets are built for the game {}",
+                game_id
+            );
+            game_ids.push(game_id);
+        } else if gecko {
+            build_gecko(printer, compiled_lib, config)
+                .with_context(|_| format!("Couldn't build the target \"{}\"", target))?;
+        } else {
+            build_and_emit_iso(printer, FileSystem, compiled_lib, config)
+                .with_context(|_| format!("Couldn't build the target \"{}\"", target))?;
+        }
+    }
+
+    Ok(())
+}
 
-    printer.print(None, "Compiling", "");
+/// Applies the target to the configuration and compiles the Rom Hack for it.
+/// Returns the compiled static library.
+fn compile<P: KeyValPrint>(
+    printer: &P,
+    debug: bool,
+    config: &mut Config,
+    target: Option<&str>,
+) -> Result<Vec<u8>, Error> {
+    let mut rust_flags = String::from("-C target-feature=+msync,+fres,+frsqrte");
+    if let Some(target) = target {
+        config.apply_target(target)?;
+        rust_flags.push_str(&format!(" --cfg romhack_target=\"{}\"", target));
+    }
+
+    printer.print(None, "Compiling", target.unwrap_or(""));
 
     {
         let mut command = Command::new("cargo");
         command
             .args(&["build", "--target", "powerpc-unknown-linux-gnu"])
-            .env("RUSTFLAGS", "-C target-feature=+msync,+fres,+frsqrte");
+            .env("RUSTFLAGS", rust_flags);
 
         if !debug {
             command.arg("--release");
@@ -79,11 +177,7 @@ pub fn build<P: KeyValPrint>(printer: &P, debug: bool, patch: bool) -> Result<()
     let compiled_lib =
         fs::read(path_to_compiled_lib).context("Couldn't read the compiled static library")?;
 
-    if patch {
-        build_patch(printer, compiled_lib, config)
-    } else {
-        build_and_emit_iso(printer, FileSystem, compiled_lib, config)
-    }
+    Ok(compiled_lib)
 }
 
 pub fn apply_patch<P: KeyValPrint>(
@@ -91,29 +185,174 @@ pub fn apply_patch<P: KeyValPrint>(
     patch: PathBuf,
     original_game: PathBuf,
     output: PathBuf,
+    force: bool,
 ) -> Result<(), Error> {
+    let patch = fs::read(patch).context("Couldn't read the patch file")?;
+    if DeltaFormat::detect(&patch).is_some() {
+        return apply_delta(printer, &patch, original_game, output, force);
+    }
+
+    let game_id = game_id(&load_game(&original_game)?)?;
+
     printer.print(None, "Parsing", "patch");
 
-    let (zip, compiled_library, mut config) = open_config_from_patch(BufReader::new(
-        File::open(patch).context("Couldn't open the patch file")?,
-    ))?;
+    let (zip, compiled_library, mut config) =
+        open_config_from_patch(Cursor::new(patch), &game_id)?;
 
     config.src.iso = original_game;
     config.build.iso = output;
 
+    if force && config.original.take().is_some() {
+        printer.print(
+            Some(MessageKind::Warning),
+            "Warning",
+            "Not verifying that the patch matches the game",
+        );
+    }
+
     build_and_emit_iso(printer, zip, compiled_library, config)
 }
 
+/// Applies a VCDIFF or BPS patch to the game. The patch applies to the plain
+/// disc image, so the original game may be in any of the supported disc image
+/// formats.
+fn apply_delta<P: KeyValPrint>(
+    printer: &P,
+    patch: &[u8],
+    original_game: PathBuf,
+    output: PathBuf,
+    force: bool,
+) -> Result<(), Error> {
+    ensure!(
+        DiscFormat::from_path(&output) == DiscFormat::Iso,
+        "Binary patches produce a plain disc image, so the Rom Hack needs to be written as an ISO"
+    );
+
+    ensure!(
+        !is_same_file(&original_game, &output)?,
+        "The Rom Hack can't be written over the original game"
+    );
+
+    printer.print(None, "Loading", "original game");
+
+    let original = open_disc_image(&original_game)?;
+
+    printer.print(None, "Applying", "patch");
+
+    // The patch is applied to a temporary file, so a patch that turns out not
+    // to match the game doesn't leave a broken disc image behind.
+    let mut temp = output.clone().into_os_string();
+    temp.push(".tmp");
+    let temp = PathBuf::from(temp);
+
+    let result = delta::apply(
+        patch,
+        original,
+        || {
+            Ok(OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(&temp)
+                .with_context(|_| format!("Couldn't create \"{}\".", temp.display()))?)
+        },
+        !force,
+    );
+    if let Err(e) = result {
+        let _ = fs::remove_file(&temp);
+        return Err(e.context("Couldn't apply the patch").into());
+    }
+
+    fs::rename(&temp, &output)
+        .with_context(|_| format!("Couldn't create \"{}\".", output.display()))?;
+
+    Ok(())
+}
+
+/// Checks whether both paths refer to the same file, which is only possible
+/// if the second one already exists.
+fn is_same_file(a: &Path, b: &Path) -> Result<bool, Error> {
+    if !b.exists() {
+        return Ok(false);
+    }
+    let a = a
+        .canonicalize()
+        .with_context(|_| format!("Couldn't open \"{}\".", a.display()))?;
+    let b = b
+        .canonicalize()
+        .with_context(|_| format!("Couldn't open \"{}\".", b.display()))?;
+    Ok(a == b)
+}
+
+/// Opens a disc image of the game for creating or applying binary patches.
+/// Compressed images are exposed as the plain disc image they contain, so
+/// patches don't depend on the format of the user's copy of the game.
+fn open_disc_image<'a>(path: &Path) -> Result<Box<ReadSeek + 'a>, Error> {
+    ensure!(
+        !path.is_dir(),
+        "Binary patches can only be created from and applied to a disc image of the game"
+    );
+    let file =
+        File::open(path).with_context(|_| format!("Couldn't open \"{}\".", path.display()))?;
+    let mut image = iso::formats::open(BufReader::new(file))
+        .with_context(|_| format!("Couldn't load \"{}\".", path.display()))?;
+    ensure!(
+        !iso::reader::is_nkit(&mut image)?,
+        "\"{}\" is an NKit image, which doesn't match the original disc. \
+         Binary patches need a regular disc image.",
+        path.display()
+    );
+    Ok(image)
+}
+
+/// Reads the game ID, including the maker code, from the game's disc header.
+pub fn game_id(iso: &Directory) -> Result<String, Error> {
+    Ok(original::identify(iso)?.0)
+}
+
+/// Opens the patch index and the compiled library of the patch. Patches may
+/// contain variants for multiple games, in which case the variant for the
+/// given game ID is opened.
 pub fn open_config_from_patch<R: Read + Seek>(
     reader: R,
+    game_id: &str,
 ) -> Result<(ZipArchive<R>, Vec<u8>, Config), Error> {
     let mut zip = ZipArchive::new(reader).context("Couldn't parse patch file")?;
 
+    let prefix = if zip.by_name("RomHack.toml").is_ok() {
+        String::new()
+    } else {
+        let prefix = format!("{}/", game_id);
+        if zip.by_name(&format!("{}RomHack.toml", prefix)).is_err() {
+            let mut game_ids = Vec::new();
+            for index in 0..zip.len() {
+                let file = zip.by_index(index).context("Couldn't read the patch file")?;
+                if let Some(id) = file.name().split('/').next() {
+                    if file.name() == format!("{}/RomHack.toml", id) {
+                        game_ids.push(id.to_owned());
+                    }
+                }
+            }
+            ensure!(
+                !game_ids.is_empty(),
+                "The patch file doesn't contain the patch index"
+            );
+            game_ids.sort();
+            bail!(
+                "The patch doesn't support the game {}. It supports: {}",
+                game_id,
+                game_ids.join(", ")
+            );
+        }
+        prefix
+    };
+
     let mut buffer = Vec::new();
 
     let config: Config = {
         let mut toml_file = zip
-            .by_name("RomHack.toml")
+            .by_name(&format!("{}RomHack.toml", prefix))
             .context("The patch file doesn't contain the patch index")?;
 
         toml_file
@@ -125,7 +364,7 @@ pub fn open_config_from_patch<R: Read + Seek>(
 
     {
         let mut compiled_library = zip
-            .by_name("libcompiled.a")
+            .by_name(&format!("{}libcompiled.a", prefix))
             .context("The patch file doesn't contain the compiled library")?;
         buffer.clear();
         compiled_library
@@ -148,37 +387,125 @@ fn build_patch<P: KeyValPrint>(
         File::create(&config.build.iso).context("Couldn't create the patch file")?,
     ));
 
+    store_patch(printer, &mut zip, compiled_library, config, false)?;
+
+    Ok(())
+}
+
+/// Stores the Rom Hack in the patch. A variant gets stored in a folder named
+/// after the game ID, so a patch can contain variants for multiple games.
+/// Returns the game ID of the original game.
+fn store_patch<P: KeyValPrint, W: Write + Seek>(
+    printer: &P,
+    zip: &mut ZipWriter<W>,
+    compiled_library: Vec<u8>,
+    mut config: Config,
+    variant: bool,
+) -> Result<String, Error> {
+    printer.print(None, "Loading", "original game");
+
+    let iso = load_game(&config.src.iso)?;
+
+    let replacements = expand_replacements(&mut FileSystem, &config.files.replace)?
+        .into_iter()
+        .map(|(iso_path, replacement, actual_path)| (iso_path, replacement.yaz0(), actual_path))
+        .collect::<Vec<_>>();
+
+    printer.print(None, "Hashing", "original files");
+
+    // The replaced files are located after the renames, so they get mapped back
+    // to the files of the original game. Files inside of archives are checked
+    // by hashing the whole archive. The removed and renamed files need to match
+    // as well.
+    let hashed = {
+        let renames = &config.files.rename;
+        replacements
+            .iter()
+            .map(|&(ref path, _, _)| {
+                let path = original_path(renames, path);
+                let archive_path = split_archive_path(&iso, &path)
+                    .map(|(archive_path, _)| archive_path.to_owned());
+                archive_path.unwrap_or(path)
+            }).chain(config.files.remove.iter().cloned())
+            .chain(
+                renames
+                    .iter()
+                    .enumerate()
+                    .map(|(index, rename)| original_path(&renames[..index], &rename.from)),
+            ).collect::<Vec<_>>()
+    };
+    let original = original::record(&iso, hashed.iter().map(|p| &p[..]))
+        .context("Couldn't hash the original game")?;
+
+    let prefix = if variant {
+        format!("{}/", original.game_id)
+    } else {
+        String::new()
+    };
+    let game_id = original.game_id.clone();
+    config.original = Some(original);
+
     printer.print(None, "Storing", "replacement files");
 
     let mut new_map = HashMap::new();
-    for (index, (iso_path, actual_path)) in config.files.iter().enumerate() {
-        let zip_path = format!("replace{}.dat", index);
-        new_map.insert(iso_path.clone(), PathBuf::from(&zip_path));
+    for (index, (iso_path, yaz0, actual_path)) in replacements.into_iter().enumerate() {
+        let zip_path = format!("{}replace{}.dat", prefix, index);
+        // All the replacements are stored as tables, as TOML doesn't allow
+        // plain values to follow the tables in the patch index.
+        new_map.insert(
+            iso_path,
+            FileReplacement::Detailed {
+                path: PathBuf::from(&zip_path),
+                yaz0,
+            },
+        );
         zip.start_file(zip_path, FileOptions::default())
             .context("Failed creating a new patch file entry")?;
 
-        zip.write_all(&fs::read(actual_path).with_context(|_| {
+        zip.write_all(&fs::read(&actual_path).with_context(|_| {
             format!(
                 "Couldn't read the file \"{}\" to store it in the patch.",
                 actual_path.display()
             )
         })?).context("Failed storing a file in the patch")?;
     }
-    config.files = new_map;
+    config.files.replace = new_map;
+
+    if config.build.prelink {
+        let LinkedRomHack {
+            dol, instructions, ..
+        } = link(printer, &mut FileSystem, &iso, compiled_library.clone(), &config)?;
+
+        printer.print(None, "Storing", "linked Rom Hack");
+
+        let prelinked = Prelinked {
+            dol: PathBuf::from(format!("{}linked.dol", prefix)),
+            instructions: PathBuf::from(format!("{}instructions.dat", prefix)),
+        };
+        zip.start_file(prelinked.dol.to_string_lossy(), FileOptions::default())
+            .context("Failed creating a new patch file entry for the linked Rom Hack")?;
+        zip.write_all(&dol.to_bytes())
+            .context("Failed storing the linked Rom Hack in the patch")?;
+        zip.start_file(prelinked.instructions.to_string_lossy(), FileOptions::default())
+            .context("Failed creating a new patch file entry for the assembled patch")?;
+        zip.write_all(&Instruction::encode_all(&instructions))
+            .context("Failed storing the assembled patch in the patch")?;
+        config.link.prelinked = Some(prelinked);
+    }
 
     printer.print(None, "Storing", "libraries");
 
-    zip.start_file("libcompiled.a", FileOptions::default())
+    zip.start_file(format!("{}libcompiled.a", prefix), FileOptions::default())
         .context("Failed creating a new patch file entry for the compiled library")?;
     zip.write_all(&compiled_library)
         .context("Failed storing the compiled library in the patch")?;
 
-    for (index, lib_path) in config.link.libs.iter().flat_map(|x| x).enumerate() {
-        let zip_path = format!("lib{}.a", index);
-        zip.start_file(zip_path, FileOptions::default())
+    for (index, lib_path) in config.link.libs.iter_mut().flat_map(|x| x).enumerate() {
+        let zip_path = format!("{}lib{}.a", prefix, index);
+        zip.start_file(&*zip_path, FileOptions::default())
             .context("Failed creating a new patch file entry")?;
 
-        let file_buf = fs::read(lib_path).with_context(|_| {
+        let file_buf = fs::read(&*lib_path).with_context(|_| {
             format!(
                 "Couldn't load \"{}\". Did you build the project correctly?",
                 lib_path.display()
@@ -186,137 +513,285 @@ fn build_patch<P: KeyValPrint>(
         })?;
         zip.write_all(&file_buf)
             .context("Failed storing a library in the patch")?;
+        *lib_path = PathBuf::from(zip_path);
+    }
+
+    let maps = config
+        .src
+        .map
+        .iter_mut()
+        .chain(&mut config.src.maps)
+        .filter(|path| iso.resolve_path(path).is_none() && Path::new(path).is_file());
+    for (index, path) in maps.enumerate() {
+        printer.print(None, "Storing", "symbol map");
+
+        let zip_path = format!("{}map{}.map", prefix, index);
+        zip.start_file(&*zip_path, FileOptions::default())
+            .context("Failed to create the symbol map file in the patch")?;
+        let file_buf = fs::read(&*path)
+            .with_context(|_| format!("Couldn't read the symbol map \"{}\"", path))?;
+        zip.write_all(&file_buf)
+            .context("Failed storing the symbol map in the patch")?;
+        *path = zip_path;
     }
 
     if let Some(path) = &mut config.src.patch {
         printer.print(None, "Storing", "patch.asm");
 
-        zip.start_file("patch.asm", FileOptions::default())
+        let zip_path = format!("{}patch.asm", prefix);
+        zip.start_file(&*zip_path, FileOptions::default())
             .context("Failed to create the patch.asm file in the patch")?;
         let file_buf = fs::read(&*path).context("Couldn't read the patch.asm file")?;
         zip.write_all(&file_buf)
             .context("Failed storing the patch.asm file in the patch")?;
-        *path = PathBuf::from("patch.asm");
+        *path = PathBuf::from(zip_path);
     }
 
     if let Some(path) = &mut config.info.image {
         printer.print(None, "Storing", "banner");
 
-        zip.start_file("banner.dat", FileOptions::default())
+        let zip_path = format!("{}banner.dat", prefix);
+        zip.start_file(&*zip_path, FileOptions::default())
             .context("Failed to create the banner file in the patch")?;
         let file_buf = fs::read(&*path).context("Couldn't read the banner file")?;
         zip.write_all(&file_buf)
             .context("Failed storing the banner file in the patch")?;
-        *path = PathBuf::from("banner.dat");
+        *path = PathBuf::from(zip_path);
     }
 
     printer.print(None, "Storing", "patch index");
 
     config.src.iso = PathBuf::new();
-    config.build = Default::default();
-    zip.start_file("RomHack.toml", FileOptions::default())
+    config.targets.clear();
+    config.build = Build {
+        junk_padding: config.build.junk_padding,
+        layout: config.build.layout,
+        file_alignment: config.build.file_alignment,
+        limit_size: config.build.limit_size,
+        ..Default::default()
+    };
+    zip.start_file(format!("{}RomHack.toml", prefix), FileOptions::default())
         .context("Failed to create the patch index")?;
     let config = toml::to_vec(&config).context("Couldn't encode the patch index")?;
     zip.write_all(&config)
         .context("Failed storing the patch index")?;
 
-    Ok(())
+    Ok(game_id)
 }
 
-pub fn build_iso<'a, P: KeyValPrint, F: FileSource>(
+/// Builds the Rom Hack as Gecko codes, both as a GCT file and as a Dolphin game
+/// settings file.
+fn build_gecko<P: KeyValPrint>(
     printer: &P,
-    mut files: F,
-    original_iso: &'a [u8],
     compiled_library: Vec<u8>,
-    config: &'a mut Config,
-) -> Result<Directory<'a>, Error> {
-    let mut iso = iso::reader::load_iso(original_iso).context("Couldn't parse the ISO")?;
+    mut config: Config,
+) -> Result<(), Error> {
+    printer.print(None, "Loading", "original game");
 
-    printer.print(None, "Replacing", "files");
+    let iso = load_game(&config.src.iso)?;
 
-    for (iso_path, actual_path) in &config.files {
-        iso.resolve_and_create_path(iso_path).data = files
-            .read_to_vec(actual_path)
-            .with_context(|_| {
-                format!(
-                    "Couldn't read the file \"{}\" to store it in the ISO.",
-                    actual_path.display()
-                )
-            })?.into();
-    }
-
-    let mut original_symbols = HashMap::new();
-    if let Some(framework_map) = config.src.map.as_ref().and_then(|m| iso.resolve_path(m)) {
-        printer.print(None, "Parsing", "symbol map");
-        original_symbols = framework_map::parse(&framework_map.data)
-            .context("Couldn't parse the game's symbol map")?;
-    } else {
+    if !config.files.replace.is_empty()
+        || !config.files.remove.is_empty()
+        || !config.files.rename.is_empty()
+    {
         printer.print(
             Some(MessageKind::Warning),
             "Warning",
-            "No symbol map specified or it wasn't found",
+            "Gecko codes can't change the files of the game",
         );
     }
 
-    printer.print(None, "Linking", "");
+    let linked = link(printer, &mut FileSystem, &iso, compiled_library, &config)?;
 
-    let mut libs_to_link = Vec::with_capacity(config.link.libs.as_ref().map_or(0, |x| x.len()) + 2);
+    printer.print(None, "Creating", "Gecko codes");
 
-    libs_to_link.push(compiled_library);
+    let codes = gecko::create(&linked.dol, &linked.instructions);
+    let name = config.info.game_name.as_ref().map_or("Rom Hack", |n| &n[..]);
 
-    for lib_path in config.link.libs.iter().flat_map(|x| x) {
-        let mut file_buf = files.read_to_vec(lib_path).with_context(|_| {
-            format!(
-                "Couldn't load \"{}\". Did you build the project correctly?",
-                lib_path.display()
-            )
-        })?;
-        libs_to_link.push(file_buf);
+    config.build.iso.set_extension("gct");
+    fs::write(&config.build.iso, gecko::to_gct(&codes)).context("Couldn't write the GCT file")?;
+
+    let settings_dir = config
+        .build
+        .iso
+        .parent()
+        .map_or_else(PathBuf::new, Path::to_owned)
+        .join("GameSettings");
+    fs::create_dir_all(&settings_dir).context("Couldn't create the GameSettings folder")?;
+    fs::write(
+        settings_dir.join(format!("{}.ini", game_id(&iso)?)),
+        gecko::to_ini(name, &codes),
+    ).context("Couldn't write the game settings file")?;
+
+    Ok(())
+}
+
+pub fn build_iso<'a, P: KeyValPrint, F: FileSource>(
+    printer: &P,
+    files: F,
+    iso: Directory<'a>,
+    compiled_library: Vec<u8>,
+    config: &'a mut Config,
+) -> Result<Directory<'a>, Error> {
+    build_game(printer, files, iso, compiled_library, config).map(|(iso, _)| iso)
+}
+
+/// Builds the Rom Hack into the game. The files for Dolphin are created as well
+/// if they are configured and the Rom Hack isn't prelinked.
+fn build_game<'a, P: KeyValPrint, F: FileSource>(
+    printer: &P,
+    mut files: F,
+    mut iso: Directory<'a>,
+    compiled_library: Vec<u8>,
+    config: &'a mut Config,
+) -> Result<(Directory<'a>, Option<DolphinFiles>), Error> {
+    if let Some(original) = &config.original {
+        printer.print(None, "Verifying", "original game");
+
+        original::verify(&iso, original).context(
+            "The patch doesn't match the game. Use the original, unmodified copy of the game.",
+        )?;
     }
 
-    libs_to_link.push(linker::BASIC_LIB.to_owned());
+    if !config.files.remove.is_empty() {
+        printer.print(None, "Removing", "files");
+    }
 
-    let base_address: syn::LitInt =
-        syn::parse_str(&config.link.base).context("Invalid Base Address")?;
+    for path in &config.files.remove {
+        ensure!(
+            !path.starts_with("&&systemdata"),
+            "The system data of the game can't be removed."
+        );
+        iso.remove_path(path)
+            .ok_or_else(|| format_err!("Couldn't find \"{}\" to remove it.", path))?;
+    }
 
-    let linked = linker::link(
-        printer,
-        &libs_to_link,
-        base_address.value() as u32,
-        config.link.entries.clone(),
-        &original_symbols,
-    ).context("Couldn't link the Rom Hack")?;
+    if !config.files.rename.is_empty() {
+        printer.print(None, "Renaming", "files");
+    }
 
-    printer.print(None, "Creating", "symbol map");
+    for &RenamedFile { ref from, ref to } in &config.files.rename {
+        ensure!(
+            !from.starts_with("&&systemdata") && !to.starts_with("&&systemdata"),
+            "The system data of the game can't be renamed."
+        );
+        ensure!(
+            iso.resolve_node(to).is_none(),
+            "Couldn't rename \"{}\", as \"{}\" already exists.",
+            from,
+            to
+        );
+        let mut node = iso
+            .remove_path(from)
+            .ok_or_else(|| format_err!("Couldn't find \"{}\" to rename it.", from))?;
+
+        let (dir_path, name) = match to.rfind('/') {
+            Some(index) => (&to[..index], &to[index + 1..]),
+            None => ("", &to[..]),
+        };
+        node.set_name(name);
+        iso.resolve_and_create_dir(dir_path).children.push(node);
+    }
 
-    // TODO NLL bind framework_map to local variable
-    framework_map::create(
-        &config,
-        config
-            .src
-            .map
-            .as_ref()
-            .and_then(|m| iso.resolve_path(m))
-            .map(|f| &*f.data),
-        &linked.sections,
-    ).context("Couldn't create the new symbol map")?;
+    printer.print(None, "Replacing", "files");
 
-    let mut instructions = Vec::new();
-    if let Some(patch) = config.src.patch.take() {
-        printer.print(None, "Parsing", "patch");
+    let mut archive_replacements = HashMap::new();
 
-        let asm = files
-            .read_to_string(&patch)
-            .with_context(|_| format!("Couldn't read the patch file \"{}\".", patch.display()))?;
+    let replacements = expand_replacements(&mut files, &config.files.replace)?;
+    for (iso_path, replacement, actual_path) in replacements {
+        let mut data = files.read_to_vec(&actual_path).with_context(|_| {
+            format!(
+                "Couldn't read the file \"{}\" to store it in the ISO.",
+                actual_path.display()
+            )
+        })?;
 
-        let lines = &asm.lines().collect::<Vec<_>>();
+        if let Some((archive_path, inner_path)) = split_archive_path(&iso, &iso_path) {
+            archive_replacements
+                .entry(archive_path.to_owned())
+                .or_insert_with(Vec::new)
+                .push((inner_path.to_owned(), replacement.yaz0(), data));
+            continue;
+        }
 
-        let mut assembler = Assembler::new(linked.symbol_table, &original_symbols);
-        instructions = assembler
-            .assemble_all_lines(lines)
-            .context("Couldn't assemble the patch file lines")?;
+        let file = iso.resolve_and_create_path(&iso_path);
+
+        let compress = match replacement.yaz0() {
+            Some(compress) => compress,
+            None => yaz0::is_compressed(
+                &file
+                    .data
+                    .read_prefix(0x10)
+                    .with_context(|_| {
+                        format!("Couldn't read the original \"{}\".", iso_path)
+                    })?,
+            ),
+        };
+        if compress && !yaz0::is_compressed(&data) {
+            printer.print(None, "Compressing", &iso_path);
+            data = yaz0::compress(&data);
+        }
+
+        file.data = data.into();
     }
 
+    for (archive_path, replacements) in archive_replacements {
+        printer.print(None, "Rebuilding", &archive_path);
+
+        let file = iso.resolve_and_create_path(&archive_path);
+        let mut archive = Archive::open(
+            &file
+                .data
+                .load()
+                .with_context(|_| format!("Couldn't read the archive \"{}\".", archive_path))?,
+        ).with_context(|_| format!("Couldn't open the archive \"{}\".", archive_path))?;
+
+        for (inner_path, yaz0, data) in replacements {
+            archive
+                .replace_file(&inner_path, data, yaz0)
+                .with_context(|_| {
+                    format!(
+                        "Couldn't replace \"{}\" in the archive \"{}\".",
+                        inner_path, archive_path
+                    )
+                })?;
+        }
+
+        file.data = archive.into_bytes().into();
+    }
+
+    let linked = match config.link.prelinked.take() {
+        Some(prelinked) => {
+            printer.print(None, "Loading", "linked Rom Hack");
+
+            let dol = files
+                .read_to_vec(&prelinked.dol)
+                .context("Couldn't read the linked Rom Hack")?;
+            let instructions = files
+                .read_to_vec(&prelinked.instructions)
+                .context("Couldn't read the assembled patch")?;
+            LinkedRomHack {
+                dol: DolFile::parse(&dol),
+                instructions: Instruction::decode_all(&instructions)
+                    .context("Couldn't parse the assembled patch")?,
+                dolphin_map: None,
+            }
+        }
+        None => link(printer, &mut files, &iso, compiled_library, config)?,
+    };
+
+    let dolphin_files = match linked.dolphin_map {
+        Some(map) => {
+            let name = config.info.game_name.as_ref().map_or("Rom Hack", |n| &n[..]);
+            Some(DolphinFiles {
+                name: name.to_owned(),
+                patch: dolphin::create_patch(name, &linked.dol, &linked.instructions),
+                map,
+            })
+        }
+        None => None,
+    };
+
     {
         printer.print(None, "Patching", "game");
 
@@ -324,8 +799,8 @@ pub fn build_iso<'a, P: KeyValPrint, F: FileSource>(
             .main_dol_mut()
             .ok_or_else(|| err_msg("Dol file not found"))?;
 
-        let original = DolFile::parse(&main_dol.data);
-        main_dol.data = patch_instructions(original, linked.dol, &instructions)
+        let original = DolFile::parse(&main_dol.data.load().context("Couldn't read the dol")?);
+        main_dol.data = patch_instructions(original, linked.dol, &linked.instructions)
             .context("Couldn't patch the game")?
             .into();
     }
@@ -335,8 +810,13 @@ pub fn build_iso<'a, P: KeyValPrint, F: FileSource>(
         if let Some(banner_file) = iso.banner_mut() {
             // TODO Not always true
             let is_japanese = true;
-            let mut banner = Banner::parse(is_japanese, &banner_file.data)
-                .context("Couldn't parse the banner")?;
+            let mut banner = Banner::parse(
+                is_japanese,
+                &banner_file
+                    .data
+                    .load()
+                    .context("Couldn't read the banner")?,
+            ).context("Couldn't parse the banner")?;
 
             if let Some(game_name) = config.info.game_name.take() {
                 banner.game_name = game_name;
@@ -365,6 +845,21 @@ pub fn build_iso<'a, P: KeyValPrint, F: FileSource>(
             printer.print(Some(MessageKind::Warning), "Warning", "No banner to patch");
         }
     }
+    {
+        printer.print(None, "Patching", "disc header");
+
+        let header_file = iso
+            .header_mut()
+            .ok_or_else(|| err_msg("Disc header not found"))?;
+
+        let mut header = header_file
+            .data
+            .load()
+            .context("Couldn't read the disc header")?
+            .into_owned();
+        header::patch(&mut header, &config.disc).context("Couldn't patch the disc header")?;
+        header_file.data = header.into();
+    }
 
     Ok(iso)
 }
@@ -377,23 +872,184 @@ pub fn build_and_emit_iso<P: KeyValPrint, F: FileSource>(
 ) -> Result<(), Error> {
     printer.print(None, "Loading", "original game");
 
-    let buf = iso::reader::load_iso_buf(&config.src.iso)
-        .with_context(|_| format!("Couldn't find \"{}\".", config.src.iso.display()))?;
+    let iso = load_game(&config.src.iso)?;
 
     let out_path = mem::replace(&mut config.build.iso, Default::default());
-
-    let iso = build_iso(printer, files, &buf, compiled_library, &mut config)?;
+    let format = config
+        .build
+        .format
+        .unwrap_or_else(|| DiscFormat::from_path(&out_path));
+    let options = config.build.write_options();
+    let delta_path = config.build.delta.take();
+    let original_path = config.src.iso.clone();
+    let dolphin_dir = config.build.dolphin.clone();
+    let game_id = game_id(&iso)?;
+
+    let (iso, dolphin_files) = build_game(printer, files, iso, compiled_library, &mut config)?;
 
     printer.print(None, "Building", "ISO");
 
-    iso::writer::write_iso(
-        BufWriter::with_capacity(
-            4 << 20,
-            File::create(out_path).context("Couldn't create the final ISO")?,
-        ),
+    iso::formats::write_disc(
+        File::create(&out_path).context("Couldn't create the final ISO")?,
         &iso,
+        format,
+        &options,
     ).context("Couldn't write the final ISO")?;
 
+    if let Some(delta_path) = delta_path {
+        printer.print(None, "Creating", "binary patch");
+
+        let original = open_disc_image(&original_path)?;
+        let built = open_disc_image(&out_path)?;
+        let patch = File::create(&delta_path).with_context(|_| {
+            format!("Couldn't create the binary patch \"{}\".", delta_path.display())
+        })?;
+        delta::create(
+            DeltaFormat::from_path(&delta_path),
+            original,
+            built,
+            BufWriter::new(patch),
+        ).context("Couldn't create the binary patch")?;
+    }
+
+    if let (Some(dir), Some(dolphin_files)) = (dolphin_dir, dolphin_files) {
+        printer.print(None, "Creating", "Dolphin files");
+        write_dolphin_files(&dir, &game_id, dolphin_files)?;
+    }
+
+    Ok(())
+}
+
+/// Adds the patch to the game settings file of the game in Dolphin's user
+/// folder and stores the symbol map next to it.
+fn write_dolphin_files(dir: &Path, game_id: &str, files: DolphinFiles) -> Result<(), Error> {
+    let settings_dir = dir.join("GameSettings");
+    fs::create_dir_all(&settings_dir).context("Couldn't create the GameSettings folder")?;
+    let settings_path = settings_dir.join(format!("{}.ini", game_id));
+    let settings = if settings_path.exists() {
+        fs::read_to_string(&settings_path).context("Couldn't read the game settings file")?
+    } else {
+        String::new()
+    };
+    fs::write(
+        &settings_path,
+        dolphin::add_patch(&settings, &files.name, files.patch),
+    ).context("Couldn't write the game settings file")?;
+
+    let maps_dir = dir.join("Maps");
+    fs::create_dir_all(&maps_dir).context("Couldn't create the Maps folder")?;
+    fs::write(maps_dir.join(format!("{}.map", game_id)), files.map)
+        .context("Couldn't write the Dolphin symbol map")?;
+
+    Ok(())
+}
+
+/// Generates Rust bindings for the functions in the game's symbol maps.
+pub fn bindgen<P: KeyValPrint>(
+    printer: &P,
+    target: Option<String>,
+    output: PathBuf,
+) -> Result<(), Error> {
+    let toml_buf = fs::read_to_string("RomHack.toml").context("Couldn't find \"RomHack.toml\".")?;
+    let mut config: Config = toml::from_str(&toml_buf).context("Can't parse RomHack.toml")?;
+    if let Some(target) = target {
+        config.apply_target(&target)?;
+    }
+
+    printer.print(None, "Loading", "original game");
+
+    let iso = load_game(&config.src.iso)?;
+    let symbols = load_symbols(printer, &mut FileSystem, &iso, &config)?;
+
+    printer.print(None, "Generating", "bindings");
+
+    fs::write(&output, bindgen::generate(&symbols))
+        .with_context(|_| format!("Couldn't write the bindings \"{}\"", output.display()))?;
+
+    Ok(())
+}
+
+/// Demangles a symbol that CodeWarrior or rustc mangled. Symbols that aren't
+/// mangled are returned as they are.
+pub fn demangle_symbol(symbol: &str) -> Result<String, Error> {
+    if let Some(demangled) = demangle::demangle_rust(symbol) {
+        return Ok(demangled);
+    }
+
+    demangle::demangle(symbol)
+        .map(|demangled| demangled.into_owned())
+        .map_err(|e| format_err!("Couldn't demangle \"{}\": {}", symbol, e))
+}
+
+pub fn extract<P: KeyValPrint>(printer: &P, iso: PathBuf, output: PathBuf) -> Result<(), Error> {
+    printer.print(None, "Loading", "game");
+
+    let iso = iso::reader::load_iso_file(&iso)
+        .with_context(|_| format!("Couldn't load \"{}\".", iso.display()))?;
+
+    printer.print(None, "Extracting", "files");
+
+    iso::extracted::export_to_disk(&iso, output).context("Couldn't extract the game")?;
+
+    Ok(())
+}
+
+pub fn pack<P: KeyValPrint>(
+    printer: &P,
+    input: PathBuf,
+    output: PathBuf,
+    options: WriteOptions,
+) -> Result<(), Error> {
+    printer.print(None, "Loading", "extracted game");
+
+    let iso = iso::extracted::import_from_disk(&input).with_context(|_| {
+        format!("Couldn't load the extracted game \"{}\".", input.display())
+    })?;
+
+    printer.print(None, "Building", "ISO");
+
+    let format = DiscFormat::from_path(&output);
+
+    iso::formats::write_disc(
+        File::create(output).context("Couldn't create the ISO")?,
+        &iso,
+        format,
+        &options,
+    ).context("Couldn't write the ISO")?;
+
+    Ok(())
+}
+
+pub fn yaz0_compress<P: KeyValPrint>(
+    printer: &P,
+    input: PathBuf,
+    output: PathBuf,
+) -> Result<(), Error> {
+    let data = fs::read(&input)
+        .with_context(|_| format!("Couldn't read \"{}\".", input.display()))?;
+
+    printer.print(None, "Compressing", &input.display().to_string());
+
+    fs::write(&output, yaz0::compress(&data))
+        .with_context(|_| format!("Couldn't write \"{}\".", output.display()))?;
+
+    Ok(())
+}
+
+pub fn yaz0_decompress<P: KeyValPrint>(
+    printer: &P,
+    input: PathBuf,
+    output: PathBuf,
+) -> Result<(), Error> {
+    let data = fs::read(&input)
+        .with_context(|_| format!("Couldn't read \"{}\".", input.display()))?;
+
+    printer.print(None, "Decompressing", &input.display().to_string());
+
+    let data = yaz0::decompress(&data).context("Couldn't decompress the file")?;
+    fs::write(&output, data)
+        .with_context(|_| format!("Couldn't write \"{}\".", output.display()))?;
+
     Ok(())
 }
 
@@ -413,23 +1069,78 @@ pub fn new(name: &str) -> Result<(), Error> {
         r#"[info]
 game-name = "{0}"
 
+# [disc]
+# game-id = "GZLE99" # Give the Rom Hack its own game ID, so it gets its own saves
+# internal-name = "{0}"
+
 [src]
-iso = "game.iso" # Provide the path of the game's ISO
+iso = "game.iso" # Provide the path of the game's ISO or extracted folder
 patch = "src/patch.asm"
 # Optionally specify the game's symbol map
 # map = "maps/framework.map"
+# Further symbol maps, either in the game or on the harddrive, get merged in.
+# CodeWarrior and Dolphin maps, IDA and Ghidra CSV exports and plain lists of
+# addresses and names are supported.
+# maps = ["maps/reverse-engineered.map"]
 
 [files]
+# Files and folders can be removed and then renamed or moved, in the order they
+# are listed, before any files get replaced:
+# remove = ["path/to/unused/file"]
+# rename = [{{ from = "path/to/old/name", to = "path/to/new/name" }}]
 # You may replace or add new files to the game here
 # "path/to/file/in/iso" = "path/to/file/on/harddrive"
+# Files replacing Yaz0 compressed files get compressed automatically, which
+# can also be controlled explicitly:
+# "path/to/file.szs" = {{ path = "path/to/file.arc", yaz0 = true }}
+# Whole folders and glob patterns can be mapped onto folders in the game:
+# "path/to/folder/in/iso" = "path/to/folder/on/harddrive"
+# "path/to/folder/in/iso/" = "path/to/files/*.bmg"
+# Files inside of RARC and U8 archives can be replaced as well:
+# "path/to/archive.arc/path/to/file" = "path/to/file/on/harddrive"
 
 [build]
 map = "target/framework.map"
 iso = "target/{0}.iso"
+# Optionally compress the game, "iso", "ciso" or "rvz"
+# format = "rvz"
+# Fill the unused space with junk data like on retail discs. This isn't supported
+# for RVZ images.
+# junk-padding = true
+# Keep the files at their original offsets if possible, instead of packing them
+# layout = "original"
+# file-alignment = 32768
+# Fail if the game doesn't fit onto a GameCube disc anymore
+# limit-size = true
+# Store the already linked Rom Hack in patches, so applying them is faster
+# prelink = true
+# Additionally create a binary patch from the original game, either VCDIFF
+# (xdelta) or BPS, depending on the file extension
+# delta = "target/{0}.xdelta"
+# Additionally store the Rom Hack as patches and a symbol map in Dolphin's user
+# folder, so it can be tried out on the original game without building it
+# dolphin = "target/dolphin"
 
 [link]
 entries = ["init"] # Enter the exported function names here
 base = "0x8040_1000" # Enter the start address of the Rom Hack's code here
+
+# You may add symbols of the game that aren't in any of the symbol maps
+# [symbols]
+# "Player::update" = "0x8000_5C40"
+
+# Targets, such as other regions of the game, can override the [src], [link]
+# and [files] sections. Build them with `romhack build --target pal` or
+# `romhack build --all-targets`. The Rust code can check the target with
+# `#[cfg(romhack_target = "pal")]`. Building all the targets as a patch stores
+# them in a single patch that picks the target matching the user's game.
+# [target.pal.src]
+# iso = "game-pal.iso"
+# map = "maps/framework-pal.map"
+# [target.pal.link]
+# base = "0x8040_2000"
+# [target.pal.files]
+# "path/to/file/in/iso" = "path/to/pal/file/on/harddrive"
 "#,
         name.replace('-', "_"),
     ).context("Couldn't write the RomHack.toml")?;
@@ -505,6 +1216,306 @@ lto = true
     Ok(())
 }
 
+/// The Rom Hack after linking it against the game.
+struct LinkedRomHack {
+    dol: DolFile,
+    instructions: Vec<Instruction>,
+    /// The symbol map for Dolphin, if the Dolphin files are configured.
+    dolphin_map: Option<String>,
+}
+
+/// The files that let Dolphin try out the Rom Hack on the original game.
+struct DolphinFiles {
+    name: String,
+    patch: Vec<String>,
+    map: String,
+}
+
+/// Links the Rom Hack and assembles the patch.asm. Returns the linked sections,
+/// the instructions to patch into the game and the symbol map for Dolphin.
+fn link<P: KeyValPrint, F: FileSource>(
+    printer: &P,
+    files: &mut F,
+    iso: &Directory,
+    compiled_library: Vec<u8>,
+    config: &Config,
+) -> Result<LinkedRomHack, Error> {
+    let original_symbols = load_symbols(printer, files, iso, config)?;
+
+    printer.print(None, "Linking", "");
+
+    let mut libs_to_link = Vec::with_capacity(config.link.libs.as_ref().map_or(0, |x| x.len()) + 2);
+
+    libs_to_link.push(compiled_library);
+
+    for lib_path in config.link.libs.iter().flat_map(|x| x) {
+        let mut file_buf = files.read_to_vec(lib_path).with_context(|_| {
+            format!(
+                "Couldn't load \"{}\". Did you build the project correctly?",
+                lib_path.display()
+            )
+        })?;
+        libs_to_link.push(file_buf);
+    }
+
+    libs_to_link.push(linker::BASIC_LIB.to_owned());
+
+    let base_address: syn::LitInt =
+        syn::parse_str(&config.link.base).context("Invalid Base Address")?;
+
+    let linked = linker::link(
+        printer,
+        &libs_to_link,
+        base_address.value() as u32,
+        config.link.entries.clone(),
+        &original_symbols,
+    ).context("Couldn't link the Rom Hack")?;
+
+    printer.print(None, "Creating", "symbol map");
+
+    framework_map::create(
+        config,
+        &original_symbols,
+        &linked.sections,
+    ).context("Couldn't create the new symbol map")?;
+
+    let dolphin_map = match config.build.dolphin {
+        Some(_) => {
+            let original = iso
+                .main_dol()
+                .ok_or_else(|| err_msg("Dol file not found"))?
+                .data
+                .load()
+                .context("Couldn't read the dol")?;
+            Some(dolphin::create_map(
+                &DolFile::parse(&original),
+                &original_symbols,
+                &linked.dol,
+                &linked.symbol_table,
+            ))
+        }
+        None => None,
+    };
+
+    let mut instructions = Vec::new();
+    if let Some(patch) = &config.src.patch {
+        printer.print(None, "Parsing", "patch");
+
+        let asm = files
+            .read_to_string(patch)
+            .with_context(|_| format!("Couldn't read the patch file \"{}\".", patch.display()))?;
+
+        let lines = &asm.lines().collect::<Vec<_>>();
+
+        let mut assembler = Assembler::new(linked.symbol_table, &original_symbols);
+        instructions = assembler
+            .assemble_all_lines(lines)
+            .context("Couldn't assemble the patch file lines")?;
+    }
+
+    Ok(LinkedRomHack {
+        dol: linked.dol,
+        instructions,
+        dolphin_map,
+    })
+}
+
+/// Loads the symbol maps of the game, which are looked up in the game first and
+/// on the host otherwise, and merges them with the symbols of the
+/// configuration. Later maps take precedence over earlier ones and the
+/// configuration takes precedence over all of them.
+fn load_symbols<P: KeyValPrint, F: FileSource>(
+    printer: &P,
+    files: &mut F,
+    iso: &Directory,
+    config: &Config,
+) -> Result<HashMap<String, GameSymbol>, Error> {
+    let mut symbols = HashMap::new();
+
+    for path in config.src.all_maps() {
+        let map = match iso.resolve_path(path) {
+            Some(file) => file
+                .data
+                .load()
+                .with_context(|_| format!("Couldn't read the game's symbol map \"{}\"", path))?
+                .into_owned(),
+            None => match files.read_to_vec(path) {
+                Ok(map) => map,
+                Err(_) => {
+                    printer.print(
+                        Some(MessageKind::Warning),
+                        "Warning",
+                        &format!("The symbol map \"{}\" wasn't found", path),
+                    );
+                    continue;
+                }
+            },
+        };
+
+        printer.print(None, "Parsing", path);
+
+        let parsed = framework_map::parse(&map)
+            .with_context(|_| format!("Couldn't parse the symbol map \"{}\"", path))?;
+        if parsed.unparsed_lines > 0 {
+            printer.print(
+                Some(MessageKind::Warning),
+                "Warning",
+                &format!(
+                    "{} lines of the symbol map \"{}\" couldn't be parsed",
+                    parsed.unparsed_lines, path
+                ),
+            );
+        }
+        for (name, symbol) in parsed.symbols {
+            merge_symbol(printer, &mut symbols, name, symbol, path);
+        }
+    }
+
+    for (name, address) in &config.symbols {
+        let address = match address {
+            SymbolAddress::Number(address) => *address,
+            SymbolAddress::Text(address) => {
+                let address: syn::LitInt = syn::parse_str(address)
+                    .with_context(|_| format!("Invalid address of the symbol \"{}\"", name))?;
+                address.value() as u32
+            }
+        };
+        merge_symbol(
+            printer,
+            &mut symbols,
+            name.clone(),
+            GameSymbol::new(address),
+            "RomHack.toml",
+        );
+    }
+
+    if symbols.is_empty() {
+        printer.print(
+            Some(MessageKind::Warning),
+            "Warning",
+            "No symbol map specified or it wasn't found",
+        );
+    }
+
+    Ok(symbols)
+}
+
+/// Adds the symbol to the symbols. If it's already known at the same address,
+/// the details of both get combined, otherwise the new address wins.
+fn merge_symbol<P: KeyValPrint>(
+    printer: &P,
+    symbols: &mut HashMap<String, GameSymbol>,
+    name: String,
+    symbol: GameSymbol,
+    source: &str,
+) {
+    let symbol = match symbols.remove(&name) {
+        Some(previous) => {
+            if previous.address == symbol.address {
+                GameSymbol {
+                    address: symbol.address,
+                    size: symbol.size.or(previous.size),
+                    align: symbol.align.or(previous.align),
+                    section: symbol.section.or(previous.section),
+                    kind: if symbol.kind == SymbolKind::Unknown {
+                        previous.kind
+                    } else {
+                        symbol.kind
+                    },
+                    object_file: symbol.object_file.or(previous.object_file),
+                    mangled_name: symbol.mangled_name.or(previous.mangled_name),
+                }
+            } else {
+                printer.print(
+                    Some(MessageKind::Warning),
+                    "Warning",
+                    &format!(
+                        "\"{}\" is at 0x{:08X} according to {}, instead of 0x{:08X}",
+                        name, symbol.address, source, previous.address
+                    ),
+                );
+                symbol
+            }
+        }
+        None => symbol,
+    };
+    symbols.insert(name, symbol);
+}
+
+/// Loads either an extracted game or a disc image.
+fn load_game<'a>(path: &Path) -> Result<Directory<'a>, Error> {
+    Ok(if path.is_dir() {
+        iso::extracted::import_from_disk(path).with_context(|_| {
+            format!("Couldn't load the extracted game \"{}\".", path.display())
+        })?
+    } else {
+        iso::reader::load_iso_file(path)
+            .with_context(|_| format!("Couldn't load \"{}\".", path.display()))?
+    })
+}
+
+/// Expands the directory and glob mappings of the replaced files into the
+/// individual files, each with its path in the game.
+fn expand_replacements<'c, F: FileSource>(
+    files: &mut F,
+    replacements: &'c HashMap<String, FileReplacement>,
+) -> Result<Vec<(String, &'c FileReplacement, PathBuf)>, Error> {
+    let mut expanded = Vec::new();
+    for (iso_path, replacement) in replacements {
+        let path = replacement.path();
+        let matches = files
+            .expand(path)
+            .with_context(|_| format!("Couldn't find the files of \"{}\".", path.display()))?;
+        ensure!(
+            !matches.is_empty(),
+            "\"{}\" doesn't match any files.",
+            path.display()
+        );
+
+        for (relative_path, actual_path) in matches {
+            let iso_path = if relative_path.as_os_str().is_empty() {
+                iso_path.clone()
+            } else {
+                let relative_path = relative_path
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                match iso_path.trim_right_matches('/') {
+                    "" => relative_path,
+                    dir => format!("{}/{}", dir, relative_path),
+                }
+            };
+            expanded.push((iso_path, replacement, actual_path));
+        }
+    }
+    Ok(expanded)
+}
+
+/// Splits a path that descends into an archive into the path of the archive in
+/// the ISO and the path inside of the archive.
+fn split_archive_path<'a>(iso: &Directory, path: &'a str) -> Option<(&'a str, &'a str)> {
+    if iso.resolve_path(path).is_some() {
+        return None;
+    }
+    path.match_indices('/')
+        .map(|(index, _)| (&path[..index], &path[index + 1..]))
+        .find(|&(archive_path, _)| iso.resolve_path(archive_path).is_some())
+}
+
+/// Maps a path of the game after the given renames back to the path in the
+/// game before the renames.
+fn original_path(renames: &[RenamedFile], path: &str) -> String {
+    let mut path = path.to_owned();
+    for rename in renames.iter().rev() {
+        let to = &rename.to;
+        if path == *to || path.starts_with(&format!("{}/", to)) {
+            path = format!("{}{}", rename.from, &path[to.len()..]);
+        }
+    }
+    path
+}
+
 fn patch_instructions(
     mut original: DolFile,
     intermediate: DolFile,
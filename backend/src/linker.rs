@@ -1,6 +1,8 @@
 use byteorder::{ByteOrder, BE};
+use demangle::demangle_rust;
 use dol::{DolFile, Section};
 use failure::Error;
+use framework_map::{self, GameSymbol, SymbolKind};
 use goblin::archive::{Archive, Member};
 use goblin::elf::{section_header, sym, Elf, Reloc};
 use key_val_print::KeyValPrint;
@@ -39,6 +41,17 @@ fn function_symbols_for_section<'a>(
     )
 }
 
+fn named_symbols_for_section<'a>(
+    section_index: usize,
+    elf: &'a Elf,
+) -> Box<Iterator<Item = sym::Sym> + 'a> {
+    Box::new(elf.syms.iter().filter(move |sym| {
+        section_index == sym.st_shndx as usize
+            && sym.st_name != 0
+            && (sym.is_function() || sym.st_type() == sym::STT_OBJECT)
+    }))
+}
+
 #[derive(Copy, Clone, PartialOrd, Ord, Hash, PartialEq, Eq, Debug)]
 pub enum SectionKind {
     TextSection,
@@ -77,10 +90,18 @@ pub struct Linked<'a> {
 pub struct LinkedSection<'a> {
     pub address: u32,
     pub len: u32,
+    pub align: u32,
     pub member_name: &'a str,
     pub section_name: &'a str,
-    pub sym_offset: u32,
     pub kind: SectionKind,
+    /// The functions and objects in the section, sorted by their address.
+    pub symbols: Vec<LinkedSymbol<'a>>,
+}
+
+pub struct LinkedSymbol<'a> {
+    pub name: &'a str,
+    pub address: u32,
+    pub size: u32,
 }
 
 fn resolve_archive_symbol_to_member<'a, 'b: 'a>(
@@ -125,7 +146,7 @@ fn traverse_global<'a>(
     archives: &mut [Option<Archive<'a>>],
     parsed_elfs: &mut BTreeMap<(usize, &'a str), Elf<'a>>,
     visited_sections: &mut HashSet<SectionInfo<'a>>,
-    prelinked_symbols: &HashMap<String, u32>,
+    prelinked_symbols: &HashMap<String, GameSymbol>,
 ) -> Result<(), Error> {
     let mut archive_symbols_to_visit = Vec::new();
 
@@ -144,8 +165,8 @@ fn traverse_global<'a>(
                 parsed_elfs,
                 visited_sections,
             );
-        } else if !prelinked_symbols.contains_key(&symbol) {
-            bail!("Unresolved symbol `{}`", symbol)
+        } else if framework_map::lookup(prelinked_symbols, &symbol).is_none() {
+            bail!("Unresolved symbol `{}`", demangle_rust(&symbol).unwrap_or(symbol))
         }
     }
 
@@ -303,8 +324,8 @@ fn relocate_and_collect<'a, P: KeyValPrint>(
     archives: &'a [Option<Archive<'a>>],
     archive_bufs: &'a [Vec<u8>],
     parsed_elfs: &BTreeMap<(usize, &'a str), Elf<'a>>,
-    prelinked_symbols: &HashMap<String, u32>,
-) -> (Vec<u8>, Vec<u8>) {
+    prelinked_symbols: &HashMap<String, GameSymbol>,
+) -> Result<(Vec<u8>, Vec<u8>), Error> {
     let (mut text_section, mut data_section) = (Vec::new(), Vec::new());
 
     for &LocatedSection {
@@ -343,13 +364,13 @@ fn relocate_and_collect<'a, P: KeyValPrint>(
                 let symbol_index = reloc.r_sym as usize;
                 let symbol = elf.syms.get(symbol_index).unwrap();
                 let symbol_section_index = symbol.st_shndx as usize;
-                let (section_address, symbol_offset) = layout
+                let (section_address, symbol_offset, game_symbol) = layout
                     .lookup
                     .get(&LookupKey {
                         archive_index,
                         member_name,
                         section_index: symbol_section_index,
-                    }).map(|&index| (layout.sections[index].address, symbol.st_value as u32))
+                    }).map(|&index| (layout.sections[index].address, symbol.st_value as u32, None))
                     .unwrap_or_else(|| {
                         let name_index = symbol.st_name;
                         let archive_symbol_name = elf.strtab.get(name_index).unwrap().unwrap();
@@ -379,6 +400,7 @@ fn relocate_and_collect<'a, P: KeyValPrint>(
                                     return (
                                         layout.sections[located_section_index].address,
                                         symbol.st_value as u32,
+                                        None,
                                     );
                                 }
                             }
@@ -394,7 +416,14 @@ fn relocate_and_collect<'a, P: KeyValPrint>(
                                         .wrapping_add(reloc.r_offset as u32)
                                 ),
                             );
-                            return (prelinked_symbols[archive_symbol_name], 0);
+                            let game_symbol =
+                                framework_map::lookup(prelinked_symbols, archive_symbol_name)
+                                    .unwrap();
+                            return (
+                                game_symbol.address,
+                                0,
+                                Some((archive_symbol_name, game_symbol)),
+                            );
                         }
                     });
 
@@ -436,6 +465,42 @@ fn relocate_and_collect<'a, P: KeyValPrint>(
                     t => panic!("Unknown reloc type {}", t),
                 };
 
+                match reloc.r_type {
+                    R_PPC_REL24 | R_PPC_PLTREL24 => ensure!(
+                        value.wrapping_add(0x200_0000) < 0x400_0000,
+                        "The branch at 0x{:08X} to 0x{:08X} is out of range",
+                        p,
+                        symbol_address.wrapping_add(a)
+                    ),
+                    _ => {}
+                }
+
+                if let Some((name, game_symbol)) = game_symbol {
+                    let is_branch = reloc.r_type == R_PPC_REL24 || reloc.r_type == R_PPC_PLTREL24;
+                    ensure!(
+                        !is_branch || game_symbol.kind != SymbolKind::Object,
+                        "The Rom Hack calls the game symbol `{}` at 0x{:08X}, which is data",
+                        name,
+                        p
+                    );
+                    match game_symbol.size {
+                        Some(size) if size > 0 => {
+                            let addend = reloc.r_addend.unwrap_or(0);
+                            // Pointing right past the end of the symbol is allowed.
+                            ensure!(
+                                addend >= 0 && addend <= size as i64,
+                                "The Rom Hack references offset {} of the game symbol `{}` at \
+                                 0x{:08X}, which is only {} bytes large",
+                                addend,
+                                name,
+                                p,
+                                size
+                            );
+                        }
+                        _ => {}
+                    }
+                }
+
                 assert_ne!(
                     reloc.r_offset as i32, -1,
                     "Should be end of section. Can't handle this yet"
@@ -478,7 +543,7 @@ fn relocate_and_collect<'a, P: KeyValPrint>(
         }
     }
 
-    (text_section, data_section)
+    Ok((text_section, data_section))
 }
 
 pub fn link<'a, P: KeyValPrint>(
@@ -486,7 +551,7 @@ pub fn link<'a, P: KeyValPrint>(
     archive_bufs: &'a [Vec<u8>],
     base_address: u32,
     mut global_symbols_to_visit: Vec<String>,
-    prelinked_symbols: &HashMap<String, u32>,
+    prelinked_symbols: &HashMap<String, GameSymbol>,
 ) -> Result<Linked<'a>, Error> {
     // TODO Handle "weak" and "merge" symbols
 
@@ -516,7 +581,7 @@ pub fn link<'a, P: KeyValPrint>(
         &archive_bufs,
         &parsed_elfs,
         prelinked_symbols,
-    );
+    )?;
 
     let dol = DolFile {
         text_sections: vec![Section {
@@ -548,20 +613,22 @@ pub fn link<'a, P: KeyValPrint>(
                     .unwrap()
                     .unwrap();
 
-                let sym_offset =
-                    if let Some(sym) = function_symbols_for_section(section_index, elf).next() {
-                        sym.st_value as u32
-                    } else {
-                        0
-                    };
+                let mut symbols = named_symbols_for_section(section_index, elf)
+                    .map(|sym| LinkedSymbol {
+                        name: elf.strtab.get(sym.st_name).unwrap().unwrap(),
+                        address: s.address + sym.st_value as u32,
+                        size: sym.st_size as u32,
+                    }).collect::<Vec<_>>();
+                symbols.sort_by_key(|sym| sym.address);
 
                 LinkedSection {
                     address: s.address,
                     len: s.len,
+                    align: section.sh_addralign as u32,
                     member_name: s.section_info.member_name,
                     section_name: section_name,
                     kind: s.section_info.kind,
-                    sym_offset,
+                    symbols,
                 }
             }).collect(),
     })
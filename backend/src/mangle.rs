@@ -0,0 +1,539 @@
+//! Mangles C++ function signatures the way CodeWarrior does. This is the
+//! inverse of the demangler, so game functions can be referred to by either
+//! their mangled name or their signature.
+
+use demangle::split_template_arguments;
+use std::borrow::Cow;
+
+/// The operators and the base names CodeWarrior encodes them as. Longer
+/// operators come first, so they are preferred over their prefixes.
+const OPERATORS: &[(&str, &str)] = &[
+    ("new[]", "__nwa"),
+    ("delete[]", "__dla"),
+    ("new", "__nw"),
+    ("delete", "__dl"),
+    ("->*", "__rm"),
+    ("<<=", "__als"),
+    (">>=", "__ars"),
+    ("->", "__rf"),
+    ("()", "__cl"),
+    ("[]", "__vc"),
+    ("+=", "__apl"),
+    ("-=", "__ami"),
+    ("*=", "__amu"),
+    ("/=", "__adv"),
+    ("%=", "__amd"),
+    ("^=", "__aer"),
+    ("&=", "__aad"),
+    ("|=", "__aor"),
+    ("<<", "__ls"),
+    (">>", "__rs"),
+    ("==", "__eq"),
+    ("!=", "__ne"),
+    ("<=", "__le"),
+    (">=", "__ge"),
+    ("&&", "__aa"),
+    ("||", "__oo"),
+    ("++", "__pp"),
+    ("--", "__mm"),
+    ("+", "__pl"),
+    ("-", "__mi"),
+    ("*", "__ml"),
+    ("/", "__dv"),
+    ("%", "__md"),
+    ("^", "__er"),
+    ("&", "__ad"),
+    ("|", "__or"),
+    ("~", "__co"),
+    ("!", "__nt"),
+    ("=", "__as"),
+    ("<", "__lt"),
+    (">", "__gt"),
+    (",", "__cm"),
+];
+
+/// Splits the text at the separator, except inside of parentheses and template
+/// arguments.
+fn split_top_level<'a>(text: &'a str, separator: &str) -> Vec<&'a str> {
+    let mut parts = Vec::new();
+    let (mut depth, mut start) = (0i32, 0);
+    let mut index = 0;
+    while index < text.len() {
+        let rest = &text[index..];
+        if depth == 0 && rest.starts_with(separator) {
+            parts.push(&text[start..index]);
+            index += separator.len();
+            start = index;
+            continue;
+        }
+        match rest.as_bytes()[0] {
+            b'(' | b'<' | b'[' => depth += 1,
+            b')' | b'>' | b']' => depth -= 1,
+            _ => {}
+        }
+        index += rest.chars().next().map_or(1, |c| c.len_utf8());
+    }
+    parts.push(&text[start..]);
+    parts
+}
+
+/// The index of the parenthesis that closes the one the text starts with.
+fn closing_parenthesis(text: &str) -> Result<usize, Cow<'static, str>> {
+    let mut depth = 0;
+    for (index, c) in text.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 1 => return Ok(index),
+            ')' => depth -= 1,
+            _ => {}
+        }
+    }
+    Err(format!("Unclosed parenthesis in {}", text).into())
+}
+
+/// The index of the first of the characters that isn't inside of template
+/// arguments.
+fn find_top_level(text: &str, chars: &[char]) -> Option<usize> {
+    let mut depth = 0i32;
+    for (index, c) in text.char_indices() {
+        match c {
+            '<' => depth += 1,
+            '>' => depth -= 1,
+            c if depth == 0 && chars.contains(&c) => return Some(index),
+            _ => {}
+        }
+    }
+    None
+}
+
+fn is_integer(text: &str) -> bool {
+    let digits = text.trim_left_matches('-');
+    !digits.is_empty() && digits.chars().all(|c| c.is_digit(10))
+}
+
+/// The name without its template arguments, as it's used for constructors.
+fn class_name(name: &str) -> &str {
+    &name[..name.find('<').unwrap_or(name.len())]
+}
+
+/// Mangles a name, including the arguments of its template. The name is
+/// prefixed by its length.
+fn mangle_name(name: &str) -> Result<String, Cow<'static, str>> {
+    let base = class_name(name);
+    let is_identifier = !base.is_empty()
+        && !base.starts_with(|c: char| c.is_digit(10))
+        && base.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !is_identifier {
+        return Err(format!("Unexpected name {}", name).into());
+    }
+
+    let mut mangled = base.to_owned();
+    if base.len() != name.len() {
+        if !name.ends_with('>') {
+            return Err(format!("Unexpected template {}", name).into());
+        }
+        mangled.push('<');
+        let arguments = split_template_arguments(&name[base.len() + 1..name.len() - 1]);
+        for (i, argument) in arguments.into_iter().enumerate() {
+            if i != 0 {
+                mangled.push(',');
+            }
+            let argument = argument.trim();
+            if is_integer(argument) {
+                mangled.push_str(argument);
+            } else {
+                mangled.push_str(&mangle_type(argument)?);
+            }
+        }
+        mangled.push('>');
+    }
+
+    Ok(format!("{}{}", mangled.len(), mangled))
+}
+
+fn mangle_path(path: &[&str]) -> Result<String, Cow<'static, str>> {
+    if path.len() == 1 {
+        mangle_name(path[0].trim())
+    } else {
+        let mut mangled = format!("Q{}", path.len());
+        for element in path {
+            mangled.push_str(&mangle_name(element.trim())?);
+        }
+        Ok(mangled)
+    }
+}
+
+/// Mangles a type without any pointers or references. Both the C++ names and
+/// the Rust names that the demangler uses are accepted.
+fn mangle_base_type(name: &str) -> Result<String, Cow<'static, str>> {
+    Ok(match name {
+        "void" => "v",
+        "bool" => "b",
+        "char" | "signed char" | "i8" => "c",
+        "unsigned char" | "u8" => "Uc",
+        "short" | "signed short" | "short int" | "i16" => "s",
+        "unsigned short" | "unsigned short int" | "u16" => "Us",
+        "int" | "signed" | "signed int" | "i32" => "i",
+        "unsigned" | "unsigned int" | "u32" => "Ui",
+        "long" | "signed long" | "long int" => "l",
+        "unsigned long" | "unsigned long int" => "Ul",
+        "long long" | "i64" => "x",
+        "unsigned long long" | "u64" => "Ux",
+        "float" | "f32" => "f",
+        "double" | "f64" => "d",
+        "long double" => "r",
+        "wchar_t" => "w",
+        "..." => "e",
+        _ => return mangle_path(&split_top_level(name, "::")),
+    }.into())
+}
+
+/// Adds the qualifiers of a function, which follow its parameters.
+fn mangle_function_qualifiers(qualifiers: &str) -> Result<String, Cow<'static, str>> {
+    let (mut is_const, mut is_volatile) = (false, false);
+    for qualifier in qualifiers.split_whitespace() {
+        match qualifier {
+            "const" => is_const = true,
+            "volatile" => is_volatile = true,
+            q => return Err(format!("Unexpected qualifier {}", q).into()),
+        }
+    }
+
+    let mut mangled = String::new();
+    if is_const {
+        mangled.push('C');
+    }
+    if is_volatile {
+        mangled.push('V');
+    }
+    Ok(mangled)
+}
+
+/// Applies the parameters of a function or the lengths of an array that
+/// follow a declarator to the mangled type.
+fn mangle_suffix(mangled: String, suffix: &str) -> Result<String, Cow<'static, str>> {
+    let suffix = suffix.trim();
+    if suffix.starts_with('(') {
+        let close = closing_parenthesis(suffix)?;
+        Ok(format!(
+            "{}F{}_{}",
+            mangle_function_qualifiers(&suffix[close + 1..])?,
+            mangle_parameters(&suffix[1..close])?,
+            mangled
+        ))
+    } else if suffix.starts_with('[') {
+        let mut lengths = String::new();
+        let mut rest = suffix;
+        while rest.starts_with('[') {
+            let close = rest.find(']').ok_or("Unclosed array length")?;
+            let length = rest[1..close].trim();
+            if !is_integer(length) {
+                return Err(format!("Unexpected array length {}", length).into());
+            }
+            lengths.push_str(&format!("A{}_", length));
+            rest = rest[close + 1..].trim_left();
+        }
+        if !rest.is_empty() {
+            return Err(format!("Unexpected {} after array", rest).into());
+        }
+        Ok(lengths + &mangled)
+    } else if suffix.is_empty() {
+        Ok(mangled)
+    } else {
+        Err(format!("Unexpected declarator {}", suffix).into())
+    }
+}
+
+/// Applies the declarator that follows the base type, such as `const*` or
+/// `(*)(int)`, to the mangled base type.
+fn mangle_declarator(mut mangled: String, declarator: &str) -> Result<String, Cow<'static, str>> {
+    let mut rest = declarator.trim();
+    loop {
+        if rest.starts_with('*') {
+            mangled.insert(0, 'P');
+            rest = rest[1..].trim_left();
+        } else if rest.starts_with('&') {
+            mangled.insert(0, 'R');
+            rest = rest[1..].trim_left();
+        } else if rest.starts_with("const") {
+            mangled.insert(0, 'C');
+            rest = rest["const".len()..].trim_left();
+        } else if rest.starts_with("volatile") {
+            mangled.insert(0, 'V');
+            rest = rest["volatile".len()..].trim_left();
+        } else if rest.starts_with('(') {
+            // The declarator in the parentheses applies to the function or
+            // array that follows them.
+            let close = closing_parenthesis(rest)?;
+            let inner = mangle_suffix(mangled, &rest[close + 1..])?;
+            return mangle_declarator(inner, &rest[1..close]);
+        } else if rest.starts_with('[') {
+            return mangle_suffix(mangled, rest);
+        } else if rest.is_empty() {
+            return Ok(mangled);
+        } else {
+            // Pointers to members, such as `Foo::*`.
+            let end = rest
+                .find("::*")
+                .ok_or_else(|| format!("Unexpected declarator {}", rest))?;
+            let class = mangle_base_type(rest[..end].trim())?;
+
+            // Member functions take the object as a void pointer first.
+            if mangled.starts_with("CF") || mangled.starts_with('F') {
+                let (this, parameters) = if mangled.starts_with('C') {
+                    ("PCv", &mangled[2..])
+                } else {
+                    ("Pv", &mangled[1..])
+                };
+                let parameters = if parameters.starts_with("v_") {
+                    &parameters[1..]
+                } else {
+                    parameters
+                };
+                mangled = format!("F{}{}", this, parameters);
+            }
+
+            mangled = format!("M{}{}", class, mangled);
+            rest = rest[end + 3..].trim_left();
+        }
+    }
+}
+
+fn mangle_type(text: &str) -> Result<String, Cow<'static, str>> {
+    let text = text.trim();
+    if text == "..." {
+        return Ok(String::from("e"));
+    }
+
+    // The base type ends where the first declarator starts.
+    let mut end = find_top_level(text, &['*', '&', '(', '[']).unwrap_or(text.len());
+    let mut words = split_top_level(text[..end].trim(), " ");
+    words.retain(|w| !w.is_empty());
+    if words.len() > 1 && words.last().map_or(false, |w| w.ends_with("::")) {
+        // The class of a pointer to a member belongs to the declarator.
+        let class = words.pop().unwrap();
+        end = text[..end].rfind(class).unwrap_or(end);
+    }
+
+    let (mut is_const, mut is_volatile) = (false, false);
+    let mut base = Vec::new();
+    for word in words {
+        match word {
+            "const" => is_const = true,
+            "volatile" => is_volatile = true,
+            "struct" | "class" | "enum" | "union" => {}
+            word => base.push(word),
+        }
+    }
+
+    let mut mangled = mangle_base_type(&base.join(" "))?;
+    if is_volatile {
+        mangled.insert(0, 'V');
+    }
+    if is_const {
+        mangled.insert(0, 'C');
+    }
+
+    mangle_declarator(mangled, &text[end..])
+}
+
+fn mangle_parameters(parameters: &str) -> Result<String, Cow<'static, str>> {
+    let parameters = parameters.trim();
+    if parameters.is_empty() || parameters == "void" {
+        return Ok(String::from("v"));
+    }
+
+    let mut mangled = String::new();
+    for parameter in split_top_level(parameters, ",") {
+        mangled.push_str(&mangle_type(parameter)?);
+    }
+    Ok(mangled)
+}
+
+/// Finds the `operator` keyword of an operator function. Returns its index,
+/// the base name it's mangled as and the index where its parameters start.
+fn find_operator(signature: &str) -> Result<Option<(usize, String, usize)>, Cow<'static, str>> {
+    let mut offset = 0;
+    while let Some(index) = signature[offset..].find("operator") {
+        let start = offset + index;
+        let end = start + "operator".len();
+        offset = end;
+
+        let is_keyword = signature[..start]
+            .chars()
+            .next_back()
+            .map_or(true, |c| c == ':' || c == ' ')
+            && !signature[end..]
+                .starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_');
+        if !is_keyword {
+            continue;
+        }
+
+        let rest = signature[end..].trim_left();
+        let rest_start = signature.len() - rest.len();
+        for &(operator, base_name) in OPERATORS {
+            if rest.starts_with(operator) {
+                let after = rest[operator.len()..].trim_left();
+                let is_word = operator.starts_with(|c: char| c.is_ascii_alphabetic());
+                if after.starts_with('(')
+                    && !(is_word && rest[operator.len()..].starts_with(char::is_alphanumeric))
+                {
+                    let parameters = signature.len() - after.len();
+                    return Ok(Some((start, base_name.to_owned(), parameters)));
+                }
+            }
+        }
+
+        // Conversion operators are named after the type they convert to.
+        let open = rest.find('(').ok_or("Expected parameters")?;
+        let base_name = format!("__op{}", mangle_type(&rest[..open])?);
+        return Ok(Some((start, base_name, rest_start + open)));
+    }
+    Ok(None)
+}
+
+/// Mangles the signature of a function, such as `Foo::bar(int, char const*)`.
+/// A leading return type, as well as the trailing return type that the
+/// demangler adds, is ignored, as CodeWarrior doesn't mangle it.
+pub fn mangle(signature: &str) -> Result<String, Cow<'static, str>> {
+    let signature = signature.trim();
+
+    let (qualified_name, operator, open) = match find_operator(signature)? {
+        Some((start, base_name, open)) => (&signature[..start], Some(base_name), open),
+        None => {
+            let open = find_top_level(signature, &['(']).ok_or("Expected parameters")?;
+            (&signature[..open], None, open)
+        }
+    };
+    let close = open + closing_parenthesis(&signature[open..])?;
+    let parameters = &signature[open + 1..close];
+    let qualifiers = signature[close + 1..].split("->").next().unwrap_or("");
+    let is_const = qualifiers.split_whitespace().any(|q| q == "const")
+        || signature[close + 1..].trim().ends_with(" const");
+    let is_volatile = qualifiers.split_whitespace().any(|q| q == "volatile")
+        || signature[close + 1..].trim().ends_with(" volatile");
+
+    // Skip the return type in front of the name.
+    let qualified_name = qualified_name.trim();
+    let qualified_name = split_top_level(qualified_name, " ")
+        .pop()
+        .unwrap_or(qualified_name);
+    let mut path = split_top_level(qualified_name, "::");
+    let name = match operator {
+        Some(base_name) => {
+            // The name ends with `::` in front of the operator, which leaves
+            // an empty element.
+            if path.pop() != Some("") {
+                return Err(format!("Unexpected name {}", qualified_name).into());
+            }
+            base_name
+        }
+        None => {
+            let name = path.pop().ok_or("Expected a name")?;
+            if name.starts_with('~') {
+                String::from("__dt")
+            } else if path.last().map(|c| class_name(c)) == Some(name) {
+                String::from("__ct")
+            } else {
+                // Unlike the classes, the name isn't prefixed by its length.
+                mangle_name(name)?
+                    .trim_left_matches(|c: char| c.is_digit(10))
+                    .to_owned()
+            }
+        }
+    };
+
+    let mut mangled = format!("{}__", name);
+    if !path.is_empty() {
+        mangled.push_str(&mangle_path(&path)?);
+    }
+    if is_const {
+        mangled.push('C');
+    }
+    if is_volatile {
+        mangled.push('V');
+    }
+    mangled.push('F');
+    mangled.push_str(&mangle_parameters(parameters)?);
+
+    Ok(mangled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use demangle::demangle;
+
+    /// Mangles the signature and checks that demangling the result gives back
+    /// the signature, which is what looking up symbols by their signature
+    /// relies on.
+    fn assert_round_trip(signature: &str, mangled: &str) {
+        assert_eq!(mangle(signature).unwrap(), mangled, "{}", signature);
+        assert_eq!(demangle(mangled).unwrap(), signature, "{}", mangled);
+    }
+
+    #[test]
+    fn functions() {
+        assert_round_trip("dComIfG_play_c::getLayerNo(i32)", "getLayerNo__14dComIfG_play_cFi");
+        assert_round_trip("OSReport(i8 const*, ...)", "OSReport__FPCce");
+        assert_round_trip("Foo::my__weird()", "my__weird__3FooFv");
+        assert_eq!(mangle("void OSInit()").unwrap(), "OSInit__Fv");
+        assert_eq!(mangle("OSInit(void)").unwrap(), "OSInit__Fv");
+        assert_eq!(mangle("int foo(int, char const*)").unwrap(), "foo__FiPCc");
+        assert_eq!(mangle("foo(unsigned long, float) -> bool").unwrap(), "foo__FUlf");
+    }
+
+    #[test]
+    fn templates() {
+        assert_round_trip("JGeometry::TVec3<f32>::TVec3()", "__ct__Q29JGeometry8TVec3<f>Fv");
+        assert_round_trip(
+            "JGeometry::TVec3<f32>::set(f32, f32, f32)",
+            "set__Q29JGeometry8TVec3<f>Ffff",
+        );
+        assert_round_trip("func<i32, 3>(void*)", "func<i,3>__FPv");
+        assert_round_trip("TBox<Foo::Bar, -12>::get()", "get__20TBox<Q23Foo3Bar,-12>Fv");
+    }
+
+    #[test]
+    fn operators() {
+        for &(operator, base_name) in OPERATORS {
+            let separator = if operator.starts_with(char::is_alphabetic) {
+                " "
+            } else {
+                ""
+            };
+            let signature = format!("Foo::operator{}{}(Foo const&)", separator, operator);
+            let mangled = format!("{}__3FooFRC3Foo", base_name);
+            assert_round_trip(&signature, &mangled);
+        }
+        assert_round_trip("Foo::Foo(i32*)", "__ct__3FooFPi");
+        assert_round_trip("Foo::Bar::~Bar()", "__dt__Q23Foo3BarFv");
+        assert_round_trip("Foo::operator bool() const", "__opb__3FooCFv");
+        assert_round_trip("Foo::operator i8 const*() const", "__opPCc__3FooCFv");
+        assert_round_trip("operator new[](u32)", "__nwa__FUi");
+    }
+
+    #[test]
+    fn qualifiers() {
+        assert_round_trip("Foo::get() const", "get__3FooCFv");
+        assert_round_trip("Foo::get() volatile", "get__3FooVFv");
+        assert_round_trip("Foo::get() const volatile", "get__3FooCVFv");
+        assert_round_trip("read(u32 volatile*)", "read__FPVUi");
+        assert_eq!(mangle("read(volatile unsigned int*)").unwrap(), "read__FPVUi");
+    }
+
+    #[test]
+    fn member_pointers() {
+        assert_round_trip("field(i32 Foo::*)", "field__FM3Fooi");
+        assert_round_trip("call(void (Foo::*)(i32))", "call__FM3FooFPvi_v");
+        assert_round_trip("call(void (Foo::*)(i32) const)", "call__FM3FooFPCvi_v");
+    }
+
+    #[test]
+    fn arrays_and_function_pointers() {
+        assert_round_trip("arr(i32 (*)[3][4])", "arr__FPA3_A4_i");
+        assert_round_trip("ref(f32 (&)[2])", "ref__FRA2_f");
+        assert_round_trip("cb(void (*)(i32, void*))", "cb__FPFiPv_v");
+        assert_round_trip("cb(void (* (*)(i32, void*))(i32))", "cb__FPFiPv_PFi_v");
+    }
+}
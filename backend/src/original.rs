@@ -0,0 +1,111 @@
+//! Patches record the game they were built for, so that applying them to a
+//! different region or revision of the game fails instead of silently
+//! producing a broken Rom Hack.
+
+use config::Original;
+use failure::{err_msg, Error, ResultExt};
+use header;
+use iso::extracted;
+use iso::virtual_file_system::{Directory, File, Node};
+use sha1::Sha1;
+use std::collections::HashMap;
+
+fn hash_file(file: &File, path: &str) -> Result<String, Error> {
+    let data = file
+        .data
+        .load()
+        .with_context(|_| format!("Couldn't read \"{}\".", path))?;
+    Ok(Sha1::from(&data[..]).digest().to_string())
+}
+
+/// Reads the game ID, including the maker code, and the revision of the game.
+pub fn identify(iso: &Directory) -> Result<(String, u8), Error> {
+    let header = iso
+        .header()
+        .ok_or_else(|| err_msg("Disc header not found"))?;
+    header::identify(&header.data.load().context("Couldn't read the disc header")?)
+}
+
+/// Hashes the main DOL up to the end of its last section, so the hash is the
+/// same for disc images and extracted games.
+fn hash_dol(iso: &Directory) -> Result<String, Error> {
+    let dol = iso
+        .main_dol()
+        .ok_or_else(|| err_msg("Dol file not found"))?;
+    let data = dol.data.load().context("Couldn't read the dol")?;
+    Ok(Sha1::from(&data[..extracted::dol_len(&data)]).digest().to_string())
+}
+
+/// Hashes the file or all the files inside of the folder.
+fn hash_node(node: &Node, path: &str, files: &mut HashMap<String, String>) -> Result<(), Error> {
+    match *node {
+        Node::File(ref file) => {
+            if !files.contains_key(path) {
+                files.insert(path.to_owned(), hash_file(file, path)?);
+            }
+        }
+        Node::Directory(ref dir) => {
+            for child in &dir.children {
+                hash_node(child, &format!("{}/{}", path, child.name()), files)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Records the game ID, the revision and the hashes of the main DOL and of the
+/// given files of the game. Folders are recorded as all the files inside of
+/// them. Paths that don't exist in the game are skipped.
+pub fn record<'p, I>(iso: &Directory, paths: I) -> Result<Original, Error>
+where
+    I: IntoIterator<Item = &'p str>,
+{
+    let (game_id, revision) = identify(iso)?;
+    let dol_sha1 = hash_dol(iso)?;
+
+    let mut files = HashMap::new();
+    for path in paths {
+        if let Some(node) = iso.resolve_node(path) {
+            hash_node(node, path, &mut files)?;
+        }
+    }
+
+    Ok(Original {
+        game_id,
+        revision,
+        dol_sha1,
+        files,
+    })
+}
+
+/// Checks that the game is the one that got recorded.
+pub fn verify(iso: &Directory, original: &Original) -> Result<(), Error> {
+    let (game_id, revision) = identify(iso)?;
+    ensure!(
+        game_id == original.game_id && revision == original.revision,
+        "The patch is meant for {} (revision {}), but the game is {} (revision {}).",
+        original.game_id,
+        original.revision,
+        game_id,
+        revision
+    );
+
+    ensure!(
+        hash_dol(iso)? == original.dol_sha1,
+        "The dol of the game differs from the one the patch is meant for. \
+         The game may already be modified."
+    );
+
+    for (path, sha1) in &original.files {
+        let file = iso
+            .resolve_path(path)
+            .ok_or_else(|| format_err!("The game doesn't contain \"{}\".", path))?;
+        ensure!(
+            hash_file(file, path)? == *sha1,
+            "\"{}\" differs from the one the patch is meant for. The game may already be modified.",
+            path
+        );
+    }
+
+    Ok(())
+}
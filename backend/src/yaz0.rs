@@ -0,0 +1,277 @@
+//! Yaz0 is the LZ77 based compression most GameCube games use for their
+//! files. The compressor follows the same strategy as Nintendo's own
+//! compressor, so the compressed files end up with the same size as the ones
+//! found on the original discs.
+
+use byteorder::{ByteOrder, BE};
+use failure::Error;
+use std::cmp;
+
+pub const MAGIC: &[u8; 4] = b"Yaz0";
+const HEADER_LENGTH: usize = 0x10;
+const WINDOW_SIZE: usize = 0x1000;
+const MIN_MATCH_LEN: usize = 3;
+const MAX_MATCH_LEN: usize = 0x111;
+const HASH_BITS: usize = 15;
+
+pub fn is_compressed(data: &[u8]) -> bool {
+    data.len() >= HEADER_LENGTH && &data[..4] == MAGIC
+}
+
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, Error> {
+    ensure!(is_compressed(data), "The file is not Yaz0 compressed");
+
+    let len = BE::read_u32(&data[4..]) as usize;
+    let mut output = Vec::with_capacity(len);
+    let mut src = HEADER_LENGTH;
+
+    let mut code = 0;
+    let mut remaining_bits = 0;
+
+    while output.len() < len {
+        if remaining_bits == 0 {
+            code = *data
+                .get(src)
+                .ok_or_else(|| format_err!("The Yaz0 data ends unexpectedly"))?;
+            src += 1;
+            remaining_bits = 8;
+        }
+
+        if code & 0x80 != 0 {
+            let byte = *data
+                .get(src)
+                .ok_or_else(|| format_err!("The Yaz0 data ends unexpectedly"))?;
+            output.push(byte);
+            src += 1;
+        } else {
+            ensure!(src + 2 <= data.len(), "The Yaz0 data ends unexpectedly");
+            let (b1, b2) = (data[src] as usize, data[src + 1] as usize);
+            src += 2;
+
+            let distance = ((b1 & 0xF) << 8 | b2) + 1;
+            let count = if b1 >> 4 == 0 {
+                let b3 = *data
+                    .get(src)
+                    .ok_or_else(|| format_err!("The Yaz0 data ends unexpectedly"))?;
+                src += 1;
+                b3 as usize + 0x12
+            } else {
+                (b1 >> 4) + 2
+            };
+
+            ensure!(
+                distance <= output.len(),
+                "The Yaz0 data references data before its start"
+            );
+
+            let start = output.len() - distance;
+            for i in 0..cmp::min(count, len - output.len()) {
+                let byte = output[start + i];
+                output.push(byte);
+            }
+        }
+
+        code <<= 1;
+        remaining_bits -= 1;
+    }
+
+    Ok(output)
+}
+
+struct MatchFinder<'a> {
+    data: &'a [u8],
+    head: Vec<i32>,
+    prev: Vec<i32>,
+    inserted: usize,
+}
+
+impl<'a> MatchFinder<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            head: vec![-1; 1 << HASH_BITS],
+            prev: vec![-1; data.len()],
+            inserted: 0,
+        }
+    }
+
+    fn hash(&self, pos: usize) -> usize {
+        let d = self.data;
+        let key = (d[pos] as usize) << 16 | (d[pos + 1] as usize) << 8 | d[pos + 2] as usize;
+        (key.wrapping_mul(0x9E37_79B1) >> 8) & ((1 << HASH_BITS) - 1)
+    }
+
+    /// Finds the longest match for the data at the given position within the
+    /// window. Returns the length and the position of the match.
+    fn find(&mut self, pos: usize) -> (usize, usize) {
+        let data = self.data;
+        if pos + MIN_MATCH_LEN > data.len() {
+            return (0, 0);
+        }
+
+        while self.inserted < pos {
+            let p = self.inserted;
+            if p + MIN_MATCH_LEN <= data.len() {
+                let hash = self.hash(p);
+                self.prev[p] = self.head[hash];
+                self.head[hash] = p as i32;
+            }
+            self.inserted += 1;
+        }
+
+        let max_len = cmp::min(MAX_MATCH_LEN, data.len() - pos);
+        let window_start = pos.saturating_sub(WINDOW_SIZE);
+        let (mut best_len, mut best_pos) = (0, 0);
+
+        let mut candidate = self.head[self.hash(pos)];
+        while candidate >= 0 && candidate as usize >= window_start {
+            let c = candidate as usize;
+            let len = data[c..]
+                .iter()
+                .zip(&data[pos..pos + max_len])
+                .take_while(|&(a, b)| a == b)
+                .count();
+
+            if len >= best_len {
+                best_len = len;
+                best_pos = c;
+                if len == max_len {
+                    break;
+                }
+            }
+
+            candidate = self.prev[c];
+        }
+
+        (best_len, best_pos)
+    }
+}
+
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(data.len() + data.len() / 8 + HEADER_LENGTH);
+    output.extend_from_slice(MAGIC);
+    output.extend_from_slice(&[0; HEADER_LENGTH - 4]);
+    BE::write_u32(&mut output[4..], data.len() as u32);
+
+    let mut finder = MatchFinder::new(data);
+    let mut pos = 0;
+    let mut code_pos = 0;
+    let mut bit = 0;
+    let mut lookahead = None;
+
+    while pos < data.len() {
+        if bit == 0 {
+            code_pos = output.len();
+            output.push(0);
+            bit = 0x80;
+        }
+
+        // Nintendo's compressor checks whether starting the match one byte
+        // later results in a considerably longer match. If that's the case,
+        // it emits a single byte and then uses that longer match instead.
+        let (len, match_pos) = match lookahead.take() {
+            Some(m) => m,
+            None => {
+                let (len, match_pos) = finder.find(pos);
+                if len >= MIN_MATCH_LEN {
+                    let next = finder.find(pos + 1);
+                    if next.0 >= len + 2 {
+                        lookahead = Some(next);
+                        (1, 0)
+                    } else {
+                        (len, match_pos)
+                    }
+                } else {
+                    (len, match_pos)
+                }
+            }
+        };
+
+        if len < MIN_MATCH_LEN {
+            output[code_pos] |= bit;
+            output.push(data[pos]);
+            pos += 1;
+        } else {
+            let distance = pos - match_pos - 1;
+            if len >= 0x12 {
+                output.push((distance >> 8) as u8);
+                output.push(distance as u8);
+                output.push((len - 0x12) as u8);
+            } else {
+                output.push(((len - 2) << 4 | distance >> 8) as u8);
+                output.push(distance as u8);
+            }
+            pos += len;
+        }
+
+        bit >>= 1;
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn random(len: usize, mut state: u32) -> Vec<u8> {
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (state >> 16) as u8
+            })
+            .collect()
+    }
+
+    fn round_trip(data: &[u8]) -> Vec<u8> {
+        let compressed = compress(data);
+        assert!(decompress(&compressed).unwrap() == data);
+        compressed
+    }
+
+    #[test]
+    fn short() {
+        assert_eq!(round_trip(&[]).len(), HEADER_LENGTH);
+        round_trip(&[1]);
+        round_trip(&[1, 2]);
+        round_trip(&[1, 1, 1]);
+    }
+
+    #[test]
+    fn long_runs() {
+        // Matches are split up at the maximum length.
+        let mut data = vec![0; 3 * MAX_MATCH_LEN + 5];
+        data.extend(vec![0xAB; MAX_MATCH_LEN + 1]);
+        let compressed = round_trip(&data);
+        assert!(compressed.len() < HEADER_LENGTH + 0x20);
+    }
+
+    #[test]
+    fn window_limit() {
+        // The repeated data is exactly at the largest distance that can be
+        // encoded, so it can be copied.
+        let block = random(WINDOW_SIZE, 1);
+        let mut data = block.clone();
+        data.extend_from_slice(&block);
+        let compressed = round_trip(&data);
+        assert!(compressed.len() < HEADER_LENGTH + WINDOW_SIZE * 9 / 8 + 0x40);
+
+        // One byte further away, it's out of reach.
+        let block = random(WINDOW_SIZE + 1, 2);
+        let mut data = block.clone();
+        data.extend_from_slice(&block[..WINDOW_SIZE / 2]);
+        let compressed = round_trip(&data);
+        assert!(compressed.len() > HEADER_LENGTH + data.len());
+    }
+
+    #[test]
+    fn lookahead() {
+        // At the second "abc", starting one byte later finds "bcdefghij"
+        // instead of just "abc". So a single byte and one long match are
+        // emitted instead of two shorter matches.
+        let data = b"abcZbcdefghijabcdefghij";
+        let compressed = round_trip(data);
+        // 14 bytes, one match and two code bytes
+        assert_eq!(compressed.len(), HEADER_LENGTH + 14 + 2 + 2);
+    }
+}
@@ -7,9 +7,12 @@ extern crate termcolor;
 mod opt;
 
 use failure::{Error, ResultExt};
-use opt::Opt;
-use romhack_backend::{apply_patch, build, new, KeyValPrint, MessageKind};
-use std::io::prelude::*;
+use opt::{Opt, Yaz0Command};
+use romhack_backend::{
+    apply_patch, bindgen, build, demangle_symbol, extract, new, pack, yaz0_compress,
+    yaz0_decompress, KeyValPrint, MessageKind, WriteOptions,
+};
+use std::io::{self, prelude::*};
 use structopt::StructOpt;
 use termcolor::{BufferWriter, Color, ColorChoice, ColorSpec, WriteColor};
 
@@ -52,21 +55,87 @@ fn try_main() -> Result<(), Error> {
     let opt = Opt::from_args();
 
     match opt {
-        Opt::Build { debug, patch } => {
-            build(&TermPrinter, debug, patch).context("Couldn't build the Rom Hack")?
-        }
+        Opt::Build {
+            debug,
+            patch,
+            gecko,
+            target,
+            all_targets,
+        } => build(&TermPrinter, debug, patch, gecko, target, all_targets)
+            .context("Couldn't build the Rom Hack")?,
         Opt::New { name } => new(&name).context("Couldn't create the Rom Hack project")?,
         Opt::Apply {
             patch,
             original_game,
             output,
-        } => apply_patch(&TermPrinter, patch, original_game, output)
+            force,
+        } => apply_patch(&TermPrinter, patch, original_game, output, force)
             .context("Couldn't apply the patch")?,
+        Opt::Bindgen { target, output } => bindgen(&TermPrinter, target, output)
+            .context("Couldn't generate the bindings")?,
+        Opt::Demangle { symbols } => demangle(symbols).context("Couldn't demangle the symbols")?,
+        Opt::Extract { iso, output } => {
+            extract(&TermPrinter, iso, output).context("Couldn't extract the game")?
+        }
+        Opt::Pack {
+            input,
+            output,
+            junk_padding,
+        } => pack(
+            &TermPrinter,
+            input,
+            output,
+            WriteOptions {
+                junk_padding,
+                ..Default::default()
+            },
+        ).context("Couldn't pack the game")?,
+        Opt::Yaz0 { command } => match command {
+            Yaz0Command::Compress { input, output } => {
+                yaz0_compress(&TermPrinter, input, output).context("Couldn't compress the file")?
+            }
+            Yaz0Command::Decompress { input, output } => {
+                yaz0_decompress(&TermPrinter, input, output)
+                    .context("Couldn't decompress the file")?
+            }
+        },
+    }
+
+    Ok(())
+}
+
+/// Prints the demangled symbols to stdout. Symbols that can't be demangled
+/// are printed as they are, so the output lines up with the input.
+fn demangle(symbols: Vec<String>) -> Result<(), Error> {
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+
+    if symbols.is_empty() {
+        let stdin = io::stdin();
+        for symbol in stdin.lock().lines() {
+            let symbol = symbol.context("Couldn't read the symbols")?;
+            print_demangled(&mut stdout, symbol.trim())?;
+        }
+    } else {
+        for symbol in &symbols {
+            print_demangled(&mut stdout, symbol)?;
+        }
     }
 
     Ok(())
 }
 
+fn print_demangled<W: Write>(out: &mut W, symbol: &str) -> Result<(), Error> {
+    match demangle_symbol(symbol) {
+        Ok(demangled) => writeln!(out, "{}", demangled)?,
+        Err(e) => {
+            TermPrinter.print(Some(MessageKind::Warning), "Warning", &e.to_string());
+            writeln!(out, "{}", symbol)?;
+        }
+    }
+    Ok(())
+}
+
 pub struct TermPrinter;
 
 impl KeyValPrint for TermPrinter {
@@ -11,21 +11,109 @@ pub enum Opt {
         /// Compiles the Rom Hack as a patch
         #[structopt(short = "p", long = "patch")]
         patch: bool,
+        /// Builds the Rom Hack as Gecko codes for Dolphin and Nintendont
+        #[structopt(short = "g", long = "gecko", conflicts_with = "patch")]
+        gecko: bool,
+        /// Builds the target of the given name, such as a region of the game
+        #[structopt(short = "t", long = "target")]
+        target: Option<String>,
+        /// Builds all the targets
+        #[structopt(long = "all-targets", conflicts_with = "target")]
+        all_targets: bool,
     },
     /// Applies a patch file to a game to create a Rom Hack
     #[structopt(name = "apply")]
     Apply {
-        /// Input path to patch file
+        /// Input path to patch file (Rom Hack patch, VCDIFF or BPS)
         #[structopt(name = "PATCH", parse(from_os_str))]
         patch: PathBuf,
-        /// Input path to original game (GCM or ISO format)
+        /// Input path to original game (GCM, ISO, CISO, GCZ, WIA or RVZ format)
         #[structopt(name = "ORIGINAL", parse(from_os_str))]
         original_game: PathBuf,
-        /// Output path for Rom Hack
+        /// Output path for Rom Hack (the extension picks ISO, CISO or RVZ)
         #[structopt(name = "OUT", parse(from_os_str))]
         output: PathBuf,
+        /// Applies the patch even if the game isn't the one it was built for
+        #[structopt(short = "f", long = "force")]
+        force: bool,
+    },
+    /// Generates Rust bindings for the functions in the game's symbol maps
+    #[structopt(name = "bindgen")]
+    Bindgen {
+        /// Uses the symbol maps of the target of the given name
+        #[structopt(short = "t", long = "target")]
+        target: Option<String>,
+        /// Output path for the bindings
+        #[structopt(
+            short = "o",
+            long = "output",
+            default_value = "src/game.rs",
+            parse(from_os_str)
+        )]
+        output: PathBuf,
+    },
+    /// Demangles the symbols that the game's compiler or rustc mangled
+    #[structopt(name = "demangle")]
+    Demangle {
+        /// The symbols to demangle, which are read from stdin line by line if
+        /// there are none
+        #[structopt(name = "SYMBOL")]
+        symbols: Vec<String>,
+    },
+    /// Extracts a game into a folder using Dolphin's extracted disc layout
+    #[structopt(name = "extract")]
+    Extract {
+        /// Input path to the game (GCM, ISO, CISO, GCZ, WIA or RVZ format)
+        #[structopt(name = "ISO", parse(from_os_str))]
+        iso: PathBuf,
+        /// Output path for the extracted game
+        #[structopt(name = "OUT", parse(from_os_str))]
+        output: PathBuf,
+    },
+    /// Builds a game from a folder using Dolphin's extracted disc layout
+    #[structopt(name = "pack")]
+    Pack {
+        /// Input path to the extracted game
+        #[structopt(name = "DIR", parse(from_os_str))]
+        input: PathBuf,
+        /// Output path for the game (the extension picks ISO, CISO or RVZ)
+        #[structopt(name = "OUT", parse(from_os_str))]
+        output: PathBuf,
+        /// Fill the unused space with junk data like on retail discs
+        #[structopt(long = "junk-padding")]
+        junk_padding: bool,
+    },
+    /// Compresses or decompresses Yaz0 files
+    #[structopt(name = "yaz0")]
+    Yaz0 {
+        #[structopt(subcommand)]
+        command: Yaz0Command,
     },
     /// Creates a new Rom Hack with the given name
     #[structopt(name = "new")]
     New { name: String },
 }
+
+#[derive(StructOpt, Debug)]
+pub enum Yaz0Command {
+    /// Compresses a file with Yaz0
+    #[structopt(name = "compress")]
+    Compress {
+        /// Input path to the uncompressed file
+        #[structopt(name = "IN", parse(from_os_str))]
+        input: PathBuf,
+        /// Output path for the compressed file
+        #[structopt(name = "OUT", parse(from_os_str))]
+        output: PathBuf,
+    },
+    /// Decompresses a Yaz0 compressed file
+    #[structopt(name = "decompress")]
+    Decompress {
+        /// Input path to the compressed file
+        #[structopt(name = "IN", parse(from_os_str))]
+        input: PathBuf,
+        /// Output path for the decompressed file
+        #[structopt(name = "OUT", parse(from_os_str))]
+        output: PathBuf,
+    },
+}
@@ -96,7 +96,8 @@ fn main() {
             let state = state.borrow();
             if let (Some(patch), Some(iso)) = (&state.patch, &state.iso) {
                 if let Some(output) = window.save_file(&ui) {
-                    apply_patch(&DontPrint, patch.to_owned(), iso.to_owned(), output).unwrap();
+                    apply_patch(&DontPrint, patch.to_owned(), iso.to_owned(), output, false)
+                        .unwrap();
                 }
             }
         }
@@ -3,10 +3,11 @@ extern crate romhack_backend;
 
 use failure::Error;
 use romhack_backend::{
-    build_iso, iso::writer::write_iso, open_config_from_patch, KeyValPrint, MessageKind,
+    build_iso, game_id, iso::reader::load_iso, iso::writer::write_iso, open_config_from_patch,
+    KeyValPrint, MessageKind,
 };
 use std::alloc::{alloc as allocate, dealloc as deallocate, Layout};
-use std::io::{self, BufWriter, Cursor, SeekFrom, Write};
+use std::io::{self, BufReader, BufWriter, Cursor, SeekFrom, Write};
 use std::slice::from_raw_parts;
 
 extern "C" {
@@ -15,6 +16,7 @@ extern "C" {
     fn restart();
     fn write(buf_ptr: *const u8, buf_len: usize);
     fn seek(kind: u8, offset: isize) -> usize;
+    fn read_iso(offset: usize, buf_ptr: *mut u8, buf_len: usize) -> usize;
     fn key_val_print(kind: u8, key: *const u8, key_len: usize, val: *const u8, val_len: usize);
     fn set_name(ptr: *const u8, len: usize);
     fn error(ptr: *const u8, len: usize);
@@ -62,6 +64,37 @@ impl io::Seek for RomHackWriter {
     }
 }
 
+struct IsoReader {
+    pos: u64,
+    len: u64,
+}
+
+impl io::Read for IsoReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = unsafe { read_iso(self.pos as usize, buf.as_mut_ptr(), buf.len()) };
+        self.pos += read as u64;
+        Ok(read)
+    }
+}
+
+impl io::Seek for IsoReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(offset) => offset as i64,
+            SeekFrom::End(offset) => self.len as i64 + offset,
+            SeekFrom::Current(offset) => self.pos as i64 + offset,
+        };
+        if new_pos < 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Invalid seek to a negative position",
+            ));
+        }
+        self.pos = new_pos as u64;
+        Ok(self.pos)
+    }
+}
+
 struct RomHackCounter;
 
 impl io::Write for RomHackCounter {
@@ -103,11 +136,13 @@ pub unsafe extern "C" fn dealloc(ptr: *mut u8, size: usize) {
 pub unsafe extern "C" fn create_romhack(
     patch_ptr: *const u8,
     patch_len: usize,
-    iso_ptr: *const u8,
     iso_len: usize,
 ) -> bool {
     let patch = from_raw_parts(patch_ptr, patch_len);
-    let iso = from_raw_parts(iso_ptr, iso_len);
+    let iso = IsoReader {
+        pos: 0,
+        len: iso_len as u64,
+    };
     if let Err(e) = try_create_romhack(patch, iso) {
         let mut buf = Vec::new();
         for cause in e.iter_chain() {
@@ -121,20 +156,28 @@ pub unsafe extern "C" fn create_romhack(
     }
 }
 
-fn try_create_romhack(patch: &[u8], iso: &[u8]) -> Result<(), Error> {
-    let (zip, compiled_library, mut config) = open_config_from_patch(Cursor::new(patch))?;
+fn try_create_romhack(patch: &[u8], iso: IsoReader) -> Result<(), Error> {
+    // The variant of the patch to use depends on the game.
+    let game_id = game_id(&load_iso(IsoReader {
+        pos: 0,
+        len: iso.len,
+    })?)?;
+    let (zip, compiled_library, mut config) =
+        open_config_from_patch(Cursor::new(patch), &game_id)?;
     if let Some(name) = &config.info.game_name {
         unsafe {
             set_name(name.as_ptr(), name.len());
         }
     }
+    let iso = load_iso(BufReader::with_capacity(1 << 20, iso))?;
+    let options = config.build.write_options();
     let romhack = build_iso(&JSPrinter, zip, iso, compiled_library, &mut config)?;
     JSPrinter.print(None, "Measuring", "Rom Hack File Size");
-    write_iso(RomHackCounter, &romhack)?;
+    write_iso(RomHackCounter, &romhack, &options)?;
     unsafe {
         restart();
     }
     JSPrinter.print(None, "Writing", "Rom Hack");
     let writer = BufWriter::new(RomHackWriter);
-    write_iso(writer, &romhack)
+    write_iso(writer, &romhack, &options)
 }
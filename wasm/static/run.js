@@ -1,57 +1,9 @@
-const compiledModule = fetch("romhack.wasm").then((r) => r.arrayBuffer()).then((b) => WebAssembly.compile(b));
-
-let decodeUtf8;
-if (typeof window["TextDecoder"] === "undefined") {
-    decodeUtf8 = (data) => {
-        var str = '',
-            i;
-
-        for (i = 0; i < data.length; i++) {
-            var value = data[i];
-
-            if (value < 0x80) {
-                str += String.fromCharCode(value);
-            } else if (value > 0xBF && value < 0xE0) {
-                str += String.fromCharCode((value & 0x1F) << 6 | data[i + 1] & 0x3F);
-                i += 1;
-            } else if (value > 0xDF && value < 0xF0) {
-                str += String.fromCharCode((value & 0x0F) << 12 | (data[i + 1] & 0x3F) << 6 | data[i + 2] & 0x3F);
-                i += 2;
-            } else {
-                var charCode = ((value & 0x07) << 18 | (data[i + 1] & 0x3F) << 12 | (data[i + 2] & 0x3F) << 6 | data[i + 3] & 0x3F) - 0x010000;
-
-                str += String.fromCharCode(charCode >> 10 | 0xD800, charCode & 0x03FF | 0xDC00);
-                i += 3;
-            }
-        }
-
-        return str;
-    };
-} else {
-    const decoder = new TextDecoder("UTF-8");
-    decodeUtf8 = (data) => decoder.decode(data);
-}
-
-async function allocFile(wasm, elementId) {
+function selectedFile(elementId) {
     const files = document.getElementById(elementId).files;
     if (files.length < 1 || files[0] == null) {
         return null;
     }
-    const file = files[0];
-    const contents = await new Promise((resolve) => {
-        const reader = new FileReader();
-        reader.onload = () => {
-            const contents = reader.result;
-            resolve(contents);
-        };
-        reader.readAsArrayBuffer(file);
-    });
-    const len = contents.byteLength;
-    const ptr = wasm.exports.alloc(len);
-    const slice = new Uint8Array(wasm.exports.memory.buffer, ptr, len);
-    slice.set(new Uint8Array(contents));
-
-    return [ptr, len];
+    return files[0];
 }
 
 function exportFile(filename, data) {
@@ -73,59 +25,23 @@ function exportFile(filename, data) {
     }
 }
 
-async function run() {
+function run() {
     const log = document.getElementById("log");
     while (log.firstChild) {
         log.removeChild(log.firstChild);
     }
 
-    let context = {
-        cursor: 0,
-        len: 0,
-        name: "RomHack",
-        errorCount: 0,
-    };
-
-    function write(ptr, len) {
-        const memory = new Uint8Array(context.wasm.exports.memory.buffer);
-        const src = memory.slice(ptr, ptr + len);
-        new Uint8Array(context.buffer).set(src, context.cursor);
-        context.cursor += len;
-        return len;
-    }
-
-    function seek(kind, offset) {
-        if (kind == 0) {
-            context.cursor = offset;
-        } else if (kind == 1) {
-            context.cursor = context.len - offset;
-        } else {
-            context.cursor += offset;
-        }
-        return context.cursor;
-    }
-
-    function countWrite(len) {
-        context.cursor += len;
-        if (context.cursor > context.len) {
-            context.len = context.cursor;
-        }
-        return len;
-    }
-
-    function restart() {
-        context.cursor = 0;
-        context.buffer = new ArrayBuffer(context.len);
+    const patch = selectedFile("patch");
+    const iso = selectedFile("iso");
+    if (patch == null || iso == null) {
+        return;
     }
 
-    function setName(ptr, len) {
-        context.name = decodeString(ptr, len);
-    }
+    let errorCount = 0;
 
-    function error(ptr, len) {
-        const message = decodeString(ptr, len);
+    function error(message) {
         const log = document.getElementById("log");
-        if (context.errorCount == 0) {
+        if (errorCount == 0) {
             log.appendChild(document.createElement("br"));
             const span = document.createElement("span");
             span.className = "error left";
@@ -139,27 +55,10 @@ async function run() {
         } else {
             keyValPrint("Caused by", message, "error");
         }
-        context.errorCount += 1;
-    }
-
-    async function keyValPrintPtr(kind, keyPtr, keyLen, valPtr, valLen) {
-        const key = decodeString(keyPtr, keyLen);
-        const val = decodeString(valPtr, valLen);
-        switch (kind) {
-            case 0: kind = "normal"; break;
-            case 1: kind = "warning"; break;
-            case 2: kind = "error"; break;
-            default: break;
-        }
-        keyValPrint(key, val, kind);
-        await new Promise((resolve) => {
-            setTimeout(() => {
-                resolve();
-            }, 0);
-        });
+        errorCount += 1;
     }
 
-    async function keyValPrint(key, val, kind) {
+    function keyValPrint(key, val, kind) {
         if (kind == null) {
             kind = "normal";
         }
@@ -175,52 +74,34 @@ async function run() {
         log.scrollTop = log.scrollHeight;
     }
 
-    function decodeString(ptr, len) {
-        const memory = new Uint8Array(context.wasm.exports.memory.buffer);
-        const slice = memory.slice(ptr, ptr + len);
-        return decodeUtf8(slice);
-    }
-
-    let wasm = await WebAssembly.instantiate(await compiledModule, {
-        env: {
-            count_write: countWrite,
-            count_seek: seek,
-            restart,
-            write,
-            seek,
-            key_val_print: keyValPrintPtr,
-            set_name: setName,
-            error,
-        },
-    });
-    context.wasm = wasm;
-
-    keyValPrint("Opening", "Patch");
-
-    const patchFile = await allocFile(wasm, "patch");
-    if (patchFile == null) {
-        return;
-    }
-    const [patchPtr, patchLen] = patchFile;
-
-    keyValPrint("Opening", "ISO");
-
-    const isoFile = await allocFile(wasm, "iso");
-    if (isoFile == null) {
-        return;
-    }
-    const [isoPtr, isoLen] = isoFile;
-
-    const returnVal = wasm.exports.create_romhack(patchPtr, patchLen, isoPtr, isoLen);
-    if (returnVal == 1) {
-        keyValPrint("Downloading", "Rom Hack");
-
-        const { buffer, name } = context;
-        context = null;
-        wasm = null;
-
-        exportFile(`${name}.iso`, buffer);
-
-        keyValPrint("Finished", "");
-    }
+    // The ISO is handed to the worker as a File, which only reads the parts
+    // of it that are needed, instead of loading all of it into memory.
+    const worker = new Worker("worker.js");
+    worker.onmessage = (event) => {
+        const message = event.data;
+        switch (message.type) {
+            case "print":
+                keyValPrint(message.key, message.val, message.kind);
+                break;
+            case "error":
+                error(message.message);
+                break;
+            case "finished":
+                worker.terminate();
+                keyValPrint("Downloading", "Rom Hack");
+                exportFile(`${message.name}.iso`, message.buffer);
+                keyValPrint("Finished", "");
+                break;
+            case "failed":
+                worker.terminate();
+                break;
+            default:
+                break;
+        }
+    };
+    worker.onerror = (event) => {
+        worker.terminate();
+        error(event.message);
+    };
+    worker.postMessage({ patch, iso });
 }
@@ -0,0 +1,161 @@
+// Runs the patcher off the main thread. Only workers can read files
+// synchronously, which lets the ISO stay on disk and only the parts that are
+// actually needed get read into the WebAssembly memory.
+
+const compiledModule = fetch("romhack.wasm").then((r) => r.arrayBuffer()).then((b) => WebAssembly.compile(b));
+
+let decodeUtf8;
+if (typeof self["TextDecoder"] === "undefined") {
+    decodeUtf8 = (data) => {
+        var str = '',
+            i;
+
+        for (i = 0; i < data.length; i++) {
+            var value = data[i];
+
+            if (value < 0x80) {
+                str += String.fromCharCode(value);
+            } else if (value > 0xBF && value < 0xE0) {
+                str += String.fromCharCode((value & 0x1F) << 6 | data[i + 1] & 0x3F);
+                i += 1;
+            } else if (value > 0xDF && value < 0xF0) {
+                str += String.fromCharCode((value & 0x0F) << 12 | (data[i + 1] & 0x3F) << 6 | data[i + 2] & 0x3F);
+                i += 2;
+            } else {
+                var charCode = ((value & 0x07) << 18 | (data[i + 1] & 0x3F) << 12 | (data[i + 2] & 0x3F) << 6 | data[i + 3] & 0x3F) - 0x010000;
+
+                str += String.fromCharCode(charCode >> 10 | 0xD800, charCode & 0x03FF | 0xDC00);
+                i += 3;
+            }
+        }
+
+        return str;
+    };
+} else {
+    const decoder = new TextDecoder("UTF-8");
+    decodeUtf8 = (data) => decoder.decode(data);
+}
+
+function print(key, val, kind) {
+    postMessage({ type: "print", key, val, kind });
+}
+
+async function run(patch, iso) {
+    const fileReader = new FileReaderSync();
+
+    let context = {
+        cursor: 0,
+        len: 0,
+        name: "RomHack",
+    };
+
+    function write(ptr, len) {
+        const memory = new Uint8Array(context.wasm.exports.memory.buffer);
+        const src = memory.slice(ptr, ptr + len);
+        new Uint8Array(context.buffer).set(src, context.cursor);
+        context.cursor += len;
+        return len;
+    }
+
+    function seek(kind, offset) {
+        if (kind == 0) {
+            context.cursor = offset;
+        } else if (kind == 1) {
+            context.cursor = context.len - offset;
+        } else {
+            context.cursor += offset;
+        }
+        return context.cursor;
+    }
+
+    function readIso(offset, ptr, len) {
+        const count = Math.max(0, Math.min(len, iso.size - offset));
+        if (count == 0) {
+            return 0;
+        }
+        const data = fileReader.readAsArrayBuffer(iso.slice(offset, offset + count));
+        const dst = new Uint8Array(context.wasm.exports.memory.buffer, ptr, count);
+        dst.set(new Uint8Array(data));
+        return count;
+    }
+
+    function countWrite(len) {
+        context.cursor += len;
+        if (context.cursor > context.len) {
+            context.len = context.cursor;
+        }
+        return len;
+    }
+
+    function restart() {
+        context.cursor = 0;
+        context.buffer = new ArrayBuffer(context.len);
+    }
+
+    function setName(ptr, len) {
+        context.name = decodeString(ptr, len);
+    }
+
+    function error(ptr, len) {
+        postMessage({ type: "error", message: decodeString(ptr, len) });
+    }
+
+    function keyValPrintPtr(kind, keyPtr, keyLen, valPtr, valLen) {
+        const key = decodeString(keyPtr, keyLen);
+        const val = decodeString(valPtr, valLen);
+        switch (kind) {
+            case 0: kind = "normal"; break;
+            case 1: kind = "warning"; break;
+            case 2: kind = "error"; break;
+            default: break;
+        }
+        print(key, val, kind);
+    }
+
+    function decodeString(ptr, len) {
+        const memory = new Uint8Array(context.wasm.exports.memory.buffer);
+        const slice = memory.slice(ptr, ptr + len);
+        return decodeUtf8(slice);
+    }
+
+    const wasm = await WebAssembly.instantiate(await compiledModule, {
+        env: {
+            count_write: countWrite,
+            count_seek: seek,
+            restart,
+            write,
+            seek,
+            read_iso: readIso,
+            key_val_print: keyValPrintPtr,
+            set_name: setName,
+            error,
+        },
+    });
+    context.wasm = wasm;
+
+    print("Opening", "Patch");
+
+    const contents = fileReader.readAsArrayBuffer(patch);
+    const patchLen = contents.byteLength;
+    const patchPtr = wasm.exports.alloc(patchLen);
+    new Uint8Array(wasm.exports.memory.buffer, patchPtr, patchLen).set(new Uint8Array(contents));
+
+    print("Opening", "ISO");
+
+    const returnVal = wasm.exports.create_romhack(patchPtr, patchLen, iso.size);
+    if (returnVal == 1) {
+        const { buffer, name } = context;
+        context = null;
+        postMessage({ type: "finished", buffer, name }, [buffer]);
+    } else {
+        postMessage({ type: "failed" });
+    }
+}
+
+onmessage = (event) => {
+    const { patch, iso } = event.data;
+    run(patch, iso).catch((e) => {
+        postMessage({ type: "error", message: `${e}` });
+        postMessage({ type: "failed" });
+    });
+};